@@ -0,0 +1,148 @@
+// askpass.rs
+
+// Copyright (C) 2026 The Nitrocli Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fs;
+use std::io;
+use std::io::Write as _;
+use std::path;
+
+use anyhow::Context as _;
+use clap::StructOpt as _;
+
+use nitrocli_ext as ext;
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+  #[serde(default)]
+  rule: Vec<Rule>,
+}
+
+/// A mapping from a prompt to the name of a password safe slot.
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Rule {
+  /// The regular expression the prompt has to match.
+  #[serde(with = "serde_regex")]
+  prompt: regex::Regex,
+  /// The name of the PWS slot storing the password.
+  slot: String,
+}
+
+mod serde_regex {
+  pub fn deserialize<'de, D>(deserializer: D) -> Result<regex::Regex, D::Error>
+  where
+    D: serde::Deserializer<'de>,
+  {
+    let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+    regex::Regex::new(&s).map_err(serde::de::Error::custom)
+  }
+}
+
+/// Answer password prompts from the Nitrokey password safe
+///
+/// This command prints the password stored in the password safe slot
+/// that is associated with the given prompt, making it usable as
+/// SSH_ASKPASS or SUDO_ASKPASS helper. Prompts are mapped to slot names
+/// through regular expressions in the extension's configuration file.
+/// The first rule whose expression matches the prompt is used.
+///
+/// The configuration is read from config.toml in the extension's
+/// configuration directory (e.g., ~/.config/nitrocli-askpass) and
+/// contains a list of rules:
+///
+///   [[rule]]
+///   prompt = "^Enter passphrase for key '.*/id_ed25519'"
+///   slot = "ssh"
+///
+/// As SSH_ASKPASS and SUDO_ASKPASS have to name a single program, point
+/// them to a script running `nitrocli askpass "$@"`.
+#[derive(Debug, clap::StructOpt)]
+#[structopt(bin_name = "nitrocli askpass", verbatim_doc_comment)]
+struct Args {
+  /// The prompt to answer
+  prompt: Vec<String>,
+}
+
+fn main() {
+  ext::exit(run())
+}
+
+fn run() -> anyhow::Result<()> {
+  // Extensions and the `pws` subcommands we use are available since
+  // 0.4.0.
+  ext::handle_describe(
+    &ext::Metadata::new(
+      "Answer password prompts from the Nitrokey password safe",
+      env!("CARGO_PKG_VERSION"),
+    )
+    .min_nitrocli_version("0.4.0"),
+  );
+  let args = Args::from_args();
+  let ctx = ext::Context::from_env()?;
+
+  let config_file = ctx.config_dir().join("config.toml");
+  let config = load_config(&config_file)?;
+  let prompt = args.prompt.join(" ");
+  let rule = find_rule(&config, &prompt)
+    .with_context(|| format!("No rule in {} matches the prompt", config_file.display()))?;
+
+  let slot = ctx
+    .find_pws_slot(&rule.slot)?
+    .with_context(|| format!("Found no password safe slot with the name {}", rule.slot))?;
+
+  let password = ctx
+    .nitrocli()
+    .args(["pws", "get", "--password", "--quiet"].iter())
+    .arg(slot.to_string())
+    .output()
+    .context("Failed to read the password safe slot")?;
+  let password = password.trim_end_matches('\n');
+
+  let mut stdout = io::stdout();
+  writeln!(stdout, "{}", password).context("Failed to print password")?;
+  Ok(())
+}
+
+fn load_config(path: &path::Path) -> anyhow::Result<Config> {
+  let s = fs::read_to_string(path)
+    .with_context(|| format!("Failed to read configuration file {}", path.display()))?;
+  toml::from_str(&s)
+    .with_context(|| format!("Failed to parse configuration file {}", path.display()))
+}
+
+fn find_rule<'cfg>(config: &'cfg Config, prompt: &str) -> Option<&'cfg Rule> {
+  config.rule.iter().find(|rule| rule.prompt.is_match(prompt))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn rule_matching() {
+    let config = r#"
+[[rule]]
+prompt = "^Enter passphrase for key '.*/id_ed25519'"
+slot = "ssh"
+
+[[rule]]
+prompt = "sudo"
+slot = "login"
+"#;
+    let config = toml::from_str::<Config>(config).unwrap();
+    let rule = find_rule(
+      &config,
+      "Enter passphrase for key '/home/me/.ssh/id_ed25519': ",
+    );
+    assert_eq!(rule.unwrap().slot, "ssh");
+    let rule = find_rule(&config, "[sudo] password for me: ");
+    assert_eq!(rule.unwrap().slot, "login");
+    assert!(find_rule(&config, "Password: ").is_none());
+
+    let err = toml::from_str::<Config>("[[rule]]\nprompt = \"(\"\nslot = \"x\"\n").unwrap_err();
+    assert!(err.to_string().contains("regex parse error"), "{}", err);
+  }
+}
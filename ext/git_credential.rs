@@ -0,0 +1,281 @@
+// git_credential.rs
+
+// Copyright (C) 2026 The Nitrocli Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections;
+use std::fs;
+use std::io;
+use std::io::Write as _;
+use std::path;
+
+use anyhow::Context as _;
+use clap::StructOpt as _;
+
+use nitrocli::api;
+use nitrocli_ext as ext;
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+  /// A mapping from `protocol://host` to the name of a PWS slot.
+  #[serde(default)]
+  slots: collections::BTreeMap<String, String>,
+}
+
+/// Store git credentials in the Nitrokey password safe
+///
+/// This command implements the git credential helper protocol, reading
+/// a request from stdin and answering on stdout. Each remote is mapped
+/// to the name of a password safe slot in config.toml in the
+/// extension's configuration directory (e.g.,
+/// ~/.config/nitrocli-git-credential):
+///
+///   [slots]
+///   "https://forge.example.com" = "forge"
+///
+/// Requests for remotes without a mapping are ignored. To use it, set
+/// credential.helper to "!nitrocli git-credential" and add
+/// "git-credential" to the interactive_extensions setting of nitrocli,
+/// as only interactive extensions can read from stdin.
+#[derive(Debug, clap::StructOpt)]
+#[structopt(bin_name = "nitrocli git-credential", verbatim_doc_comment)]
+struct Args {
+  #[structopt(subcommand)]
+  cmd: Command,
+}
+
+#[derive(Debug, clap::StructOpt)]
+enum Command {
+  /// Prints the credentials stored for a remote
+  Get,
+  /// Stores the credentials for a remote
+  Store,
+  /// Erases the credentials stored for a remote
+  Erase,
+}
+
+/// A credential request as sent by git.
+#[derive(Debug, Default, PartialEq)]
+struct Request {
+  protocol: Option<String>,
+  host: Option<String>,
+  username: Option<String>,
+  password: Option<String>,
+}
+
+impl Request {
+  /// Parse a request from its `key=value` line representation.
+  fn parse(reader: impl io::BufRead) -> anyhow::Result<Self> {
+    let mut request = Self::default();
+    let mut lines = reader.lines().peekable();
+    // nitrocli closes stdin of extensions that are not run
+    // interactively, which would otherwise look like a request for an
+    // unknown remote.
+    anyhow::ensure!(
+      lines.peek().is_some(),
+      "Received no credential request; make sure that git-credential is listed in the \
+      interactive_extensions setting of nitrocli"
+    );
+    for line in lines {
+      let line = line.context("Failed to read credential request")?;
+      if line.is_empty() {
+        break;
+      }
+      let (key, value) = line
+        .split_once('=')
+        .with_context(|| format!("Encountered invalid request line: {}", line))?;
+      let value = Some(value.to_string());
+      match key {
+        "protocol" => request.protocol = value,
+        "host" => request.host = value,
+        "username" => request.username = value,
+        "password" => request.password = value,
+        // We do not care about paths and other attributes.
+        _ => (),
+      }
+    }
+    Ok(request)
+  }
+
+  /// Retrieve the `protocol://host` form of the remote.
+  fn remote(&self) -> Option<String> {
+    match (&self.protocol, &self.host) {
+      (Some(protocol), Some(host)) => Some(format!("{}://{}", protocol, host)),
+      _ => None,
+    }
+  }
+}
+
+fn main() {
+  ext::exit(run())
+}
+
+fn run() -> anyhow::Result<()> {
+  // `pws add` and `pws update` as well as reading passwords from stdin
+  // were introduced in 0.4.1.
+  ext::handle_describe(
+    &ext::Metadata::new(
+      "Store git credentials in the Nitrokey password safe",
+      env!("CARGO_PKG_VERSION"),
+    )
+    .min_nitrocli_version("0.4.1"),
+  );
+  let args = Args::from_args();
+  let ctx = ext::Context::from_env()?;
+
+  let config = load_config(&ctx.config_dir().join("config.toml"))?;
+  let stdin = io::stdin();
+  let request = Request::parse(stdin.lock())?;
+  let name = match request
+    .remote()
+    .and_then(|remote| config.slots.get(&remote))
+  {
+    Some(name) => name,
+    None => return Ok(()),
+  };
+
+  match args.cmd {
+    Command::Get => cmd_get(&ctx, name),
+    Command::Store => cmd_store(&ctx, name, &request),
+    Command::Erase => cmd_erase(&ctx, name, &request),
+  }
+}
+
+fn load_config(path: &path::Path) -> anyhow::Result<Config> {
+  match fs::read_to_string(path) {
+    Ok(s) => toml::from_str(&s)
+      .with_context(|| format!("Failed to parse configuration file {}", path.display())),
+    Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+    Err(err) => {
+      Err(err).with_context(|| format!("Failed to read configuration file {}", path.display()))
+    }
+  }
+}
+
+fn cmd_get(ctx: &ext::Context, name: &str) -> anyhow::Result<()> {
+  if let Some(slot) = ctx.find_pws_slot(name)? {
+    let data = ctx
+      .nitrocli()
+      .args(["pws", "get", "--login", "--password", "--quiet"].iter())
+      .arg(slot.to_string())
+      .output()
+      .context("Failed to read the password safe slot")?;
+    let mut lines = data.lines();
+    let login = lines.next().unwrap_or_default();
+    let password = lines.next().unwrap_or_default();
+
+    let mut stdout = io::stdout();
+    writeln!(stdout, "username={}", login)?;
+    writeln!(stdout, "password={}", password)?;
+  }
+  Ok(())
+}
+
+fn cmd_store(ctx: &ext::Context, name: &str, request: &Request) -> anyhow::Result<()> {
+  let login = request
+    .username
+    .as_deref()
+    .context("The credential request does not contain a username")?;
+  let password = request
+    .password
+    .as_deref()
+    .context("The credential request does not contain a password")?;
+  ensure_lengths(name, login, password)?;
+
+  let mut nitrocli = ctx.nitrocli();
+  if let Some(slot) = ctx.find_pws_slot(name)? {
+    let _ = nitrocli
+      .args(["pws", "update", "--login", login, "--password", "-"].iter())
+      .arg(slot.to_string());
+  } else {
+    // Let nitrocli pick the first unprogrammed slot.
+    let _ = nitrocli.args(["pws", "add", name, login, "-"].iter());
+  }
+  let _ = nitrocli
+    .output_with_input(password.as_bytes())
+    .context("Failed to write the password safe slot")?;
+  Ok(())
+}
+
+fn cmd_erase(ctx: &ext::Context, name: &str, request: &Request) -> anyhow::Result<()> {
+  if let Some(slot) = ctx.find_pws_slot(name)? {
+    if let Some(username) = &request.username {
+      let login = ctx
+        .nitrocli()
+        .args(["pws", "get", "--login", "--quiet"].iter())
+        .arg(slot.to_string())
+        .output()
+        .context("Failed to read the password safe slot")?;
+      if login.trim_end_matches('\n') != username {
+        return Ok(());
+      }
+    }
+
+    let _ = ctx
+      .nitrocli()
+      .args(["pws", "clear"].iter())
+      .arg(slot.to_string())
+      .output()
+      .context("Failed to clear the password safe slot")?;
+  }
+  Ok(())
+}
+
+/// Check that the data fits into a password safe slot.
+fn ensure_lengths(name: &str, login: &str, password: &str) -> anyhow::Result<()> {
+  let data = [
+    ("slot name", name, api::PWS_NAME_LENGTH),
+    ("login", login, api::PWS_LOGIN_LENGTH),
+    ("password", password, api::PWS_PASSWORD_LENGTH),
+  ];
+  for (label, value, max_length) in data.iter() {
+    anyhow::ensure!(
+      value.len() <= *max_length,
+      "The provided {} is too long (actual length: {} bytes, maximum length: {} bytes)",
+      label,
+      value.len(),
+      max_length
+    );
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn request_parsing() {
+    let input = b"protocol=https\nhost=forge.example.com\npath=a/b.git\nusername=me\n\nignored=1\n";
+    let request = Request::parse(&input[..]).unwrap();
+    assert_eq!(request.remote().unwrap(), "https://forge.example.com");
+    assert_eq!(request.username.as_deref(), Some("me"));
+    assert_eq!(request.password, None);
+
+    let request = Request::parse(&b"host=forge.example.com\n"[..]).unwrap();
+    assert_eq!(request.remote(), None);
+
+    let err = Request::parse(&b""[..]).unwrap_err();
+    assert!(err
+      .to_string()
+      .starts_with("Received no credential request"));
+
+    let err = Request::parse(&b"protocol\n"[..]).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "Encountered invalid request line: protocol"
+    );
+  }
+
+  #[test]
+  fn length_limits() {
+    ensure_lengths("forge", "me", "01234567890123456789").unwrap();
+
+    let err = ensure_lengths("forge", "me", "012345678901234567890").unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "The provided password is too long (actual length: 21 bytes, maximum length: 20 bytes)"
+    );
+  }
+}
@@ -6,19 +6,110 @@
 use std::fs;
 use std::io::Write as _;
 use std::path;
+use std::time;
 
 use anyhow::Context as _;
 use clap::StructOpt as _;
+use nitrokey::Device as _;
+use nitrokey::GenerateOtp as _;
 
-mod ext;
+use nitrocli_ext as ext;
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
 struct Cache {
   hotp: Vec<Slot>,
   totp: Vec<Slot>,
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+impl Cache {
+  /// Iterate over all slots along with their algorithm.
+  fn slots(&self) -> impl Iterator<Item = (&'static str, &Slot)> {
+    let totp = self.totp.iter().map(|slot| ("totp", slot));
+    let hotp = self.hotp.iter().map(|slot| ("hotp", slot));
+    totp.chain(hotp)
+  }
+
+  /// Find the slot with the given name.
+  ///
+  /// Exact matches take precedence over case-insensitive ones, which in
+  /// turn take precedence over case-insensitive prefix matches.
+  fn find(&self, name: &str) -> anyhow::Result<(&'static str, u8)> {
+    let lowercase = name.to_lowercase();
+    let matchers: [&dyn Fn(&Slot) -> bool; 3] = [
+      &|slot| slot.name == name,
+      &|slot| slot.name.to_lowercase() == lowercase,
+      &|slot| slot.name.to_lowercase().starts_with(&lowercase),
+    ];
+
+    for matcher in matchers.iter() {
+      let slots = self
+        .slots()
+        .filter(|(_, slot)| matcher(slot))
+        .collect::<Vec<_>>();
+      match slots.as_slice() {
+        [] => continue,
+        [(algorithm, slot)] => return Ok((algorithm, slot.id)),
+        _ => {
+          let names = slots
+            .iter()
+            .map(|(algorithm, slot)| format!("{} ({} slot {})", slot.name, algorithm, slot.id))
+            .collect::<Vec<_>>();
+          anyhow::bail!(
+            "Found multiple OTP slots matching the given name: {}",
+            names.join(", ")
+          )
+        }
+      }
+    }
+
+    let suggestions = self.suggest(name);
+    if suggestions.is_empty() {
+      anyhow::bail!("Found no OTP slot with the given name")
+    } else {
+      anyhow::bail!(
+        "Found no OTP slot with the given name; did you mean {}?",
+        suggestions.join(", ")
+      )
+    }
+  }
+
+  /// Find the names of slots that are similar to the given one.
+  fn suggest(&self, name: &str) -> Vec<&str> {
+    let name = name.to_lowercase();
+    let max_distance = (name.chars().count() / 3).max(1);
+    let mut suggestions = self
+      .slots()
+      .map(|(_, slot)| {
+        (
+          distance(&name, &slot.name.to_lowercase()),
+          slot.name.as_str(),
+        )
+      })
+      .filter(|(distance, _)| *distance <= max_distance)
+      .collect::<Vec<_>>();
+    suggestions.sort();
+    suggestions.dedup_by_key(|(_, name)| *name);
+    suggestions.into_iter().map(|(_, name)| name).collect()
+  }
+}
+
+/// Calculate the Levenshtein distance between two strings.
+fn distance(a: &str, b: &str) -> usize {
+  let b = b.chars().collect::<Vec<_>>();
+  let mut row = (0..=b.len()).collect::<Vec<_>>();
+  for (i, ca) in a.chars().enumerate() {
+    let mut diagonal = row[0];
+    row[0] = i + 1;
+    for (j, cb) in b.iter().enumerate() {
+      let substitution = diagonal + usize::from(ca != *cb);
+      diagonal = row[j + 1];
+      row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+    }
+  }
+  row[b.len()]
+}
+
+#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
 struct Slot {
   name: String,
   id: u8,
@@ -28,11 +119,13 @@ struct Slot {
 ///
 /// This command caches the names of the OTP slots on a Nitrokey device
 /// and makes it possible to generate a one-time password from a slot
-/// with a given name without knowing its index. It only queries the
-/// names of the OTP slots if there is no cached data or if the
-/// `--force-update` option is set. The cache includes the Nitrokey's
-/// serial number so that it is possible to use it with multiple
-/// devices.
+/// with a given name without knowing its index. Names are matched
+/// case-insensitively and a unique prefix suffices. The cache includes
+/// the Nitrokey's serial number so that it is possible to use it with
+/// multiple devices. It is refreshed automatically if it does not
+/// contain the requested name, if the slot it maps the name to has been
+/// renamed or cleared, if nitrocli changed the OTP slots since it was
+/// written, or if the `--force-update` option is set.
 #[derive(Debug, clap::StructOpt)]
 #[structopt(bin_name = "nitrocli otp-cache")]
 struct Args {
@@ -49,70 +142,144 @@ enum Command {
   Get {
     /// The name of the OTP slot to generate a OTP from
     name: String,
+    /// The time window of the TOTP slot, in seconds
+    #[structopt(long, default_value = "30")]
+    time_window: u64,
+    /// Prints the one-time password and its validity as JSON
+    #[structopt(long)]
+    json: bool,
   },
   /// Lists the cached slots and their names
   List,
 }
 
-fn main() -> anyhow::Result<()> {
+fn main() {
+  ext::exit(run())
+}
+
+fn run() -> anyhow::Result<()> {
+  // We rely on the resolved device and the secret channel, which are
+  // provided by every version that checks a minimum version at all, so
+  // we do not state one.
+  ext::handle_describe(&ext::Metadata::new(
+    "Access Nitrokey OTP slots by name",
+    env!("CARGO_PKG_VERSION"),
+  ));
   let args = Args::from_args();
   let ctx = ext::Context::from_env()?;
 
-  let cache = get_cache(&ctx, args.force_update)?;
+  let mut mgr = nitrokey::take().context("Failed to obtain Nitrokey manager instance")?;
+  let device = ctx.connect(&mut mgr)?;
+  let serial_number = get_serial_number(&device)?;
+  let cache_file = ctx.cache_dir().join(format!("{}.toml", serial_number));
+  let force_update = args.force_update || is_outdated(&ctx, &cache_file);
+  let (mut cache, updated) = get_cache(&device, &cache_file, force_update)?;
+
   match &args.cmd {
-    Command::Get { name } => cmd_get(&ctx, &cache, name)?,
+    Command::Get {
+      name,
+      time_window,
+      json,
+    } => {
+      let mut found = cache.find(name);
+      if !updated {
+        // The name may refer to a slot that was programmed after we
+        // last updated the cache. Slots may also have been reprogrammed
+        // with a tool other than nitrocli, which the stamp file does
+        // not capture, so we make sure that the slot still has the
+        // cached name.
+        let current = match found {
+          Ok((algorithm, slot)) => is_current(&device, &cache, algorithm, slot)?,
+          Err(_) => false,
+        };
+        if !current {
+          cache = update_cache(&device, &cache_file, Some(&cache))?;
+          found = cache.find(name);
+        }
+      }
+      let (algorithm, slot) = found?;
+      let otp = generate_otp(&ctx, device, algorithm, slot, *time_window)?;
+      if *json {
+        println!("{}", otp.to_json());
+      } else {
+        println!("{}", otp.code);
+      }
+    }
     Command::List => cmd_list(&cache),
   }
   Ok(())
 }
 
-fn cmd_get(ctx: &ext::Context, cache: &Cache, slot_name: &str) -> anyhow::Result<()> {
-  let totp_slots = cache
-    .totp
-    .iter()
-    .filter(|s| s.name == slot_name)
-    .collect::<Vec<_>>();
-  let hotp_slots = cache
-    .hotp
-    .iter()
-    .filter(|s| s.name == slot_name)
-    .collect::<Vec<_>>();
-  if totp_slots.len() + hotp_slots.len() > 1 {
-    Err(anyhow::anyhow!(
-      "Found multiple OTP slots with the given name"
-    ))
-  } else if let Some(slot) = totp_slots.first() {
-    generate_otp(ctx, "totp", slot.id)
-  } else if let Some(slot) = hotp_slots.first() {
-    generate_otp(ctx, "hotp", slot.id)
-  } else {
-    Err(anyhow::anyhow!("Found no OTP slot with the given name"))
-  }
-}
-
 fn cmd_list(cache: &Cache) {
   println!("alg\tslot\tname");
-  for slot in &cache.totp {
-    println!("totp\t{}\t{}", slot.id, slot.name);
+  for (algorithm, slot) in cache.slots() {
+    println!("{}\t{}\t{}", algorithm, slot.id, slot.name);
   }
-  for slot in &cache.hotp {
-    println!("hotp\t{}\t{}", slot.id, slot.name);
+}
+
+/// Check whether nitrocli changed the OTP slots after the cache file
+/// was last written.
+fn is_outdated(ctx: &ext::Context, cache_file: &path::Path) -> bool {
+  let cached = fs::metadata(cache_file).and_then(|m| m.modified()).ok();
+  match (ctx.otp_modified(), cached) {
+    (Some(modified), Some(cached)) => modified >= cached,
+    _ => false,
   }
 }
 
-fn get_cache(ctx: &ext::Context, force_update: bool) -> anyhow::Result<Cache> {
-  let mut mgr = nitrokey::take().context("Failed to obtain Nitrokey manager instance")?;
-  let device = ctx.connect(&mut mgr)?;
-  let serial_number = get_serial_number(&device)?;
-  let cache_file = ctx.cache_dir().join(format!("{}.toml", serial_number));
+/// Check whether the given slot on the device has the name stored in the
+/// cache.
+fn is_current(
+  device: &impl nitrokey::GenerateOtp,
+  cache: &Cache,
+  algorithm: &str,
+  slot: u8,
+) -> anyhow::Result<bool> {
+  let result = if algorithm == "hotp" {
+    device.get_hotp_slot_name(slot)
+  } else {
+    device.get_totp_slot_name(slot)
+  };
+  let name = match result {
+    Ok(name) => name,
+    Err(nitrokey::Error::CommandError(nitrokey::CommandError::SlotNotProgrammed)) => {
+      return Ok(false)
+    }
+    Err(err) => return Err(err).context("Failed to check OTP slot"),
+  };
+  Ok(
+    cache
+      .slots()
+      .any(|(alg, cached)| alg == algorithm && cached.id == slot && cached.name == name),
+  )
+}
 
+/// Retrieve the cached slots, updating the cache if necessary.
+///
+/// The returned flag indicates whether the cache was updated.
+fn get_cache(
+  device: &impl nitrokey::GenerateOtp,
+  cache_file: &path::Path,
+  force_update: bool,
+) -> anyhow::Result<(Cache, bool)> {
   if cache_file.is_file() && !force_update {
-    load_cache(&cache_file)
-  } else {
-    let cache = get_otp_slots(&device)?;
-    save_cache(&cache, &cache_file)?;
-    Ok(cache)
+    return Ok((load_cache(cache_file)?, false));
   }
+  Ok((update_cache(device, cache_file, None)?, true))
+}
+
+/// Query the slots from the device and store them in the cache file if
+/// they differ from the given cache.
+fn update_cache(
+  device: &impl nitrokey::GenerateOtp,
+  cache_file: &path::Path,
+  old: Option<&Cache>,
+) -> anyhow::Result<Cache> {
+  let cache = get_otp_slots(device)?;
+  if old != Some(&cache) {
+    save_cache(&cache, cache_file)?;
+  }
+  Ok(cache)
 }
 
 fn load_cache(path: &path::Path) -> anyhow::Result<Cache> {
@@ -161,17 +328,182 @@ where
 
 fn get_otp_slots(device: &impl nitrokey::GenerateOtp) -> anyhow::Result<Cache> {
   Ok(Cache {
-    totp: get_otp_slots_fn(device, |device, slot| device.get_totp_slot_name(slot))?,
     hotp: get_otp_slots_fn(device, |device, slot| device.get_hotp_slot_name(slot))?,
+    totp: get_otp_slots_fn(device, |device, slot| device.get_totp_slot_name(slot))?,
   })
 }
 
-fn generate_otp(ctx: &ext::Context, algorithm: &str, slot: u8) -> anyhow::Result<()> {
-  ctx
-    .nitrocli()
-    .args(["otp", "get"].iter())
-    .arg(slot.to_string())
-    .arg("--algorithm")
-    .arg(algorithm)
-    .spawn()
+/// A generated one-time password.
+#[derive(Debug, PartialEq)]
+struct Otp {
+  code: String,
+  /// The number of seconds the password remains valid for, if it is
+  /// time-based.
+  seconds_remaining: Option<u64>,
+}
+
+impl Otp {
+  /// Format the password as a JSON object.
+  ///
+  /// The object is formatted by hand because `serde_json` is not a
+  /// dependency and two fields do not justify pulling it in.
+  fn to_json(&self) -> String {
+    let seconds_remaining = match self.seconds_remaining {
+      Some(seconds) => seconds.to_string(),
+      None => "null".to_string(),
+    };
+    format!(
+      "{{\"code\":{},\"seconds_remaining\":{}}}",
+      json_string(&self.code),
+      seconds_remaining
+    )
+  }
+}
+
+/// Format a string as a JSON string literal.
+fn json_string(s: &str) -> String {
+  let mut json = String::with_capacity(s.len() + 2);
+  json.push('"');
+  for c in s.chars() {
+    match c {
+      '"' => json.push_str("\\\""),
+      '\\' => json.push_str("\\\\"),
+      c if c.is_control() => json.push_str(&format!("\\u{:04x}", u32::from(c))),
+      c => json.push(c),
+    }
+  }
+  json.push('"');
+  json
+}
+
+fn get_unix_timestamp() -> anyhow::Result<u64> {
+  time::SystemTime::now()
+    .duration_since(time::UNIX_EPOCH)
+    .context("Current system time is before the Unix epoch")
+    .map(|duration| duration.as_secs())
+}
+
+fn get_otp<T>(device: &mut T, algorithm: &str, slot: u8) -> anyhow::Result<String>
+where
+  T: nitrokey::GenerateOtp,
+{
+  match algorithm {
+    "hotp" => device.get_hotp_code(slot),
+    _ => device.get_totp_code(slot),
+  }
+  .context("Failed to generate OTP")
+}
+
+/// Generate a one-time password the same way `nitrocli otp get` does.
+fn generate_otp(
+  ctx: &ext::Context,
+  mut device: nitrokey::DeviceWrapper<'_>,
+  algorithm: &str,
+  slot: u8,
+  time_window: u64,
+) -> anyhow::Result<Otp> {
+  anyhow::ensure!(time_window > 0, "The time window must not be zero");
+
+  let seconds_remaining = if algorithm == "totp" {
+    let time = get_unix_timestamp().context("Failed to retrieve current time")?;
+    let force = !ctx.env().no_force_time;
+    match device.set_time(time, force) {
+      Err(nitrokey::Error::CommandError(nitrokey::CommandError::Timestamp)) if !force => {
+        Err(anyhow::anyhow!(
+          "The device time is ahead of the system time or has not been set yet; \
+          use `nitrocli otp time --force` to set it once"
+        ))
+      }
+      result => result.map_err(From::from),
+    }
+    .context("Failed to set new time")?;
+    Some(time_window - time % time_window)
+  } else {
+    None
+  };
+
+  let config = device
+    .get_config()
+    .context("Failed to get get current device configuration")?;
+  let code = if config.user_password {
+    let mut user = ctx.authenticate_user(device)?;
+    get_otp(&mut user, algorithm, slot)
+  } else {
+    get_otp(&mut device, algorithm, slot)
+  }?;
+  Ok(Otp {
+    code,
+    seconds_remaining,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn test_cache() -> Cache {
+    let slot = |name: &str, id| Slot {
+      name: name.to_string(),
+      id,
+    };
+    Cache {
+      hotp: vec![slot("GitHub", 0), slot("gitlab", 1)],
+      totp: vec![slot("mail", 0), slot("Mailbox", 2), slot("bank", 3)],
+    }
+  }
+
+  #[test]
+  fn slot_lookup() {
+    let cache = test_cache();
+    assert_eq!(cache.find("mail").unwrap(), ("totp", 0));
+    assert_eq!(cache.find("MAILBOX").unwrap(), ("totp", 2));
+    assert_eq!(cache.find("github").unwrap(), ("hotp", 0));
+    assert_eq!(cache.find("ba").unwrap(), ("totp", 3));
+    assert_eq!(cache.find("gitl").unwrap(), ("hotp", 1));
+
+    let err = cache.find("git").unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "Found multiple OTP slots matching the given name: GitHub (hotp slot 0), gitlab (hotp slot 1)"
+    );
+    let err = cache.find("gihtub").unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "Found no OTP slot with the given name; did you mean GitHub?"
+    );
+    let err = cache.find("bnk").unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "Found no OTP slot with the given name; did you mean bank?"
+    );
+    let err = cache.find("shop").unwrap_err();
+    assert_eq!(err.to_string(), "Found no OTP slot with the given name");
+  }
+
+  #[test]
+  fn json_output() {
+    let otp = Otp {
+      code: "123456".to_string(),
+      seconds_remaining: Some(12),
+    };
+    assert_eq!(otp.to_json(), r#"{"code":"123456","seconds_remaining":12}"#);
+
+    let otp = Otp {
+      code: "\"a\\b\n".to_string(),
+      seconds_remaining: None,
+    };
+    assert_eq!(
+      otp.to_json(),
+      r#"{"code":"\"a\\b\u000a","seconds_remaining":null}"#
+    );
+  }
+
+  #[test]
+  fn edit_distance() {
+    assert_eq!(distance("", ""), 0);
+    assert_eq!(distance("bank", ""), 4);
+    assert_eq!(distance("bank", "bank"), 0);
+    assert_eq!(distance("bnak", "bank"), 2);
+    assert_eq!(distance("kitten", "sitting"), 3);
+  }
 }
@@ -0,0 +1,853 @@
+// lib.rs
+
+// Copyright (C) 2020-2026 The Nitrocli Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+#![warn(
+  bad_style,
+  rustdoc::broken_intra_doc_links,
+  dead_code,
+  future_incompatible,
+  improper_ctypes,
+  late_bound_lifetime_arguments,
+  missing_debug_implementations,
+  missing_docs,
+  no_mangle_generic_items,
+  non_shorthand_field_patterns,
+  nonstandard_style,
+  overflowing_literals,
+  path_statements,
+  patterns_in_fns_without_body,
+  proc_macro_derive_resolution_fallback,
+  renamed_and_removed_lints,
+  rust_2018_compatibility,
+  rust_2018_idioms,
+  stable_features,
+  trivial_bounds,
+  trivial_numeric_casts,
+  type_alias_bounds,
+  tyvar_behind_raw_pointer,
+  unconditional_recursion,
+  unreachable_code,
+  unreachable_patterns,
+  unstable_features,
+  unstable_name_collisions,
+  unused,
+  unused_comparisons,
+  unused_import_braces,
+  unused_lifetimes,
+  unused_qualifications,
+  unused_results,
+  while_true
+)]
+
+//! Support for writing extensions for `nitrocli`.
+//!
+//! `nitrocli` runs any executable named `nitrocli-<name>` that it finds
+//! through `PATH` when invoked as `nitrocli <name>`. It passes its
+//! configuration and information about the device it would connect to
+//! on to the extension through environment variables, which
+//! [`Context::from_env`] parses.
+//!
+//! ```no_run
+//! fn run() -> anyhow::Result<()> {
+//!   nitrocli_ext::handle_describe(&nitrocli_ext::Metadata::new(
+//!     "Print the status of the device",
+//!     env!("CARGO_PKG_VERSION"),
+//!   ));
+//!
+//!   let ctx = nitrocli_ext::Context::from_env()?;
+//!   let status = ctx.nitrocli().arg("status").output()?;
+//!   print!("{}", status);
+//!   Ok(())
+//! }
+//!
+//! fn main() {
+//!   nitrocli_ext::exit(run())
+//! }
+//! ```
+
+use std::env;
+use std::error;
+use std::ffi;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::BufRead as _;
+use std::io::Write as _;
+use std::os::unix::io::FromRawFd as _;
+use std::os::unix::io::RawFd;
+use std::os::unix::net;
+use std::path;
+use std::process;
+use std::str::FromStr as _;
+use std::time;
+
+use anyhow::Context as _;
+
+/// The argument with which `nitrocli` asks an extension for its
+/// metadata.
+const DESCRIBE_ARG: &str = "--nitrocli-describe";
+/// The number of tries for authenticating with a PIN, as used by
+/// `nitrocli`.
+const PIN_TRIES: u8 = 3;
+
+/// Parse a model as passed by `nitrocli`.
+fn parse_model(model: &str) -> anyhow::Result<nitrokey::Model> {
+  match model {
+    "librem" => Ok(nitrokey::Model::Librem),
+    "pro" => Ok(nitrokey::Model::Pro),
+    "storage" => Ok(nitrokey::Model::Storage),
+    _ => Err(anyhow::anyhow!("Unsupported device model: {}", model)),
+  }
+}
+
+/// Format a model the way `nitrocli` does.
+fn format_model(model: nitrokey::Model) -> String {
+  match model {
+    nitrokey::Model::Librem => "librem".to_string(),
+    nitrokey::Model::Pro => "pro".to_string(),
+    nitrokey::Model::Storage => "storage".to_string(),
+    model => model.to_string(),
+  }
+}
+
+/// The information `nitrocli` passes on to extensions through
+/// environment variables.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub struct Environment {
+  /// The path to the `nitrocli` binary (`NITROCLI_BINARY`).
+  pub binary: ffi::OsString,
+  /// The model to connect to (`NITROCLI_MODEL`).
+  pub model: Option<nitrokey::Model>,
+  /// The serial numbers of the device to connect to
+  /// (`NITROCLI_SERIAL_NUMBERS`).
+  pub serial_numbers: Vec<nitrokey::SerialNumber>,
+  /// The USB path of the device to connect to (`NITROCLI_USB_PATH`).
+  pub usb_path: Option<String>,
+  /// The verbosity `nitrocli` runs with (`NITROCLI_VERBOSITY`).
+  pub verbosity: Option<u8>,
+  /// Whether to bypass the cache for all secrets
+  /// (`NITROCLI_NO_CACHE`).
+  pub no_cache: bool,
+  /// Whether to refuse moving the device time backwards when setting
+  /// it (`NITROCLI_NO_FORCE_TIME`).
+  pub no_force_time: bool,
+  /// The USB path of the device `nitrocli` would connect to
+  /// (`NITROCLI_RESOLVED_USB_PATH`).
+  pub resolved_usb_path: Option<String>,
+  /// The model of the device `nitrocli` would connect to
+  /// (`NITROCLI_RESOLVED_MODEL`).
+  pub resolved_model: Option<nitrokey::Model>,
+  /// The serial number of the device `nitrocli` would connect to
+  /// (`NITROCLI_RESOLVED_SERIAL_NUMBER`).
+  pub resolved_serial_number: Option<nitrokey::SerialNumber>,
+  /// The file descriptor of the channel for requesting secrets
+  /// (`NITROCLI_SECRET_FD`).
+  pub secret_fd: Option<RawFd>,
+  /// The file whose modification time reflects the last change of the
+  /// OTP slots of the resolved device (`NITROCLI_OTP_STAMP`).
+  pub otp_stamp: Option<path::PathBuf>,
+}
+
+impl Environment {
+  /// Parse the environment variables set by `nitrocli` from the
+  /// environment of the current process.
+  pub fn from_env() -> anyhow::Result<Self> {
+    Self::from_vars(|name| env::var_os(name))
+  }
+
+  /// Parse the environment variables set by `nitrocli`, retrieving
+  /// their values through the given function.
+  pub fn from_vars<F>(var: F) -> anyhow::Result<Self>
+  where
+    F: Fn(&str) -> Option<ffi::OsString>,
+  {
+    let string = |name: &str| -> anyhow::Result<Option<String>> {
+      var(name)
+        .map(|value| {
+          value
+            .into_string()
+            .map_err(|_| anyhow::anyhow!("{} is not valid UTF-8", name))
+        })
+        .transpose()
+    };
+    // `nitrocli` sets some variables to an empty string to indicate the
+    // absence of a value.
+    let non_empty = |name: &str| -> anyhow::Result<Option<String>> {
+      Ok(string(name)?.filter(|s| !s.is_empty()))
+    };
+
+    let binary = var("NITROCLI_BINARY")
+      .context("NITROCLI_BINARY environment variable not present")
+      .context("Failed to retrieve nitrocli path")?;
+
+    let verbosity = string("NITROCLI_VERBOSITY")?
+      .context("NITROCLI_VERBOSITY environment variable not present")
+      .context("Failed to retrieve nitrocli verbosity")?;
+    let verbosity = if verbosity.is_empty() {
+      None
+    } else {
+      Some(verbosity.parse().context("Failed to parse verbosity")?)
+    };
+
+    let no_cache = non_empty("NITROCLI_NO_CACHE")?
+      .map(|no_cache| no_cache.parse())
+      .transpose()
+      .context("Failed to parse NITROCLI_NO_CACHE")?
+      .unwrap_or_default();
+    let no_force_time = non_empty("NITROCLI_NO_FORCE_TIME")?
+      .map(|no_force_time| no_force_time.parse())
+      .transpose()
+      .context("Failed to parse NITROCLI_NO_FORCE_TIME")?
+      .unwrap_or_default();
+
+    let serial_numbers = non_empty("NITROCLI_SERIAL_NUMBERS")?
+      .map(|serial_numbers| {
+        serial_numbers
+          .split(',')
+          .map(nitrokey::SerialNumber::from_str)
+          .collect::<Result<Vec<_>, _>>()
+      })
+      .transpose()
+      .context("Failed to parse NITROCLI_SERIAL_NUMBERS")?
+      .unwrap_or_default();
+
+    let model = non_empty("NITROCLI_MODEL")?
+      .map(|model| parse_model(&model))
+      .transpose()
+      .context("Failed to parse NITROCLI_MODEL")?;
+    let resolved_model = non_empty("NITROCLI_RESOLVED_MODEL")?
+      .map(|model| parse_model(&model))
+      .transpose()
+      .context("Failed to parse NITROCLI_RESOLVED_MODEL")?;
+    let resolved_serial_number = non_empty("NITROCLI_RESOLVED_SERIAL_NUMBER")?
+      .map(|serial_number| nitrokey::SerialNumber::from_str(&serial_number))
+      .transpose()
+      .context("Failed to parse NITROCLI_RESOLVED_SERIAL_NUMBER")?;
+    let secret_fd = non_empty("NITROCLI_SECRET_FD")?
+      .map(|fd| fd.parse())
+      .transpose()
+      .context("Failed to parse NITROCLI_SECRET_FD")?;
+
+    Ok(Self {
+      binary,
+      model,
+      serial_numbers,
+      usb_path: non_empty("NITROCLI_USB_PATH")?,
+      verbosity,
+      no_cache,
+      no_force_time,
+      resolved_usb_path: non_empty("NITROCLI_RESOLVED_USB_PATH")?,
+      resolved_model,
+      resolved_serial_number,
+      secret_fd,
+      otp_stamp: var("NITROCLI_OTP_STAMP")
+        .filter(|stamp| !stamp.is_empty())
+        .map(path::PathBuf::from),
+    })
+  }
+
+  /// Check whether the given device matches the device filters.
+  fn matches(&self, device: &nitrokey::DeviceInfo) -> bool {
+    (self.model.is_none() || device.model == self.model)
+      && (self.serial_numbers.is_empty()
+        || device
+          .serial_number
+          .map(|sn| self.serial_numbers.contains(&sn))
+          .unwrap_or_default())
+      && (self.usb_path.is_none() || self.usb_path.as_ref() == Some(&device.path))
+  }
+
+  /// Format the device filters for use in an error message.
+  fn format_filter(&self) -> String {
+    let mut filters = Vec::new();
+    if let Some(model) = self.model {
+      filters.push(format!("model={}", format_model(model)));
+    }
+    if !self.serial_numbers.is_empty() {
+      let serial_numbers = self
+        .serial_numbers
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+      filters.push(format!("serial number in [{}]", serial_numbers.join(", ")));
+    }
+    if let Some(path) = &self.usb_path {
+      filters.push(format!("usb path={}", path));
+    }
+    if filters.is_empty() {
+      String::new()
+    } else {
+      format!(" (filter: {})", filters.join(", "))
+    }
+  }
+}
+
+/// A secret that an extension may request from `nitrocli`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Secret {
+  /// The user PIN.
+  UserPin,
+  /// The admin PIN.
+  AdminPin,
+}
+
+impl Secret {
+  fn as_str(self) -> &'static str {
+    match self {
+      Secret::UserPin => "user-pin",
+      Secret::AdminPin => "admin-pin",
+    }
+  }
+}
+
+/// A context providing information relevant to `nitrocli` extensions.
+#[derive(Debug)]
+pub struct Context {
+  /// The information passed on by `nitrocli`.
+  env: Environment,
+  /// The channel for requesting secrets from `nitrocli`, if any.
+  secret_channel: Option<net::UnixStream>,
+  /// The project directory root to use for the extension in question.
+  project_dirs: directories::ProjectDirs,
+}
+
+impl Context {
+  /// Create a new `Context` with information provided by `nitrocli`
+  /// via environment variables.
+  ///
+  /// This function also sets the log level of `libnitrokey` according
+  /// to the verbosity `nitrocli` runs with.
+  pub fn from_env() -> anyhow::Result<Self> {
+    let env = Environment::from_env()?;
+    if let Some(verbosity) = env.verbosity {
+      set_log_level(verbosity);
+    }
+
+    let secret_channel = env
+      .secret_fd
+      .map(open_secret_channel)
+      .transpose()
+      .context("Failed to open the secret channel")?;
+
+    let exe =
+      env::current_exe().context("Failed to determine the path of the extension executable")?;
+    let name = exe
+      .file_name()
+      .context("Failed to extract the name of the extension executable")?
+      .to_str()
+      .context("The name of the extension executable contains non-UTF-8 characters")?;
+    let project_dirs = directories::ProjectDirs::from("", "", name).with_context(|| {
+      format!(
+        "Could not determine the application directories for the {} extension",
+        name
+      )
+    })?;
+
+    Ok(Self {
+      env,
+      secret_channel,
+      project_dirs,
+    })
+  }
+
+  /// Retrieve the information passed on by `nitrocli`.
+  pub fn env(&self) -> &Environment {
+    &self.env
+  }
+
+  /// Retrieve a `Nitrocli` object for invoking the main `nitrocli`
+  /// program.
+  pub fn nitrocli(&self) -> Nitrocli {
+    Nitrocli::from_context(self)
+  }
+
+  /// Request a secret for the resolved device from `nitrocli`.
+  ///
+  /// `nitrocli` takes the secret from its environment or inquires it
+  /// through pinentry, honoring its cache.
+  pub fn secret(&self, secret: Secret) -> anyhow::Result<String> {
+    self.request_secret(secret.as_str())
+  }
+
+  /// Report a secret retrieved through [`Context::secret`] as wrong,
+  /// removing it from the cache.
+  ///
+  /// The return value indicates whether requesting the secret again may
+  /// yield a different value, which is not the case if it is provided
+  /// through the environment.
+  pub fn reject(&self, secret: Secret) -> anyhow::Result<bool> {
+    let response = self.request_secret(&format!("reject {}", secret.as_str()))?;
+    Ok(response == "true")
+  }
+
+  /// Authenticate the given device as user, with the user PIN
+  /// requested from `nitrocli`.
+  ///
+  /// If the PIN is wrong, it is rejected and requested again, for at
+  /// most three tries overall, just as `nitrocli` itself would do.
+  pub fn authenticate_user<'mgr, D>(&self, device: D) -> anyhow::Result<nitrokey::User<'mgr, D>>
+  where
+    D: nitrokey::Authenticate<'mgr> + nitrokey::Device<'mgr>,
+  {
+    let mut device = device;
+    let mut retry = PIN_TRIES;
+    loop {
+      let pin = self.secret(Secret::UserPin)?;
+      match device.authenticate_user(&pin) {
+        Ok(user) => return Ok(user),
+        Err((
+          new_device,
+          err @ nitrokey::Error::CommandError(nitrokey::CommandError::WrongPassword),
+        )) => {
+          // Make sure that nitrocli does not hand out the same PIN again.
+          let can_retry = self.reject(Secret::UserPin)?;
+          retry -= 1;
+          if retry == 0 || !can_retry {
+            return Err(err).context("Failed to authenticate as user");
+          }
+          device = new_device;
+        }
+        Err((_, err)) => return Err(err).context("Failed to authenticate as user"),
+      }
+    }
+  }
+
+  fn request_secret(&self, request: &str) -> anyhow::Result<String> {
+    let mut stream = self.secret_channel.as_ref().context(
+      "nitrocli did not provide a secret channel; make sure that the extension is listed \
+        in its secret_extensions setting",
+    )?;
+    writeln!(stream, "{}", request).context("Failed to send secret request")?;
+
+    let mut response = String::new();
+    let _ = io::BufReader::new(stream)
+      .read_line(&mut response)
+      .context("Failed to receive secret")?;
+    let response = response.strip_suffix('\n').unwrap_or(&response);
+    match response.split_once(' ') {
+      Some(("ok", value)) => Ok(value.to_string()),
+      Some(("err", msg)) => Err(anyhow::anyhow!("{}", msg)),
+      _ => Err(anyhow::anyhow!("Received invalid secret response")),
+    }
+  }
+
+  /// Connect to a Nitrokey (or Librem Key) device as `nitrocli` would.
+  pub fn connect<'mgr>(
+    &self,
+    mgr: &'mgr mut nitrokey::Manager,
+  ) -> anyhow::Result<nitrokey::DeviceWrapper<'mgr>> {
+    if let Some(usb_path) = &self.env.resolved_usb_path {
+      return mgr.connect_path(usb_path.to_owned()).map_err(From::from);
+    }
+
+    // `nitrocli` only resolves a device if exactly one matches the
+    // filters. Find out which case we are dealing with.
+    let devices = nitrokey::list_devices().context("Failed to enumerate Nitrokey devices")?;
+    let mut iter = devices
+      .into_iter()
+      .filter(|device| self.env.matches(device));
+    let device = iter
+      .next()
+      .with_context(|| format!("Nitrokey device not found{}", self.env.format_filter()))?;
+    anyhow::ensure!(
+      iter.next().is_none(),
+      "Multiple Nitrokey devices found{}.  Use the --model, --serial-number, and --usb-path \
+      options to select one",
+      self.env.format_filter()
+    );
+    // The device may have shown up since `nitrocli` looked.
+    mgr.connect_path(device.path).map_err(From::from)
+  }
+
+  /// Retrieve the path to the directory in which this extension may
+  /// store its configuration.
+  pub fn config_dir(&self) -> &path::Path {
+    self.project_dirs.config_dir()
+  }
+
+  /// Retrieve the path to the directory in which this extension may
+  /// store its data.
+  pub fn data_dir(&self) -> &path::Path {
+    self.project_dirs.data_dir()
+  }
+
+  /// Retrieve the time at which `nitrocli` last changed the OTP slots
+  /// of the resolved device, if known.
+  ///
+  /// Extensions caching information about the OTP slots should
+  /// consider their cache outdated if it is older than this time.
+  pub fn otp_modified(&self) -> Option<time::SystemTime> {
+    let stamp = self.env.otp_stamp.as_ref()?;
+    fs::metadata(stamp).and_then(|m| m.modified()).ok()
+  }
+
+  /// Retrieve the path to the directory in which this extension may
+  /// store cacheable artifacts.
+  pub fn cache_dir(&self) -> &path::Path {
+    self.project_dirs.cache_dir()
+  }
+
+  /// Find the index of the password safe slot with the given name, if
+  /// any.
+  pub fn find_pws_slot(&self, name: &str) -> anyhow::Result<Option<u8>> {
+    let status = self
+      .nitrocli()
+      .args(["pws", "status"].iter())
+      .output()
+      .context("Failed to list the password safe slots")?;
+    parse_pws_slot(&status, name)
+  }
+}
+
+/// Find the index of the password safe slot with the given name in the
+/// output of `nitrocli pws status`.
+///
+/// An error is reported if multiple slots have the given name.
+pub fn parse_pws_slot(status: &str, name: &str) -> anyhow::Result<Option<u8>> {
+  let slots = status
+    .lines()
+    .skip(1)
+    .filter_map(|line| line.split_once('\t'))
+    .filter(|(_, slot_name)| *slot_name == name)
+    .map(|(slot, _)| slot)
+    .collect::<Vec<_>>();
+
+  match slots.as_slice() {
+    [] => Ok(None),
+    [slot] => slot
+      .parse()
+      .map(Some)
+      .with_context(|| format!("Failed to parse slot index {}", slot)),
+    _ => Err(anyhow::anyhow!(
+      "Found multiple password safe slots with the name {}",
+      name
+    )),
+  }
+}
+
+/// Take ownership of the secret channel passed on by `nitrocli`.
+fn open_secret_channel(fd: RawFd) -> anyhow::Result<net::UnixStream> {
+  // nitrocli hands the descriptor over to us and nobody else uses it.
+  let stream = unsafe { net::UnixStream::from_raw_fd(fd) };
+  // Do not pass the channel on to processes we start ourselves.
+  let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+  if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) } < 0 {
+    return Err(io::Error::last_os_error()).context("Failed to configure the file descriptor");
+  }
+  Ok(stream)
+}
+
+/// The metadata of an extension as reported to `nitrocli`.
+#[derive(Debug, serde::Serialize)]
+#[non_exhaustive]
+pub struct Metadata<'s> {
+  /// A short description of the extension.
+  pub description: &'s str,
+  /// The version of the extension.
+  pub version: &'s str,
+  /// The minimum version of `nitrocli` the extension works with.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub min_nitrocli_version: Option<&'s str>,
+}
+
+impl<'s> Metadata<'s> {
+  /// Create the metadata of an extension with the given description
+  /// and version.
+  pub fn new(description: &'s str, version: &'s str) -> Self {
+    Self {
+      description,
+      version,
+      min_nitrocli_version: None,
+    }
+  }
+
+  /// Set the minimum version of `nitrocli` the extension works with.
+  pub fn min_nitrocli_version(mut self, version: &'s str) -> Self {
+    self.min_nitrocli_version = Some(version);
+    self
+  }
+}
+
+/// Print the extension's metadata and exit if `nitrocli` asks for it
+/// through the `--nitrocli-describe` argument.
+///
+/// This function should be called before parsing any arguments.
+pub fn handle_describe(metadata: &Metadata<'_>) {
+  if env::args_os().nth(1).as_deref() == Some(ffi::OsStr::new(DESCRIBE_ARG)) {
+    match toml::to_string(metadata) {
+      Ok(data) => {
+        print!("{}", data);
+        process::exit(0)
+      }
+      Err(_) => process::exit(1),
+    }
+  }
+}
+
+// See src/commands.rs in nitrocli core.
+fn set_log_level(verbosity: u8) {
+  let log_lvl = match verbosity {
+    // The error log level is what libnitrokey uses by default. As such,
+    // there is no harm in us setting that as well when the user did not
+    // ask for higher verbosity.
+    0 => nitrokey::LogLevel::Error,
+    1 => nitrokey::LogLevel::Warning,
+    2 => nitrokey::LogLevel::Info,
+    3 => nitrokey::LogLevel::DebugL1,
+    4 => nitrokey::LogLevel::Debug,
+    _ => nitrokey::LogLevel::DebugL2,
+  };
+  nitrokey::set_log_level(log_lvl);
+}
+
+/// The error reported when `nitrocli` exits with a non-zero status.
+///
+/// It can be retrieved from the errors of [`Nitrocli::output`] and
+/// [`Nitrocli::output_with_input`] through
+/// [`anyhow::Error::downcast_ref`] to propagate the status.
+#[derive(Debug)]
+pub struct ExitError {
+  status: process::ExitStatus,
+}
+
+impl ExitError {
+  /// Retrieve the exit status of `nitrocli`.
+  pub fn status(&self) -> process::ExitStatus {
+    self.status
+  }
+}
+
+impl fmt::Display for ExitError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "nitrocli exited with {}", self.status)
+  }
+}
+
+impl error::Error for ExitError {}
+
+/// Exit the extension with a code reflecting the given result of its
+/// main logic.
+///
+/// If `nitrocli` exited with a non-zero status, it already reported
+/// the problem, so its exit code is passed on without printing the
+/// error again. Other errors are printed and cause an exit code of 1.
+pub fn exit(result: anyhow::Result<()>) -> ! {
+  let code = match result {
+    Ok(()) => 0,
+    Err(err) => match err.downcast_ref::<ExitError>() {
+      Some(err) => err.status.code().unwrap_or(1),
+      None => {
+        eprintln!("Error: {:#}", err);
+        1
+      }
+    },
+  };
+  process::exit(code)
+}
+
+/// A type allowing for convenient invocation of `nitrocli` itself.
+#[derive(Debug)]
+pub struct Nitrocli {
+  cmd: process::Command,
+}
+
+impl Nitrocli {
+  /// Create a new `Nitrocli` instance from a `Context`.
+  fn from_context(ctx: &Context) -> Nitrocli {
+    Self {
+      cmd: process::Command::new(&ctx.env.binary),
+    }
+  }
+
+  /// Add an argument to the `nitrocli` invocation.
+  pub fn arg<S>(&mut self, arg: S) -> &mut Nitrocli
+  where
+    S: AsRef<ffi::OsStr>,
+  {
+    let _ = self.cmd.arg(arg);
+    self
+  }
+
+  /// Add multiple arguments to the `nitrocli` invocation.
+  pub fn args<I, S>(&mut self, args: I) -> &mut Nitrocli
+  where
+    I: IntoIterator<Item = S>,
+    S: AsRef<ffi::OsStr>,
+  {
+    let _ = self.cmd.args(args);
+    self
+  }
+
+  /// Invoke `nitrocli` with the standard streams inherited and wait
+  /// for it to exit.
+  pub fn spawn(&mut self) -> anyhow::Result<process::ExitStatus> {
+    let mut child = self.cmd.spawn().context("Failed to invoke nitrocli")?;
+    child.wait().context("Failed to wait on nitrocli")
+  }
+
+  /// Invoke `nitrocli` and capture its standard output.
+  ///
+  /// The standard error output is passed through. A non-zero exit
+  /// status of `nitrocli` is reported as an [`ExitError`].
+  pub fn output(&mut self) -> anyhow::Result<String> {
+    let output = self
+      .cmd
+      .stderr(process::Stdio::inherit())
+      .output()
+      .context("Failed to invoke nitrocli")?;
+    Self::check_output(output)
+  }
+
+  /// Invoke `nitrocli` with the given data on its standard input and
+  /// capture its standard output.
+  ///
+  /// Secrets should be passed this way rather than as arguments, as
+  /// the latter are visible to other users of the system.
+  pub fn output_with_input(&mut self, input: &[u8]) -> anyhow::Result<String> {
+    let mut child = self
+      .cmd
+      .stdin(process::Stdio::piped())
+      .stdout(process::Stdio::piped())
+      .stderr(process::Stdio::inherit())
+      .spawn()
+      .context("Failed to invoke nitrocli")?;
+    // Dropping the handle closes the pipe, signaling end of input.
+    child
+      .stdin
+      .take()
+      .context("Failed to retrieve nitrocli's standard input")?
+      .write_all(input)
+      .context("Failed to write to nitrocli's standard input")?;
+    let output = child
+      .wait_with_output()
+      .context("Failed to wait on nitrocli")?;
+    Self::check_output(output)
+  }
+
+  fn check_output(output: process::Output) -> anyhow::Result<String> {
+    if !output.status.success() {
+      return Err(anyhow::Error::new(ExitError {
+        status: output.status,
+      }));
+    }
+    String::from_utf8(output.stdout).context("nitrocli output is not valid UTF-8")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::collections;
+
+  fn parse(vars: &[(&str, &str)]) -> anyhow::Result<Environment> {
+    let vars = vars
+      .iter()
+      .map(|(name, value)| (name.to_string(), ffi::OsString::from(value)))
+      .collect::<collections::HashMap<_, _>>();
+    Environment::from_vars(|name| vars.get(name).cloned())
+  }
+
+  #[test]
+  fn exit_status() {
+    use std::os::unix::process::ExitStatusExt as _;
+
+    let output = |code| process::Output {
+      status: process::ExitStatus::from_raw(code << 8),
+      stdout: b"output".to_vec(),
+      stderr: Vec::new(),
+    };
+    assert_eq!(Nitrocli::check_output(output(0)).unwrap(), "output");
+
+    let err = Nitrocli::check_output(output(2)).unwrap_err();
+    let status = err.downcast_ref::<ExitError>().unwrap().status();
+    assert_eq!(status.code(), Some(2));
+  }
+
+  #[test]
+  fn pws_slot_lookup() {
+    let status = "slot\tname\n0\tforge\n3\tmail\n4\tmail\n";
+    assert_eq!(parse_pws_slot(status, "forge").unwrap(), Some(0));
+    assert_eq!(parse_pws_slot(status, "other").unwrap(), None);
+
+    let err = parse_pws_slot(status, "mail").unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "Found multiple password safe slots with the name mail"
+    );
+  }
+
+  #[test]
+  fn minimal_environment() {
+    let env = parse(&[
+      ("NITROCLI_BINARY", "/usr/bin/nitrocli"),
+      ("NITROCLI_VERBOSITY", "0"),
+      ("NITROCLI_NO_CACHE", "false"),
+      ("NITROCLI_SERIAL_NUMBERS", ""),
+    ])
+    .unwrap();
+    assert_eq!(env.binary, "/usr/bin/nitrocli");
+    assert_eq!(env.verbosity, Some(0));
+    assert!(!env.no_cache);
+    assert!(!env.no_force_time);
+    assert!(env.serial_numbers.is_empty());
+    assert_eq!(env.model, None);
+    assert_eq!(env.resolved_usb_path, None);
+    assert_eq!(env.secret_fd, None);
+    assert_eq!(env.otp_stamp, None);
+    assert_eq!(env.format_filter(), "");
+
+    let err = parse(&[("NITROCLI_VERBOSITY", "0")]).unwrap_err();
+    assert_eq!(err.to_string(), "Failed to retrieve nitrocli path");
+  }
+
+  #[test]
+  fn full_environment() {
+    let env = parse(&[
+      ("NITROCLI_BINARY", "nitrocli"),
+      ("NITROCLI_VERBOSITY", "2"),
+      ("NITROCLI_NO_CACHE", "true"),
+      ("NITROCLI_NO_FORCE_TIME", "true"),
+      ("NITROCLI_MODEL", "storage"),
+      ("NITROCLI_SERIAL_NUMBERS", "0xdeadbeef,0x1"),
+      ("NITROCLI_USB_PATH", "0001:0002:00"),
+      ("NITROCLI_RESOLVED_USB_PATH", "0001:0002:00"),
+      ("NITROCLI_RESOLVED_MODEL", "storage"),
+      ("NITROCLI_RESOLVED_SERIAL_NUMBER", "0xdeadbeef"),
+      ("NITROCLI_SECRET_FD", "5"),
+      ("NITROCLI_OTP_STAMP", "/tmp/otp-stamps/0xdeadbeef"),
+    ])
+    .unwrap();
+    let serial_number = nitrokey::SerialNumber::from_str("0xdeadbeef").unwrap();
+    assert_eq!(env.verbosity, Some(2));
+    assert!(env.no_cache);
+    assert!(env.no_force_time);
+    assert_eq!(env.model, Some(nitrokey::Model::Storage));
+    assert_eq!(env.serial_numbers.len(), 2);
+    assert_eq!(env.serial_numbers[0], serial_number);
+    assert_eq!(env.usb_path.as_deref(), Some("0001:0002:00"));
+    assert_eq!(env.resolved_usb_path.as_deref(), Some("0001:0002:00"));
+    assert_eq!(env.resolved_model, Some(nitrokey::Model::Storage));
+    assert_eq!(env.resolved_serial_number, Some(serial_number));
+    assert_eq!(env.secret_fd, Some(5));
+    assert_eq!(
+      env.otp_stamp.as_deref(),
+      Some(path::Path::new("/tmp/otp-stamps/0xdeadbeef"))
+    );
+    assert_eq!(
+      env.format_filter(),
+      " (filter: model=storage, serial number in [0xdeadbeef, 0x00000001], usb path=0001:0002:00)"
+    );
+
+    let err = parse(&[
+      ("NITROCLI_BINARY", "nitrocli"),
+      ("NITROCLI_VERBOSITY", ""),
+      ("NITROCLI_MODEL", "fido2"),
+    ])
+    .unwrap_err();
+    assert_eq!(err.to_string(), "Failed to parse NITROCLI_MODEL");
+  }
+}
@@ -0,0 +1,586 @@
+// agent.rs
+
+// Copyright (C) 2026 The Nitrocli Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! The agent keeping the password safe of a Nitrokey unlocked for
+//! other nitrocli invocations.
+//!
+//! The agent listens on a Unix socket. Each connection carries a single
+//! request and its response, both encoded as one line of tab separated
+//! fields, in which backslashes, tabs, and newlines are escaped. A
+//! response starts with `ok`, followed by the result fields, or with
+//! `err`, followed by an error message.
+
+use std::io;
+use std::io::BufRead as _;
+use std::io::Write as _;
+use std::os::raw;
+use std::os::unix::net;
+use std::path;
+use std::str::FromStr as _;
+use std::sync::atomic;
+use std::thread;
+use std::time;
+
+use anyhow::Context as _;
+
+use crate::api;
+use crate::args;
+
+/// The interval in which to check for new connections and signals.
+const POLL_INTERVAL: time::Duration = time::Duration::from_millis(100);
+/// The interval in which to check whether the device is still present.
+const PRESENCE_INTERVAL: time::Duration = time::Duration::from_secs(2);
+/// The maximum time to wait for a client to send its request.
+const REQUEST_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+/// Whether a termination signal has been received.
+static TERMINATE: atomic::AtomicBool = atomic::AtomicBool::new(false);
+
+extern "C" fn handle_signal(_signal: raw::c_int) {
+  TERMINATE.store(true, atomic::Ordering::SeqCst);
+}
+
+/// Install the handlers for the signals that stop the agent.
+pub fn install_signal_handlers() {
+  let handler = handle_signal as extern "C" fn(raw::c_int) as libc::sighandler_t;
+  for signal in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+    let _ = unsafe { libc::signal(signal, handler) };
+  }
+}
+
+/// The reason why the agent stopped serving requests.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stop {
+  /// No request was received within the idle timeout.
+  Idle,
+  /// A termination signal was received.
+  Signal,
+  /// The device was removed.
+  Removed,
+  /// A client requested to lock the device.
+  Locked,
+}
+
+impl Stop {
+  /// Describe the reason for display to the user.
+  pub fn describe(self) -> &'static str {
+    match self {
+      Stop::Idle => "idle timeout expired",
+      Stop::Signal => "received termination signal",
+      Stop::Removed => "device was removed",
+      Stop::Locked => "lock requested",
+    }
+  }
+}
+
+/// The device whose password safe an agent keeps unlocked.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Device {
+  /// The model of the device.
+  pub model: args::DeviceModel,
+  /// The serial number of the device.
+  pub serial_number: nitrokey::SerialNumber,
+  /// The USB path of the device.
+  pub usb_path: String,
+}
+
+impl Device {
+  fn encode(&self) -> Vec<String> {
+    vec![
+      self.model.as_ref().to_string(),
+      self.serial_number.to_string(),
+      self.usb_path.clone(),
+    ]
+  }
+
+  fn decode(fields: Vec<String>) -> anyhow::Result<Self> {
+    match <[String; 3]>::try_from(fields) {
+      Ok([model, serial_number, usb_path]) => Ok(Self {
+        model: model
+          .parse()
+          .map_err(|_| anyhow::anyhow!("Invalid model in agent response: {}", model))?,
+        serial_number: serial_number
+          .parse()
+          .with_context(|| format!("Invalid serial number in agent response: {}", serial_number))?,
+        usb_path,
+      }),
+      Err(_) => anyhow::bail!("Received invalid response from the nitrocli agent"),
+    }
+  }
+}
+
+/// Escape the special characters of a field.
+fn escape(field: &str) -> String {
+  let mut result = String::with_capacity(field.len());
+  for c in field.chars() {
+    match c {
+      '\\' => result.push_str("\\\\"),
+      '\t' => result.push_str("\\t"),
+      '\n' => result.push_str("\\n"),
+      c => result.push(c),
+    }
+  }
+  result
+}
+
+/// Undo the escaping of a field.
+fn unescape(field: &str) -> anyhow::Result<String> {
+  let mut result = String::with_capacity(field.len());
+  let mut chars = field.chars();
+  while let Some(c) = chars.next() {
+    if c == '\\' {
+      match chars.next() {
+        Some('\\') => result.push('\\'),
+        Some('t') => result.push('\t'),
+        Some('n') => result.push('\n'),
+        _ => anyhow::bail!("Invalid escape sequence in agent message"),
+      }
+    } else {
+      result.push(c);
+    }
+  }
+  Ok(result)
+}
+
+/// Encode the given fields as a message line.
+fn encode<S>(fields: &[S]) -> String
+where
+  S: AsRef<str>,
+{
+  let mut line = fields
+    .iter()
+    .map(|field| escape(field.as_ref()))
+    .collect::<Vec<_>>()
+    .join("\t");
+  line.push('\n');
+  line
+}
+
+/// Decode a message line into its fields.
+fn decode(line: &str) -> anyhow::Result<Vec<String>> {
+  line
+    .strip_suffix('\n')
+    .unwrap_or(line)
+    .split('\t')
+    .map(unescape)
+    .collect()
+}
+
+/// Encode an optional slot name as a field.
+fn encode_name(name: Option<&str>) -> String {
+  match name {
+    Some(name) => format!("+{}", name),
+    None => "-".to_string(),
+  }
+}
+
+/// Decode an optional slot name from a field.
+fn decode_name(field: &str) -> anyhow::Result<Option<String>> {
+  match field.strip_prefix('+') {
+    Some(name) => Ok(Some(name.to_string())),
+    None if field == "-" => Ok(None),
+    None => anyhow::bail!("Invalid slot name in agent response"),
+  }
+}
+
+fn parse_slot(field: Option<&String>) -> anyhow::Result<u8> {
+  let field = field.context("Missing slot in agent request")?;
+  u8::from_str(field).with_context(|| format!("Invalid slot in agent request: {}", field))
+}
+
+fn encode_field(field: api::PwsField) -> &'static str {
+  match field {
+    api::PwsField::Name => "name",
+    api::PwsField::Login => "login",
+    api::PwsField::Password => "password",
+  }
+}
+
+fn decode_field(field: &str) -> anyhow::Result<api::PwsField> {
+  match field {
+    "name" => Ok(api::PwsField::Name),
+    "login" => Ok(api::PwsField::Login),
+    "password" => Ok(api::PwsField::Password),
+    _ => anyhow::bail!("Invalid slot field in agent request: {}", field),
+  }
+}
+
+/// Handle a request, returning the fields of the response and whether
+/// the device should be locked.
+fn handle_request<S>(
+  store: &mut S,
+  device: &Device,
+  request: &[String],
+) -> anyhow::Result<(Vec<String>, bool)>
+where
+  S: api::PasswordStore + ?Sized,
+{
+  let command = request.first().map(String::as_str).unwrap_or_default();
+  match (command, request.len()) {
+    ("device", 1) => Ok((device.encode(), false)),
+    ("programmed", 1) => {
+      let programmed = store.programmed_slots()?;
+      let fields = programmed
+        .iter()
+        .map(|programmed| u8::from(*programmed).to_string());
+      Ok((fields.collect(), false))
+    }
+    ("names", 1) => {
+      let names = store.slot_names()?;
+      let fields = names.iter().map(|name| encode_name(name.as_deref()));
+      Ok((fields.collect(), false))
+    }
+    ("get", n) if n > 2 => {
+      let fields = request[2..]
+        .iter()
+        .map(|field| decode_field(field))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+      let values = store.slot_fields(parse_slot(request.get(1))?, &fields)?;
+      Ok((values, false))
+    }
+    ("write", 5) => {
+      let content = api::PwsSlot {
+        name: request[2].clone(),
+        login: request[3].clone(),
+        password: request[4].clone(),
+      };
+      store.write_slot(parse_slot(request.get(1))?, &content)?;
+      Ok((Vec::new(), false))
+    }
+    ("erase", 2) => {
+      store.erase_slot(parse_slot(request.get(1))?)?;
+      Ok((Vec::new(), false))
+    }
+    ("lock", 1) => Ok((Vec::new(), true)),
+    _ => anyhow::bail!("Invalid agent request: {}", command),
+  }
+}
+
+/// Read a request from the given connection, handle it, and write the
+/// response, returning whether the device should be locked.
+fn handle_connection<S>(store: &mut S, device: &Device, stream: net::UnixStream) -> io::Result<bool>
+where
+  S: api::PasswordStore + ?Sized,
+{
+  stream.set_nonblocking(false)?;
+  stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+
+  let mut line = String::new();
+  let _ = io::BufReader::new(&stream).read_line(&mut line)?;
+  let result = decode(&line).and_then(|request| handle_request(store, device, &request));
+  let (response, lock) = match result {
+    Ok((mut fields, lock)) => {
+      fields.insert(0, "ok".to_string());
+      (fields, lock)
+    }
+    Err(err) => (vec!["err".to_string(), format!("{:#}", err)], false),
+  };
+  (&stream).write_all(encode(&response).as_bytes())?;
+  Ok(lock)
+}
+
+/// Serve requests on the given socket using the given password store
+/// of the given device until the agent is asked to stop.
+///
+/// `is_present` is invoked regularly to check whether the device is
+/// still connected.
+pub fn serve<S, P>(
+  listener: &net::UnixListener,
+  store: &mut S,
+  device: &Device,
+  idle_timeout: time::Duration,
+  mut is_present: P,
+) -> anyhow::Result<Stop>
+where
+  S: api::PasswordStore + ?Sized,
+  P: FnMut() -> bool,
+{
+  listener
+    .set_nonblocking(true)
+    .context("Failed to configure agent socket")?;
+
+  let mut last_request = time::Instant::now();
+  let mut last_check = time::Instant::now();
+  loop {
+    if TERMINATE.load(atomic::Ordering::SeqCst) {
+      return Ok(Stop::Signal);
+    }
+
+    match listener.accept() {
+      Ok((stream, _)) => {
+        last_request = time::Instant::now();
+        // A misbehaving client must not bring down the agent, so errors
+        // on the connection are ignored.
+        if let Ok(true) = handle_connection(store, device, stream) {
+          return Ok(Stop::Locked);
+        }
+      }
+      Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+        if last_request.elapsed() >= idle_timeout {
+          return Ok(Stop::Idle);
+        }
+        if last_check.elapsed() >= PRESENCE_INTERVAL {
+          if !is_present() {
+            return Ok(Stop::Removed);
+          }
+          last_check = time::Instant::now();
+        }
+        thread::sleep(POLL_INTERVAL);
+      }
+      Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+      Err(err) => return Err(err).context("Failed to accept agent connection"),
+    }
+  }
+}
+
+/// A client of the agent.
+#[derive(Debug)]
+pub struct Client {
+  path: path::PathBuf,
+}
+
+impl Client {
+  pub fn new<P>(path: P) -> Self
+  where
+    P: Into<path::PathBuf>,
+  {
+    Self { path: path.into() }
+  }
+
+  /// Send a request to the agent and return the fields of its response.
+  fn request<S>(&self, request: &[S]) -> anyhow::Result<Vec<String>>
+  where
+    S: AsRef<str>,
+  {
+    let mut stream = net::UnixStream::connect(&self.path).with_context(|| {
+      format!(
+        "Failed to connect to the nitrocli agent at {}",
+        self.path.display()
+      )
+    })?;
+    stream
+      .write_all(encode(request).as_bytes())
+      .context("Failed to send request to the nitrocli agent")?;
+
+    let mut line = String::new();
+    let _ = io::BufReader::new(&stream)
+      .read_line(&mut line)
+      .context("Failed to read response of the nitrocli agent")?;
+    let mut fields = decode(&line)?;
+    match fields.first().map(String::as_str) {
+      Some("ok") => Ok(fields.split_off(1)),
+      Some("err") if fields.len() == 2 => Err(anyhow::anyhow!(fields.remove(1))),
+      _ => anyhow::bail!("Received invalid response from the nitrocli agent"),
+    }
+  }
+
+  /// Retrieve the device served by the agent.
+  pub fn device(&self) -> anyhow::Result<Device> {
+    Device::decode(self.request(&["device"])?)
+  }
+
+  /// Ask the agent to lock the device and to exit.
+  pub fn lock(&self) -> anyhow::Result<()> {
+    let _ = self.request(&["lock"])?;
+    Ok(())
+  }
+}
+
+impl api::PasswordStore for Client {
+  fn programmed_slots(&self) -> anyhow::Result<Vec<bool>> {
+    self
+      .request(&["programmed"])?
+      .iter()
+      .map(|field| match field.as_str() {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => anyhow::bail!("Received invalid response from the nitrocli agent"),
+      })
+      .collect()
+  }
+
+  fn slot_names(&self) -> anyhow::Result<Vec<Option<String>>> {
+    self
+      .request(&["names"])?
+      .iter()
+      .map(|field| decode_name(field))
+      .collect()
+  }
+
+  fn slot_fields(&self, slot: u8, fields: &[api::PwsField]) -> anyhow::Result<Vec<String>> {
+    let slot = slot.to_string();
+    let mut request = vec!["get", &slot];
+    request.extend(fields.iter().map(|field| encode_field(*field)));
+    let values = self.request(&request)?;
+    anyhow::ensure!(
+      values.len() == fields.len(),
+      "Received invalid response from the nitrocli agent"
+    );
+    Ok(values)
+  }
+
+  fn write_slot(&mut self, slot: u8, content: &api::PwsSlot) -> anyhow::Result<()> {
+    let slot = slot.to_string();
+    let request = [
+      "write",
+      &slot,
+      &content.name,
+      &content.login,
+      &content.password,
+    ];
+    let _ = self.request(&request)?;
+    Ok(())
+  }
+
+  fn erase_slot(&mut self, slot: u8) -> anyhow::Result<()> {
+    let _ = self.request(&["erase", &slot.to_string()])?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::api::PasswordStore as _;
+
+  /// A password store keeping its slots in memory.
+  #[derive(Debug, Default)]
+  struct MemoryStore {
+    slots: Vec<Option<api::PwsSlot>>,
+  }
+
+  impl api::PasswordStore for MemoryStore {
+    fn programmed_slots(&self) -> anyhow::Result<Vec<bool>> {
+      Ok(self.slots.iter().map(Option::is_some).collect())
+    }
+
+    fn slot_names(&self) -> anyhow::Result<Vec<Option<String>>> {
+      Ok(
+        self
+          .slots
+          .iter()
+          .map(|slot| slot.as_ref().map(|slot| slot.name.clone()))
+          .collect(),
+      )
+    }
+
+    fn slot_fields(&self, slot: u8, fields: &[api::PwsField]) -> anyhow::Result<Vec<String>> {
+      let slot = self
+        .slots
+        .get(usize::from(slot))
+        .cloned()
+        .flatten()
+        .context("Failed to access PWS slot")?;
+      let values = fields.iter().map(|field| match field {
+        api::PwsField::Name => slot.name.clone(),
+        api::PwsField::Login => slot.login.clone(),
+        api::PwsField::Password => slot.password.clone(),
+      });
+      Ok(values.collect())
+    }
+
+    fn write_slot(&mut self, slot: u8, content: &api::PwsSlot) -> anyhow::Result<()> {
+      self.slots[usize::from(slot)] = Some(content.clone());
+      Ok(())
+    }
+
+    fn erase_slot(&mut self, slot: u8) -> anyhow::Result<()> {
+      self.slots[usize::from(slot)] = None;
+      Ok(())
+    }
+  }
+
+  fn device() -> Device {
+    Device {
+      model: args::DeviceModel::Pro,
+      serial_number: "0x1234".parse().unwrap(),
+      usb_path: "0001:0002:00".to_string(),
+    }
+  }
+
+  #[test]
+  fn encode_decode() -> anyhow::Result<()> {
+    let fields = ["get", "", "a\tb", "back\\slash", "new\nline", "\\t"];
+    let line = encode(&fields);
+    assert_eq!(line.matches('\n').count(), 1);
+    assert_eq!(decode(&line)?, fields);
+    assert!(decode("invalid\\x").is_err());
+
+    assert_eq!(decode_name(&encode_name(Some("-")))?, Some("-".to_string()));
+    assert_eq!(decode_name(&encode_name(None))?, None);
+    Ok(())
+  }
+
+  #[test]
+  fn client_server() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("agent.sock");
+    let listener = net::UnixListener::bind(&path)?;
+
+    let server = thread::spawn(move || {
+      let mut store = MemoryStore {
+        slots: vec![None; 4],
+      };
+      let timeout = time::Duration::from_secs(60);
+      let stop = serve(&listener, &mut store, &device(), timeout, || true);
+      (stop.unwrap(), store)
+    });
+
+    let mut client = Client::new(&path);
+    assert_eq!(client.device()?, device());
+    let slot = api::PwsSlot {
+      name: "name with\ttab".to_string(),
+      login: "login".to_string(),
+      password: "pass\\word".to_string(),
+    };
+    client.write_slot(1, &slot)?;
+    assert_eq!(client.slot(1)?, slot);
+    assert_eq!(
+      client.slot_fields(1, &[api::PwsField::Password, api::PwsField::Name])?,
+      vec![slot.password.clone(), slot.name.clone()]
+    );
+    assert_eq!(client.programmed_slots()?, vec![false, true, false, false]);
+    assert_eq!(
+      client.slot_names()?,
+      vec![None, Some(slot.name.clone()), None, None]
+    );
+    let err = client.slot(0).unwrap_err();
+    assert_eq!(err.to_string(), "Failed to access PWS slot");
+    client.erase_slot(1)?;
+    assert_eq!(client.slot_names()?, vec![None; 4]);
+    client.lock()?;
+
+    let (stop, store) = server.join().unwrap();
+    assert_eq!(stop, Stop::Locked);
+    assert_eq!(store.slots, vec![None; 4]);
+
+    let err = client.lock().unwrap_err();
+    assert!(err
+      .to_string()
+      .starts_with("Failed to connect to the nitrocli agent"));
+    Ok(())
+  }
+
+  #[test]
+  fn stop_serving() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let listener = net::UnixListener::bind(dir.path().join("agent.sock"))?;
+    let mut store = MemoryStore::default();
+
+    let timeout = time::Duration::from_millis(200);
+    assert_eq!(
+      serve(&listener, &mut store, &device(), timeout, || true)?,
+      Stop::Idle
+    );
+
+    let timeout = time::Duration::from_secs(60);
+    assert_eq!(
+      serve(&listener, &mut store, &device(), timeout, || false)?,
+      Stop::Removed
+    );
+    Ok(())
+  }
+}
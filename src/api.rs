@@ -0,0 +1,721 @@
+// api.rs
+
+// Copyright (C) 2026 The Nitrocli Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! A Rust API for the operations provided by nitrocli.
+//!
+//! The functions in this module work on devices connected through the
+//! [`nitrokey`] crate and return typed data instead of printing it.
+//! Secrets such as PINs are retrieved through an [`Authenticator`],
+//! which the command line interface implements on top of pinentry.
+
+use std::ops;
+
+use anyhow::Context as _;
+use libc::sync;
+
+use nitrokey::Authenticate;
+use nitrokey::Device;
+use nitrokey::GenerateOtp;
+use nitrokey::GetPasswordSafe;
+
+pub use crate::args::OtpAlgorithm;
+pub use crate::args::UnencryptedVolumeMode;
+
+use crate::volume;
+
+/// The number of tries a user has to enter a correct PIN.
+pub(crate) const PIN_TRIES: u8 = 3;
+
+/// A secret that an operation requires.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Secret {
+  /// The user PIN.
+  UserPin,
+  /// The admin PIN.
+  AdminPin,
+  /// The password of a hidden volume.
+  HiddenVolumePassword,
+  /// The update PIN of a Nitrokey Storage.
+  UpdatePin,
+}
+
+/// A provider of the secrets required by operations.
+pub trait Authenticator {
+  /// Retrieve the given secret.
+  ///
+  /// If a previously retrieved secret was rejected by the device,
+  /// `error` contains a message suitable for display to the user.
+  fn secret(&mut self, secret: Secret, error: Option<&str>) -> anyhow::Result<String>;
+
+  /// Handle the rejection of the given secret by the device, e.g., by
+  /// clearing a cached value.
+  ///
+  /// The return value indicates whether the secret should be retrieved
+  /// again. By default, a rejected secret is not retried, as every
+  /// wrong PIN decrements the retry counter of the device.
+  fn reject(&mut self, secret: Secret) -> anyhow::Result<bool> {
+    let _ = secret;
+    Ok(false)
+  }
+}
+
+impl<F> Authenticator for F
+where
+  F: FnMut(Secret, Option<&str>) -> anyhow::Result<String>,
+{
+  fn secret(&mut self, secret: Secret, error: Option<&str>) -> anyhow::Result<String> {
+    self(secret, error)
+  }
+}
+
+/// Try to execute the given function with a secret retrieved from the
+/// given authenticator.
+///
+/// If the function fails with a `CommandError::WrongPassword` and the
+/// authenticator asks for it, the secret is retrieved again, for at
+/// most three tries overall. The data argument can be used to pass on
+/// data between the tries: at the first try, `op` is called with
+/// `data`, at every further try with the data returned by the previous
+/// call to `op`.
+pub(crate) fn try_with_secret_and_data<A, D, F, R>(
+  auth: &mut A,
+  secret: Secret,
+  data: D,
+  mut op: F,
+) -> anyhow::Result<R>
+where
+  A: Authenticator + ?Sized,
+  F: FnMut(D, &str) -> Result<R, (D, anyhow::Error)>,
+{
+  let mut data = data;
+  let mut retry = PIN_TRIES;
+  let mut error_msg = None;
+  loop {
+    let value = auth.secret(secret, error_msg)?;
+    match op(data, &value) {
+      Ok(result) => return Ok(result),
+      Err((new_data, err)) => match err.downcast_ref::<nitrokey::Error>() {
+        Some(nitrokey::Error::CommandError(nitrokey::CommandError::WrongPassword)) => {
+          if !auth.reject(secret)? {
+            return Err(err);
+          }
+          retry -= 1;
+
+          if retry > 0 {
+            error_msg = Some("Wrong password, please reenter");
+            data = new_data;
+            continue;
+          }
+          anyhow::bail!(err.downcast::<nitrokey::Error>().unwrap());
+        }
+        _ => return Err(err),
+      },
+    };
+  }
+}
+
+/// Try to execute the given function with a secret retrieved from the
+/// given authenticator.
+pub(crate) fn try_with_secret<A, F, R>(auth: &mut A, secret: Secret, mut op: F) -> anyhow::Result<R>
+where
+  A: Authenticator + ?Sized,
+  F: FnMut(&str) -> anyhow::Result<R>,
+{
+  try_with_secret_and_data(auth, secret, (), |data, value| {
+    op(value).map_err(|err| (data, err))
+  })
+}
+
+/// The status of a Nitrokey device.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Status {
+  /// The model of the device.
+  pub model: nitrokey::Model,
+  /// The serial number of the device.
+  pub serial_number: nitrokey::SerialNumber,
+  /// The firmware version of the device.
+  pub firmware_version: nitrokey::FirmwareVersion,
+  /// The number of remaining tries for the user PIN.
+  pub user_retry_count: u8,
+  /// The number of remaining tries for the admin PIN.
+  pub admin_retry_count: u8,
+  /// The status of the storage area and the range of the SD card that
+  /// has not been written to, in percent, for Nitrokey Storage devices.
+  pub storage: Option<(nitrokey::StorageStatus, ops::Range<u8>)>,
+  /// The production information, for Nitrokey Storage devices and only
+  /// if requested.
+  pub production_info: Option<nitrokey::StorageProductionInfo>,
+}
+
+/// Retrieve the status of the given device.
+///
+/// The production information is not retrieved, see
+/// [`production_info`].
+pub fn status(device: &nitrokey::DeviceWrapper<'_>) -> anyhow::Result<Status> {
+  if let nitrokey::DeviceWrapper::Storage(device) = device {
+    // TODO: Extract serial number from storage status, see
+    //       https://todo.sr.ht/~ireas/nitrokey-rs/1
+    let serial_number = device
+      .get_serial_number()
+      .context("Failed to retrieve serial number")?;
+    let status = device
+      .get_storage_status()
+      .context("Failed to retrieve storage status")?;
+    let sd_card_usage = device
+      .get_sd_card_usage()
+      .context("Failed to retrieve SD card usage")?;
+
+    Ok(Status {
+      model: device.get_model(),
+      serial_number,
+      firmware_version: status.firmware_version,
+      user_retry_count: status.user_retry_count,
+      admin_retry_count: status.admin_retry_count,
+      storage: Some((status, sd_card_usage)),
+      production_info: None,
+    })
+  } else {
+    let status = device
+      .get_status()
+      .context("Could not query the device status")?;
+    let user_retry_count = device
+      .get_user_retry_count()
+      .context("Failed to retrieve user retry count")?;
+    let admin_retry_count = device
+      .get_admin_retry_count()
+      .context("Failed to retrieve admin retry count")?;
+
+    Ok(Status {
+      model: device.get_model(),
+      serial_number: status.serial_number,
+      firmware_version: status.firmware_version,
+      user_retry_count,
+      admin_retry_count,
+      storage: None,
+      production_info: None,
+    })
+  }
+}
+
+/// Retrieve the production information of the given device, if it is a
+/// Nitrokey Storage.
+pub fn production_info(
+  device: &nitrokey::DeviceWrapper<'_>,
+) -> anyhow::Result<Option<nitrokey::StorageProductionInfo>> {
+  if let nitrokey::DeviceWrapper::Storage(device) = device {
+    device
+      .get_production_info()
+      .map(Some)
+      .context("Failed to retrieve production information")
+  } else {
+    Ok(None)
+  }
+}
+
+/// Retrieve the names of all OTP slots for the given algorithm, with
+/// `None` for slots that are not programmed.
+pub fn otp_slot_names(
+  device: &nitrokey::DeviceWrapper<'_>,
+  algorithm: OtpAlgorithm,
+) -> anyhow::Result<Vec<Option<String>>> {
+  let mut names = Vec::new();
+  let mut slot: u8 = 0;
+  loop {
+    let result = match algorithm {
+      OtpAlgorithm::Hotp => device.get_hotp_slot_name(slot),
+      OtpAlgorithm::Totp => device.get_totp_slot_name(slot),
+    };
+    slot = slot
+      .checked_add(1)
+      .context("Encountered integer overflow when iterating OTP slots")?;
+    match result {
+      Ok(name) => names.push(Some(name)),
+      Err(nitrokey::Error::LibraryError(nitrokey::LibraryError::InvalidSlot)) => return Ok(names),
+      Err(nitrokey::Error::CommandError(nitrokey::CommandError::SlotNotProgrammed)) => {
+        names.push(None)
+      }
+      Err(err) => return Err(err).context("Failed to check OTP slot"),
+    }
+  }
+}
+
+/// The maximum length of the name of a password safe slot.
+pub const PWS_NAME_LENGTH: usize = 11;
+/// The maximum length of the login stored in a password safe slot.
+pub const PWS_LOGIN_LENGTH: usize = 32;
+/// The maximum length of the password stored in a password safe slot.
+pub const PWS_PASSWORD_LENGTH: usize = 20;
+
+/// The content of a password safe slot.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PwsSlot {
+  /// The name of the slot.
+  pub name: String,
+  /// The login stored in the slot.
+  pub login: String,
+  /// The password stored in the slot.
+  pub password: String,
+}
+
+/// A field of a password safe slot.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PwsField {
+  /// The name of the slot.
+  Name,
+  /// The login stored in the slot.
+  Login,
+  /// The password stored in the slot.
+  Password,
+}
+
+impl PwsField {
+  /// All fields of a slot.
+  pub const ALL: [PwsField; 3] = [PwsField::Name, PwsField::Login, PwsField::Password];
+}
+
+/// Access to the slots of an unlocked password safe.
+pub trait PasswordStore {
+  /// Retrieve whether each of the slots is programmed.
+  fn programmed_slots(&self) -> anyhow::Result<Vec<bool>>;
+
+  /// Retrieve the names of all slots, with `None` for slots that are
+  /// not programmed.
+  fn slot_names(&self) -> anyhow::Result<Vec<Option<String>>>;
+
+  /// Read the given fields of the given slot, in the given order.
+  fn slot_fields(&self, slot: u8, fields: &[PwsField]) -> anyhow::Result<Vec<String>>;
+
+  /// Read the content of the given slot.
+  fn slot(&self, slot: u8) -> anyhow::Result<PwsSlot> {
+    match <[String; 3]>::try_from(self.slot_fields(slot, &PwsField::ALL)?) {
+      Ok([name, login, password]) => Ok(PwsSlot {
+        name,
+        login,
+        password,
+      }),
+      Err(_) => anyhow::bail!("Failed to access PWS slot"),
+    }
+  }
+
+  /// Write the given content to the given slot.
+  fn write_slot(&mut self, slot: u8, content: &PwsSlot) -> anyhow::Result<()>;
+
+  /// Erase the given slot.
+  fn erase_slot(&mut self, slot: u8) -> anyhow::Result<()>;
+}
+
+/// An unlocked password safe.
+#[derive(Debug)]
+pub struct PasswordSafe<'pws, 'mgr> {
+  pws: nitrokey::PasswordSafe<'pws, 'mgr>,
+}
+
+impl PasswordStore for PasswordSafe<'_, '_> {
+  fn programmed_slots(&self) -> anyhow::Result<Vec<bool>> {
+    let slots = self
+      .pws
+      .get_slots()
+      .context("Failed to read PWS slot status")?;
+    Ok(slots.iter().map(Option::is_some).collect())
+  }
+
+  fn slot_names(&self) -> anyhow::Result<Vec<Option<String>>> {
+    let slots = self
+      .pws
+      .get_slots()
+      .context("Failed to read PWS slot status")?;
+    slots
+      .iter()
+      .map(|slot| {
+        slot
+          .map(|slot| slot.get_name().context("Failed to read PWS slot name"))
+          .transpose()
+      })
+      .collect()
+  }
+
+  fn slot_fields(&self, slot: u8, fields: &[PwsField]) -> anyhow::Result<Vec<String>> {
+    let slot = self
+      .pws
+      .get_slot(slot)
+      .context("Failed to access PWS slot")?;
+    fields
+      .iter()
+      .map(|field| {
+        match field {
+          PwsField::Name => slot.get_name(),
+          PwsField::Login => slot.get_login(),
+          PwsField::Password => slot.get_password(),
+        }
+        .context("Failed to access PWS slot")
+      })
+      .collect()
+  }
+
+  fn write_slot(&mut self, slot: u8, content: &PwsSlot) -> anyhow::Result<()> {
+    self
+      .pws
+      .write_slot(slot, &content.name, &content.login, &content.password)
+      .context("Failed to write PWS slot")
+  }
+
+  fn erase_slot(&mut self, slot: u8) -> anyhow::Result<()> {
+    self
+      .pws
+      .erase_slot(slot)
+      .context("Failed to clear PWS slot")
+  }
+}
+
+/// Unlock the password safe of the given device with the user PIN and
+/// do something with it.
+pub fn with_password_safe<A, F, R>(
+  device: &mut nitrokey::DeviceWrapper<'_>,
+  auth: &mut A,
+  op: F,
+) -> anyhow::Result<R>
+where
+  A: Authenticator + ?Sized,
+  F: FnOnce(&mut PasswordSafe<'_, '_>) -> anyhow::Result<R>,
+{
+  // The operation is only invoked once the password safe is unlocked,
+  // at which point no more tries happen.
+  let mut op = Some(op);
+  try_with_secret(auth, Secret::UserPin, |pin| {
+    let pws = device
+      .get_password_safe(pin)
+      .context("Could not access the password safe")?;
+    let op = op.take().expect("password safe operation invoked twice");
+    Ok(op(&mut PasswordSafe { pws }))
+  })?
+}
+
+/// The general configuration of a device.
+#[derive(Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+  /// The HOTP slot bound to the Num Lock key.
+  pub num_lock: Option<u8>,
+  /// The HOTP slot bound to the Caps Lock key.
+  pub caps_lock: Option<u8>,
+  /// The HOTP slot bound to the Scroll Lock key.
+  pub scroll_lock: Option<u8>,
+  /// Whether the user PIN is required to generate one-time passwords.
+  #[serde(default)]
+  pub otp_pin: bool,
+  /// The mode of the unencrypted volume (Nitrokey Storage only).
+  pub unencrypted_volume: Option<UnencryptedVolumeMode>,
+}
+
+/// Retrieve the general configuration of the given device.
+pub fn device_config(device: &nitrokey::DeviceWrapper<'_>) -> anyhow::Result<DeviceConfig> {
+  let config = device.get_config().context("Failed to get configuration")?;
+  let unencrypted_volume = if let nitrokey::DeviceWrapper::Storage(storage) = device {
+    let status = storage
+      .get_storage_status()
+      .context("Failed to retrieve storage status")?;
+    if status.unencrypted_volume.read_only {
+      Some(UnencryptedVolumeMode::ReadOnly)
+    } else {
+      Some(UnencryptedVolumeMode::ReadWrite)
+    }
+  } else {
+    None
+  };
+
+  Ok(DeviceConfig {
+    num_lock: config.num_lock,
+    caps_lock: config.caps_lock,
+    scroll_lock: config.scroll_lock,
+    otp_pin: config.user_password,
+    unencrypted_volume,
+  })
+}
+
+/// Write the general configuration of the given device, authenticating
+/// with the admin PIN.
+///
+/// The mode of the unencrypted volume is only changed if it differs
+/// from the current one. Note that a Nitrokey Storage reconnects after
+/// such a change.
+pub fn set_device_config<A>(
+  device: nitrokey::DeviceWrapper<'_>,
+  auth: &mut A,
+  config: &DeviceConfig,
+) -> anyhow::Result<()>
+where
+  A: Authenticator + ?Sized,
+{
+  let current = device_config(&device)?;
+  anyhow::ensure!(
+    config.unencrypted_volume.is_none() || current.unencrypted_volume.is_some(),
+    "The unencrypted volume mode can only be set on a Nitrokey Storage"
+  );
+
+  // Changing the mode of the unencrypted volume requires the admin PIN
+  // as well, so we keep it around instead of asking for it again.
+  let mut admin_pin = String::new();
+  let mut device = try_with_secret_and_data(auth, Secret::AdminPin, device, |device, pin| {
+    admin_pin = pin.to_string();
+    device.authenticate_admin(pin).or_else(|(x, err)| {
+      Err(err)
+        .context("Failed to authenticate as admin")
+        .map_err(|err| (x, err))
+    })
+  })?;
+  let nk_config = nitrokey::Config {
+    num_lock: config.num_lock,
+    caps_lock: config.caps_lock,
+    scroll_lock: config.scroll_lock,
+    user_password: config.otp_pin,
+  };
+  device
+    .write_config(nk_config)
+    .context("Failed to set new configuration")?;
+
+  if let nitrokey::DeviceWrapper::Storage(storage) = &mut *device {
+    if let Some(mode) = config.unencrypted_volume {
+      if Some(mode) != current.unencrypted_volume {
+        write_unencrypted_volume_mode(storage, &admin_pin, mode)?;
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Change the mode of the unencrypted volume of the given device.
+///
+/// The device reconnects after the change, so it cannot be used any
+/// further.
+pub fn set_unencrypted_volume_mode<A>(
+  device: &mut nitrokey::Storage<'_>,
+  auth: &mut A,
+  mode: UnencryptedVolumeMode,
+) -> anyhow::Result<()>
+where
+  A: Authenticator + ?Sized,
+{
+  try_with_secret(auth, Secret::AdminPin, |pin| {
+    write_unencrypted_volume_mode(device, pin, mode)
+  })
+}
+
+/// Change the mode of the unencrypted volume of the given device using
+/// the given admin PIN.
+fn write_unencrypted_volume_mode(
+  device: &mut nitrokey::Storage<'_>,
+  admin_pin: &str,
+  mode: UnencryptedVolumeMode,
+) -> anyhow::Result<()> {
+  let mode = match mode {
+    UnencryptedVolumeMode::ReadWrite => nitrokey::VolumeMode::ReadWrite,
+    UnencryptedVolumeMode::ReadOnly => nitrokey::VolumeMode::ReadOnly,
+  };
+
+  // The unencrypted volume may reconnect, so be sure to flush caches to
+  // disk.
+  unsafe { sync() };
+
+  device
+    .set_unencrypted_volume_mode(admin_pin, mode)
+    .context("Failed to change unencrypted volume mode")
+}
+
+/// Unmount all file systems of the encrypted volume of the device with
+/// the given USB path.
+fn unmount_encrypted_volume(usb_path: &str) -> anyhow::Result<()> {
+  let usb_device = volume::UsbDevice::from_hid_path(usb_path)?;
+  let _ = volume::unmount_all(usb_device, volume::Volume::Encrypted)?;
+  Ok(())
+}
+
+/// Unmount and flush the encrypted volume if the given volume of the
+/// device is active, as opening another volume closes it.
+fn prepare_volume_switch(
+  device: &nitrokey::Storage<'_>,
+  usb_path: &str,
+  active: fn(&nitrokey::StorageStatus) -> bool,
+) -> anyhow::Result<()> {
+  let status = device
+    .get_storage_status()
+    .context("Failed to retrieve storage status")?;
+  if active(&status) {
+    unmount_encrypted_volume(usb_path)?;
+  }
+  unsafe { sync() };
+  Ok(())
+}
+
+/// Open the encrypted volume of the given device with the user PIN.
+///
+/// `usb_path` is the path of the device as reported by
+/// `nitrokey::list_devices`, used to find its block devices.  An active
+/// hidden volume is unmounted and closed.
+pub fn open_encrypted_volume<A>(
+  device: &mut nitrokey::Storage<'_>,
+  usb_path: &str,
+  auth: &mut A,
+) -> anyhow::Result<()>
+where
+  A: Authenticator + ?Sized,
+{
+  prepare_volume_switch(device, usb_path, |status| status.hidden_volume.active)?;
+  try_with_secret(auth, Secret::UserPin, |pin| {
+    device
+      .enable_encrypted_volume(pin)
+      .context("Failed to open encrypted volume")
+  })
+}
+
+/// Unmount and close the encrypted volume of the given device.
+///
+/// `usb_path` is the path of the device as reported by
+/// `nitrokey::list_devices`, used to find its block devices.
+pub fn close_encrypted_volume(
+  device: &mut nitrokey::Storage<'_>,
+  usb_path: &str,
+) -> anyhow::Result<()> {
+  // Unmount the volume and flush all filesystem caches to disk. We are
+  // mostly interested in making sure that the encrypted volume on the
+  // Nitrokey we are about to close is not closed while not all data
+  // was written to it.
+  unmount_encrypted_volume(usb_path)?;
+  unsafe { sync() };
+
+  device
+    .disable_encrypted_volume()
+    .context("Failed to close encrypted volume")
+}
+
+/// Open the hidden volume of the given device matching the password
+/// retrieved from the authenticator.
+///
+/// `usb_path` is the path of the device as reported by
+/// `nitrokey::list_devices`, used to find its block devices.  An active
+/// encrypted volume is unmounted and closed.
+pub fn open_hidden_volume<A>(
+  device: &mut nitrokey::Storage<'_>,
+  usb_path: &str,
+  auth: &mut A,
+) -> anyhow::Result<()>
+where
+  A: Authenticator + ?Sized,
+{
+  let password = auth.secret(Secret::HiddenVolumePassword, None)?;
+  prepare_volume_switch(device, usb_path, |status| status.encrypted_volume.active)?;
+  device
+    .enable_hidden_volume(&password)
+    .context("Failed to open hidden volume")
+}
+
+/// Unmount and close the hidden volume of the given device.
+///
+/// `usb_path` is the path of the device as reported by
+/// `nitrokey::list_devices`, used to find its block devices.
+pub fn close_hidden_volume(
+  device: &mut nitrokey::Storage<'_>,
+  usb_path: &str,
+) -> anyhow::Result<()> {
+  unmount_encrypted_volume(usb_path)?;
+  unsafe { sync() };
+
+  device
+    .disable_hidden_volume()
+    .context("Failed to close hidden volume")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// An authenticator returning a fixed sequence of secrets.
+  struct Secrets {
+    secrets: Vec<&'static str>,
+    errors: Vec<Option<String>>,
+    retry: bool,
+  }
+
+  impl Authenticator for Secrets {
+    fn secret(&mut self, _secret: Secret, error: Option<&str>) -> anyhow::Result<String> {
+      self.errors.push(error.map(ToOwned::to_owned));
+      Ok(self.secrets.remove(0).to_string())
+    }
+
+    fn reject(&mut self, _secret: Secret) -> anyhow::Result<bool> {
+      Ok(self.retry)
+    }
+  }
+
+  fn wrong_password() -> anyhow::Error {
+    anyhow::Error::new(nitrokey::Error::CommandError(
+      nitrokey::CommandError::WrongPassword,
+    ))
+    .context("Failed to authenticate")
+  }
+
+  #[test]
+  fn retry_wrong_secret() {
+    let mut auth = Secrets {
+      secrets: vec!["1", "2", "3"],
+      errors: Vec::new(),
+      retry: true,
+    };
+    let result = try_with_secret(&mut auth, Secret::UserPin, |pin| {
+      if pin == "2" {
+        Ok(pin.to_string())
+      } else {
+        Err(wrong_password())
+      }
+    });
+    assert_eq!(result.unwrap(), "2");
+    assert_eq!(
+      auth.errors,
+      vec![None, Some("Wrong password, please reenter".to_string())]
+    );
+
+    let mut auth = Secrets {
+      secrets: vec!["1", "2", "3"],
+      errors: Vec::new(),
+      retry: true,
+    };
+    let result = try_with_secret(&mut auth, Secret::AdminPin, |_| -> anyhow::Result<()> {
+      Err(wrong_password())
+    });
+    assert_eq!(
+      result.unwrap_err().to_string(),
+      "Command error: The given password is wrong"
+    );
+    assert_eq!(auth.errors.len(), 3);
+  }
+
+  #[test]
+  fn no_retry_without_reject() {
+    let mut auth = Secrets {
+      secrets: vec!["1", "2"],
+      errors: Vec::new(),
+      retry: false,
+    };
+    let result = try_with_secret(&mut auth, Secret::UserPin, |_| -> anyhow::Result<()> {
+      Err(wrong_password())
+    });
+    assert_eq!(result.unwrap_err().to_string(), "Failed to authenticate");
+    assert_eq!(auth.errors, vec![None]);
+
+    let mut calls = 0;
+    let mut auth = |secret, _error: Option<&str>| {
+      assert_eq!(secret, Secret::HiddenVolumePassword);
+      calls += 1;
+      Ok("password".to_string())
+    };
+    let result = try_with_secret(
+      &mut auth,
+      Secret::HiddenVolumePassword,
+      |_| -> anyhow::Result<()> { anyhow::bail!("Some other error") },
+    );
+    assert_eq!(result.unwrap_err().to_string(), "Some other error");
+    assert_eq!(calls, 1);
+  }
+}
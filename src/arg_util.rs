@@ -51,11 +51,14 @@ macro_rules! Command {
 // TODO: Right now we hard code the derives we create. We may want to
 //       make this set configurable.
 macro_rules! Enum {
-  ( $(#[$docs:meta])* $name:ident, [ $( $var:ident => $str:expr, ) *] ) => {
+  ( $(#[$docs:meta])* $name:ident, [
+    $( $(#[$doc:meta])* $var:ident => $str:expr, ) *
+  ] ) => {
     $(#[$docs])*
     #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
     pub enum $name {
       $(
+        $(#[$doc])*
         $var,
       )*
     }
@@ -69,11 +72,13 @@ macro_rules! Enum {
 macro_rules! enum_int {
   ( $name:ident, [ $( $var:ident => $str:expr, ) *] ) => {
     impl $name {
+      /// Retrieve all variants of the enum.
       #[allow(unused)]
       pub fn all(&self) -> [$name; count!($($var),*) ] {
         $name::all_variants()
       }
 
+      /// Retrieve all variants of the enum.
       pub fn all_variants() -> [$name; count!($($var),*) ] {
         [
           $(
@@ -82,6 +87,7 @@ macro_rules! enum_int {
         ]
       }
 
+      /// Retrieve the textual representations of all variants.
       #[allow(unused)]
       pub fn all_str() -> [&'static str; count!($($var),*)] {
         [
@@ -4,6 +4,7 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use std::ffi::OsString;
+use std::path::PathBuf;
 
 /// Provides access to a Nitrokey device
 #[derive(Debug, clap::StructOpt)]
@@ -78,12 +79,18 @@ impl<'de> serde::Deserialize<'de> for DeviceModel {
 Command! {
   /// A top-level command for nitrocli.
   Command, [
+    /// Keeps the password safe unlocked for other invocations
+    Agent(AgentArgs) => crate::commands::agent,
     /// Reads or writes the device configuration
     Config(ConfigArgs) => |ctx, args: ConfigArgs| args.subcmd.execute(ctx),
     /// Interacts with the device's encrypted volume
     Encrypted(EncryptedArgs) => |ctx, args: EncryptedArgs| args.subcmd.execute(ctx),
+    /// Lists the available extensions
+    Extensions(ExtensionsArgs) => |ctx, args: ExtensionsArgs| args.subcmd.execute(ctx),
+    /// Interacts with the firmware of a Nitrokey Storage
+    Firmware(FirmwareArgs) => |ctx, args: FirmwareArgs| args.subcmd.execute(ctx),
     /// Fills the SD card with random data
-    Fill(FillArgs) => |ctx, args: FillArgs| crate::commands::fill(ctx, args.attach),
+    Fill(FillArgs) => crate::commands::fill,
     /// Interacts with the device's hidden volume
     Hidden(HiddenArgs) => |ctx, args: HiddenArgs| args.subcmd.execute(ctx),
     /// Lists the attached Nitrokey devices
@@ -98,8 +105,10 @@ Command! {
     Pws(PwsArgs) => |ctx, args: PwsArgs| args.subcmd.execute(ctx),
     /// Performs a factory reset
     Reset(ResetArgs) => |ctx, args: ResetArgs| crate::commands::reset(ctx, args.only_aes_key),
+    /// Inspects the nitrocli settings
+    Settings(SettingsArgs) => |ctx, args: SettingsArgs| args.subcmd.execute(ctx),
     /// Prints the status of the connected Nitrokey device
-    Status => crate::commands::status,
+    Status(StatusArgs) => crate::commands::status,
     /// Interacts with the device's unencrypted volume
     Unencrypted(UnencryptedArgs) => |ctx, args: UnencryptedArgs| args.subcmd.execute(ctx),
     /// An extension and its arguments.
@@ -108,6 +117,18 @@ Command! {
   ]
 }
 
+#[derive(Debug, PartialEq, clap::StructOpt)]
+pub struct AgentArgs {
+  /// The path of the socket to listen on [default:
+  /// $XDG_RUNTIME_DIR/nitrocli/agent.sock]
+  #[structopt(short, long, value_name = "path")]
+  pub socket: Option<PathBuf>,
+  /// The number of seconds without requests after which the device is
+  /// locked
+  #[structopt(short = 't', long, default_value = "900", value_name = "seconds")]
+  pub idle_timeout: u64,
+}
+
 #[derive(Debug, PartialEq, clap::StructOpt)]
 pub struct ConfigArgs {
   #[structopt(subcommand)]
@@ -115,12 +136,29 @@ pub struct ConfigArgs {
 }
 
 Command! {ConfigCommand, [
+  /// Exports the Nitrokey configuration as TOML
+  Export => crate::commands::config_export,
   /// Prints the Nitrokey configuration
   Get => crate::commands::config_get,
+  /// Imports a Nitrokey configuration previously exported
+  Import(ConfigImportArgs) => crate::commands::config_import,
   /// Changes the Nitrokey configuration
   Set(ConfigSetArgs) => crate::commands::config_set,
 ]}
 
+#[derive(Debug, PartialEq, clap::StructOpt)]
+pub struct ConfigImportArgs {
+  /// Only shows the changes that would be made without applying them
+  #[structopt(short, long)]
+  pub dry_run: bool,
+  /// Applies the changes without asking for confirmation
+  #[structopt(short, long, conflicts_with("dry-run"))]
+  pub yes: bool,
+  /// The file to import the configuration from or - to read it from
+  /// stdin
+  pub file: String,
+}
+
 #[derive(Debug, PartialEq, clap::StructOpt)]
 pub struct ConfigSetArgs {
   /// Sets the Num Lock option to the given HOTP slot
@@ -192,7 +230,30 @@ Command! {EncryptedCommand, [
   /// Closes the encrypted volume on a Nitrokey Storage
   Close => crate::commands::encrypted_close,
   /// Opens the encrypted volume on a Nitrokey Storage
-  Open => crate::commands::encrypted_open,
+  Open(VolumeOpenArgs) => |ctx, args: VolumeOpenArgs| {
+    crate::commands::encrypted_open(ctx, args.mount)
+  },
+]}
+
+#[derive(Debug, PartialEq, clap::StructOpt)]
+pub struct VolumeOpenArgs {
+  /// Mounts the volume after opening it, optionally at the given
+  /// directory [default: chosen by udisks]
+  #[structopt(long, require_equals = true, value_name = "dir")]
+  pub mount: Option<Option<PathBuf>>,
+}
+
+#[derive(Debug, PartialEq, clap::StructOpt)]
+pub struct FirmwareArgs {
+  #[structopt(subcommand)]
+  subcmd: FirmwareCommand,
+}
+
+Command! {FirmwareCommand, [
+  /// Prints whether the firmware of a Nitrokey Storage is locked
+  Status => crate::commands::firmware_status,
+  /// Puts a Nitrokey Storage into firmware update mode
+  UpdateMode => crate::commands::firmware_update_mode,
 ]}
 
 #[derive(Debug, PartialEq, clap::StructOpt)]
@@ -200,7 +261,13 @@ pub struct FillArgs {
   /// Checks if a fill operation is already running and show its progress instead of starting a new
   /// operation.
   #[structopt(short, long)]
-  attach: bool,
+  pub attach: bool,
+  /// Starts the fill operation and exits without waiting for it to finish
+  #[structopt(short, long, conflicts_with("attach"))]
+  pub detach: bool,
+  /// Runs the given shell command once the fill operation finished
+  #[structopt(long, conflicts_with("detach"), value_name = "command")]
+  pub notify_command: Option<String>,
 }
 
 #[derive(Debug, PartialEq, clap::StructOpt)]
@@ -217,7 +284,13 @@ Command! {HiddenCommand, [
     crate::commands::hidden_create(ctx, args.slot, args.start, args.end)
   },
   /// Opens the hidden volume on a Nitrokey Storage
-  Open => crate::commands::hidden_open,
+  Open(VolumeOpenArgs) => |ctx, args: VolumeOpenArgs| {
+    crate::commands::hidden_open(ctx, args.mount)
+  },
+  /// Plans the location of a hidden volume on a Nitrokey Storage
+  Plan(HiddenPlanArgs) => |ctx, args: HiddenPlanArgs| {
+    crate::commands::hidden_plan(ctx, args.slot, args.start, args.size)
+  },
 ]}
 
 #[derive(Debug, PartialEq, clap::StructOpt)]
@@ -230,6 +303,17 @@ pub struct HiddenCreateArgs {
   pub end: u8,
 }
 
+#[derive(Debug, PartialEq, clap::StructOpt)]
+pub struct HiddenPlanArgs {
+  /// The hidden volume slot to plan
+  pub slot: u8,
+  /// The start location of the hidden volume as a percentage (e.g., 10%) or as an offset in MiB or
+  /// GiB (e.g., 512MiB)
+  pub start: crate::layout::Size,
+  /// The size of the hidden volume as a percentage (e.g., 20%) or in MiB or GiB (e.g., 2GiB)
+  pub size: crate::layout::Size,
+}
+
 #[derive(Debug, PartialEq, clap::StructOpt)]
 pub struct ListArgs {
   /// Only print the information that is available without connecting to a device
@@ -249,13 +333,15 @@ Command! {OtpCommand, [
     crate::commands::otp_clear(ctx, args.slot, args.algorithm)
   },
   /// Generates a one-time password
-  Get(OtpGetArgs) => |ctx, args: OtpGetArgs| {
-    crate::commands::otp_get(ctx, args.slot, args.algorithm, args.time)
-  },
+  Get(OtpGetArgs) => crate::commands::otp_get,
   /// Configures a one-time password slot
   Set(OtpSetArgs) => crate::commands::otp_set,
   /// Prints the status of the one-time password slots
   Status(OtpStatusArgs) => |ctx, args: OtpStatusArgs| crate::commands::otp_status(ctx, args.all),
+  /// Checks the device time against the system time and updates it
+  Time(OtpTimeArgs) => |ctx, args: OtpTimeArgs| crate::commands::otp_time(ctx, args.force),
+  /// Verifies that a one-time password slot matches a secret
+  Verify(OtpVerifyArgs) => crate::commands::otp_verify,
 ]}
 
 #[derive(Debug, PartialEq, clap::StructOpt)]
@@ -274,9 +360,30 @@ pub struct OtpGetArgs {
   #[structopt(short, long, default_value = OtpAlgorithm::Totp.as_ref(),
               possible_values = OtpAlgorithm::all_str())]
   pub algorithm: OtpAlgorithm,
-  /// The time to use for TOTP generation (Unix timestamp) [default: system time]
-  #[structopt(short, long)]
-  pub time: Option<u64>,
+  /// The time to use for TOTP generation (Unix timestamp, RFC 3339 date,
+  /// or offset relative to the current time such as +30s or -1w)
+  /// [default: system time]
+  #[structopt(short, long, allow_hyphen_values = true)]
+  pub time: Option<crate::timestamp::Timestamp>,
+  /// Generates the TOTP for the start of the time window following the
+  /// given time
+  #[structopt(long)]
+  pub at_next_window: bool,
+  /// The time window of the TOTP slot, in seconds; the device does not
+  /// report it, so it has to match the value the slot was configured
+  /// with and only affects the reported validity
+  #[structopt(short = 'w', long, default_value = "30")]
+  pub time_window: u16,
+  /// Waits for the next time window if the TOTP would be valid for less
+  /// than the given number of seconds
+  #[structopt(long, value_name = "seconds", conflicts_with_all = &["time", "at-next-window"])]
+  pub wait_min: Option<u64>,
+  /// Prints a fresh TOTP along with a countdown for every time window
+  #[structopt(long, conflicts_with_all = &["time", "at-next-window", "wait-min"])]
+  pub watch: bool,
+  /// Prints the period for which the TOTP is valid
+  #[structopt(long, conflicts_with = "watch")]
+  pub validity: bool,
   /// The OTP slot to use
   pub slot: u8,
 }
@@ -317,8 +424,48 @@ pub struct OtpStatusArgs {
   pub all: bool,
 }
 
-Enum! {OtpAlgorithm, [
+#[derive(Debug, PartialEq, clap::StructOpt)]
+pub struct OtpVerifyArgs {
+  /// The OTP algorithm to use
+  #[structopt(short, long, default_value = OtpAlgorithm::Totp.as_ref(),
+              possible_values = OtpAlgorithm::all_str())]
+  pub algorithm: OtpAlgorithm,
+  /// The lowest HOTP counter value to consider
+  #[structopt(short, long, default_value = "0")]
+  pub counter: u64,
+  /// The time window of the TOTP slot, in seconds
+  #[structopt(short = 'w', long, default_value = "30")]
+  pub time_window: u16,
+  /// The format of the secret
+  #[structopt(short, long, default_value = OtpSecretFormat::Base32.as_ref(),
+              possible_values = OtpSecretFormat::all_str())]
+  pub format: OtpSecretFormat,
+  /// The secret the slot is expected to be configured with as a base32
+  /// encoded string (or in the format set with the --format option)
+  #[structopt(short, long)]
+  pub secret: String,
+  /// Advances the HOTP counter of the slot without asking for
+  /// confirmation
+  #[structopt(short, long)]
+  pub yes: bool,
+  /// The OTP slot to verify
+  pub slot: u8,
+}
+
+#[derive(Debug, PartialEq, clap::StructOpt)]
+pub struct OtpTimeArgs {
+  /// Sets the device time to the system time even if that moves it
+  /// backwards
+  #[structopt(short, long)]
+  pub force: bool,
+}
+
+Enum! {
+  /// An algorithm for generating one-time passwords.
+  OtpAlgorithm, [
+  /// The HMAC-based one-time password algorithm (RFC 4226).
   Hotp => "hotp",
+  /// The time-based one-time password algorithm (RFC 6238).
   Totp => "totp",
 ]}
 
@@ -368,11 +515,23 @@ Enum! {
   ]
 }
 
+Enum! {
+  /// PIN type that can be changed using the `pin set` command.
+  ///
+  /// In addition to the PIN types of `PinType`, this includes the
+  /// update PIN of the Nitrokey Storage.
+  PinSetType, [
+    Admin => "admin",
+    Update => "update",
+    User => "user",
+  ]
+}
+
 #[derive(Debug, PartialEq, clap::StructOpt)]
 pub struct PinSetArgs {
   /// The PIN type to change
-  #[structopt(name = "type", possible_values = PinType::all_str())]
-  pub pintype: PinType,
+  #[structopt(name = "type", possible_values = PinSetType::all_str())]
+  pub pintype: PinSetType,
 }
 
 #[derive(Debug, PartialEq, clap::StructOpt)]
@@ -474,6 +633,88 @@ pub struct ResetArgs {
   pub only_aes_key: bool,
 }
 
+#[derive(Debug, PartialEq, clap::StructOpt)]
+pub struct ExtensionsArgs {
+  #[structopt(subcommand)]
+  subcmd: ExtensionsCommand,
+}
+
+Command! {ExtensionsCommand, [
+  /// Lists the available extensions along with their descriptions
+  List => crate::commands::extensions_list,
+]}
+
+#[derive(Debug, PartialEq, clap::StructOpt)]
+pub struct SettingsArgs {
+  #[structopt(subcommand)]
+  subcmd: SettingsCommand,
+}
+
+Command! {SettingsCommand, [
+  /// Checks a configuration file for errors and unknown keys
+  Check(SettingsCheckArgs) => |ctx, args: SettingsCheckArgs| {
+    crate::commands::settings_check(ctx, args.file)
+  },
+  /// Prints the effective settings and where they came from
+  Show => crate::commands::settings_show,
+]}
+
+#[derive(Debug, PartialEq, clap::StructOpt)]
+pub struct SettingsCheckArgs {
+  /// The configuration file to check [default: the user configuration file]
+  #[structopt(parse(from_os_str))]
+  pub file: Option<PathBuf>,
+}
+
+#[derive(Debug, PartialEq, clap::StructOpt)]
+pub struct StatusArgs {
+  /// Checks the status against thresholds and reports the result in a
+  /// single line, exiting with a Nagios-style exit code
+  #[structopt(short, long)]
+  pub check: bool,
+  /// The minimum user PIN retry count not to report a warning [default: 3]
+  #[structopt(long, requires("check"))]
+  pub min_user_retries: Option<u8>,
+  /// The minimum admin PIN retry count not to report a warning [default: 3]
+  #[structopt(long, requires("check"))]
+  pub min_admin_retries: Option<u8>,
+  /// The expected firmware state of a Nitrokey Storage
+  #[structopt(long, requires("check"), possible_values = FirmwareState::all_str())]
+  pub firmware: Option<FirmwareState>,
+  /// Requires the storage keys of a Nitrokey Storage to be created
+  #[structopt(long, requires("check"))]
+  pub require_stick_initialized: bool,
+  /// Requires the SD card of a Nitrokey Storage to be filled with
+  /// random data
+  #[structopt(long, requires("check"))]
+  pub require_sd_filled: bool,
+  /// The format to print the status in
+  #[structopt(short, long, default_value = StatusFormat::Text.as_ref(),
+              possible_values = StatusFormat::all_str(), conflicts_with("check"))]
+  pub format: StatusFormat,
+  /// Writes the status to the given file instead of stdout, replacing
+  /// the file atomically
+  #[structopt(short, long, conflicts_with("check"))]
+  pub output: Option<PathBuf>,
+  /// Includes the production information of a Nitrokey Storage
+  #[structopt(short, long, conflicts_with("check"))]
+  pub production_info: bool,
+  /// Includes the number of programmed PWS slots in the Prometheus
+  /// output, using the user PIN from NITROCLI_USER_PIN
+  #[structopt(long, conflicts_with("check"))]
+  pub count_pws: bool,
+}
+
+Enum! {StatusFormat, [
+  Prometheus => "prometheus",
+  Text => "text",
+]}
+
+Enum! {FirmwareState, [
+  Locked => "locked",
+  Unlocked => "unlocked",
+]}
+
 #[derive(Debug, PartialEq, clap::StructOpt)]
 pub struct UnencryptedArgs {
   #[structopt(subcommand)]
@@ -482,9 +723,7 @@ pub struct UnencryptedArgs {
 
 Command! {UnencryptedCommand, [
   /// Changes the configuration of the unencrypted volume on a Nitrokey Storage
-  Set(UnencryptedSetArgs) => |ctx, args: UnencryptedSetArgs| {
-    crate::commands::unencrypted_set(ctx, args.mode)
-  },
+  Set(UnencryptedSetArgs) => crate::commands::unencrypted_set,
 ]}
 
 #[derive(Debug, PartialEq, clap::StructOpt)]
@@ -492,9 +731,43 @@ pub struct UnencryptedSetArgs {
   /// The mode to change to
   #[structopt(name = "type", possible_values = UnencryptedVolumeMode::all_str())]
   pub mode: UnencryptedVolumeMode,
+  /// The number of seconds to wait for the device to reconnect in the
+  /// new mode
+  #[structopt(short, long, default_value = "30", value_name = "seconds")]
+  pub timeout: u64,
+  /// Unmounts the volume before changing the mode and mounts it again
+  /// afterwards
+  #[structopt(short, long)]
+  pub remount: bool,
 }
 
-Enum! {UnencryptedVolumeMode, [
+Enum! {
+  /// The mode of the unencrypted volume of a Nitrokey Storage.
+  UnencryptedVolumeMode, [
+  /// The volume can be read and written.
   ReadWrite => "read-write",
+  /// The volume can only be read.
   ReadOnly => "read-only",
 ]}
+
+impl<'de> serde::Deserialize<'de> for UnencryptedVolumeMode {
+  fn deserialize<D>(deserializer: D) -> Result<UnencryptedVolumeMode, D::Error>
+  where
+    D: serde::Deserializer<'de>,
+  {
+    use serde::de::Error as _;
+    use std::str::FromStr as _;
+
+    let s = String::deserialize(deserializer)?;
+    UnencryptedVolumeMode::from_str(&s).map_err(D::Error::custom)
+  }
+}
+
+impl serde::Serialize for UnencryptedVolumeMode {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: serde::Serializer,
+  {
+    serializer.serialize_str(self.as_ref())
+  }
+}
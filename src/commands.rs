@@ -10,8 +10,14 @@ use std::ffi;
 use std::fmt;
 use std::fs;
 use std::io;
+use std::io::BufRead as _;
+use std::io::Write as _;
 use std::ops;
 use std::ops::Deref as _;
+use std::os::unix::fs::PermissionsExt as _;
+use std::os::unix::io::AsRawFd as _;
+use std::os::unix::net;
+use std::os::unix::process::CommandExt as _;
 use std::path;
 use std::process;
 use std::thread;
@@ -24,22 +30,25 @@ use libc::sync;
 use nitrokey::ConfigureOtp;
 use nitrokey::Device;
 use nitrokey::GenerateOtp;
-use nitrokey::GetPasswordSafe;
 
+use crate::agent;
+use crate::api;
+use crate::api::PasswordStore as _;
 use crate::args;
 use crate::config;
+use crate::extensions;
+use crate::layout;
+use crate::otp;
 use crate::output;
 use crate::pinentry;
+use crate::volume;
 use crate::Context;
 
 const NITROCLI_EXT_PREFIX: &str = "nitrocli-";
+const EXTENSION_MANIFEST_SUFFIX: &str = ".toml";
 
 const OTP_NAME_LENGTH: usize = 15;
 
-const PWS_NAME_LENGTH: usize = 11;
-const PWS_LOGIN_LENGTH: usize = 32;
-const PWS_PASSWORD_LENGTH: usize = 20;
-
 /// Set `libnitrokey`'s log level based on the execution context's verbosity.
 fn set_log_level(ctx: &mut Context<'_>) {
   let log_lvl = match ctx.config.verbosity {
@@ -126,9 +135,9 @@ fn connect<'mgr>(
 }
 
 /// Connect to any Nitrokey device and do something with it.
-fn with_device<F>(ctx: &mut Context<'_>, op: F) -> anyhow::Result<()>
+fn with_device<F, R>(ctx: &mut Context<'_>, op: F) -> anyhow::Result<R>
 where
-  F: FnOnce(&mut Context<'_>, nitrokey::DeviceWrapper<'_>) -> anyhow::Result<()>,
+  F: FnOnce(&mut Context<'_>, nitrokey::DeviceWrapper<'_>) -> anyhow::Result<R>,
 {
   let mut manager =
     nitrokey::take().context("Failed to acquire access to Nitrokey device manager")?;
@@ -140,9 +149,9 @@ where
 }
 
 /// Connect to a Nitrokey Storage device and do something with it.
-fn with_storage_device<F>(ctx: &mut Context<'_>, op: F) -> anyhow::Result<()>
+fn with_storage_device<F, R>(ctx: &mut Context<'_>, op: F) -> anyhow::Result<R>
 where
-  F: FnOnce(&mut Context<'_>, nitrokey::Storage<'_>) -> anyhow::Result<()>,
+  F: FnOnce(&mut Context<'_>, nitrokey::Storage<'_>) -> anyhow::Result<R>,
 {
   let mut manager =
     nitrokey::take().context("Failed to acquire access to Nitrokey device manager")?;
@@ -165,41 +174,74 @@ where
   }
 }
 
-/// Connect to any Nitrokey device, retrieve a password safe handle, and
-/// do something with it.
-fn with_password_safe<F>(ctx: &mut Context<'_>, mut op: F) -> anyhow::Result<()>
+/// Determine the USB path of the device selected by the configuration,
+/// e.g., to find the block devices of a Nitrokey Storage.
+fn usb_path(ctx: &Context<'_>) -> anyhow::Result<String> {
+  find_device(&ctx.config).map(|device_info| device_info.path)
+}
+
+/// Create a client for the nitrocli agent set in the environment, if
+/// any, making sure that it serves the selected device.
+fn agent_client(ctx: &Context<'_>) -> anyhow::Result<Option<agent::Client>> {
+  let socket = match &ctx.agent_socket {
+    Some(socket) => socket,
+    None => return Ok(None),
+  };
+  let client = agent::Client::new(socket);
+  let config = &ctx.config;
+  if config.model.is_some() || !config.serial_numbers.is_empty() || config.usb_path.is_some() {
+    let device = client.device()?;
+    anyhow::ensure!(
+      config.model.map_or(true, |model| model == device.model)
+        && (config.serial_numbers.is_empty()
+          || config.serial_numbers.contains(&device.serial_number))
+        && config
+          .usb_path
+          .as_ref()
+          .map_or(true, |path| path == &device.usb_path),
+      "The nitrocli agent serves the Nitrokey {} with serial number {}, which does not match \
+      the selected device{}.  Unset {} to use the device directly",
+      device.model.as_ref(),
+      device.serial_number,
+      format_filter(config),
+      crate::NITROCLI_AGENT_SOCK,
+    );
+  }
+  Ok(Some(client))
+}
+
+/// Connect to any Nitrokey device, unlock its password safe, and do
+/// something with it.
+///
+/// If the socket of a nitrocli agent is set in the environment, the
+/// password safe kept unlocked by the agent is used instead.
+fn with_password_safe<F>(ctx: &mut Context<'_>, op: F) -> anyhow::Result<()>
 where
-  F: FnMut(&mut Context<'_>, nitrokey::PasswordSafe<'_, '_>) -> anyhow::Result<()>,
+  F: FnOnce(&mut Context<'_>, &mut dyn api::PasswordStore) -> anyhow::Result<()>,
 {
-  with_device(ctx, |ctx, mut device| {
-    let pin_entry = pinentry::PinEntry::from(args::PinType::User, &device)?;
-    try_with_pin_and_data(ctx, &pin_entry, (), move |ctx, _, pin| {
-      let pws = device.get_password_safe(pin).or_else(|err| {
-        Err(err)
-          .context("Could not access the password safe")
-          .map_err(|err| ((), err))
-      })?;
+  if let Some(mut client) = agent_client(ctx)? {
+    return op(ctx, &mut client);
+  }
 
-      op(ctx, pws).map_err(|err| ((), err))
-    })
-  })?;
-  Ok(())
+  with_device(ctx, |ctx, mut device| {
+    let mut auth = pinentry::Authenticator::from(ctx, &device)?;
+    api::with_password_safe(&mut device, &mut auth, |pws| op(ctx, pws))
+  })
 }
 
-/// Authenticate the given device using the given PIN type and operation.
+/// Authenticate the given device using the given secret and operation.
 fn authenticate<'mgr, D, A, F>(
   ctx: &mut Context<'_>,
   device: D,
-  pin_type: args::PinType,
+  secret: api::Secret,
   op: F,
 ) -> anyhow::Result<A>
 where
   D: Device<'mgr>,
-  F: FnMut(&mut Context<'_>, D, &str) -> Result<A, (D, anyhow::Error)>,
+  F: FnMut(D, &str) -> Result<A, (D, anyhow::Error)>,
 {
-  let pin_entry = pinentry::PinEntry::from(pin_type, &device)?;
-
-  try_with_pin_and_data(ctx, &pin_entry, device, op)
+  let mut auth = pinentry::Authenticator::from(ctx, &device)?;
+  api::try_with_secret_and_data(&mut auth, secret, device, op)
 }
 
 /// Authenticate the given device with the user PIN.
@@ -210,7 +252,7 @@ fn authenticate_user<'mgr, T>(
 where
   T: Device<'mgr>,
 {
-  authenticate(ctx, device, args::PinType::User, |_ctx, device, pin| {
+  authenticate(ctx, device, api::Secret::UserPin, |device, pin| {
     device.authenticate_user(pin).or_else(|(x, err)| {
       Err(err)
         .context("Failed to authenticate as user")
@@ -227,7 +269,7 @@ fn authenticate_admin<'mgr, T>(
 where
   T: Device<'mgr>,
 {
-  authenticate(ctx, device, args::PinType::Admin, |_ctx, device, pin| {
+  authenticate(ctx, device, api::Secret::AdminPin, |device, pin| {
     device.authenticate_admin(pin).or_else(|(x, err)| {
       Err(err)
         .context("Failed to authenticate as admin")
@@ -249,110 +291,29 @@ fn get_volume_status(status: &nitrokey::VolumeStatus) -> &'static str {
   }
 }
 
-/// Try to execute the given function with a pin queried using pinentry.
-///
-/// This function will query the pin of the given type from the user
-/// using pinentry.  It will then execute the given function.  If this
-/// function returns a result, the result will be passed on.  If it
-/// returns a `CommandError::WrongPassword`, the user will be asked
-/// again to enter the pin.  Otherwise, this function returns an error
-/// containing the given error message.  The user will have at most
-/// three tries to get the pin right.
-///
-/// The data argument can be used to pass on data between the tries.  At
-/// the first try, this function will call `op` with `data`.  At the
-/// second or third try, it will call `op` with the data returned by the
-/// previous call to `op`.
-fn try_with_pin_and_data_with_pinentry<D, F, R>(
-  ctx: &mut Context<'_>,
-  pin_entry: &pinentry::PinEntry,
-  data: D,
-  mut op: F,
-) -> anyhow::Result<R>
-where
-  F: FnMut(&mut Context<'_>, D, &str) -> Result<R, (D, anyhow::Error)>,
-{
-  let mut data = data;
-  let mut retry = 3;
-  let mut error_msg = None;
-  loop {
-    let pin = pinentry::inquire(ctx, pin_entry, pinentry::Mode::Query, error_msg)?;
-    match op(ctx, data, &pin) {
-      Ok(result) => return Ok(result),
-      Err((new_data, err)) => match err.downcast::<nitrokey::Error>() {
-        Ok(err) => match err {
-          nitrokey::Error::CommandError(nitrokey::CommandError::WrongPassword) => {
-            pinentry::clear(pin_entry).context("Failed to clear cached secret")?;
-            retry -= 1;
-
-            if retry > 0 {
-              error_msg = Some("Wrong password, please reenter");
-              data = new_data;
-              continue;
-            }
-            anyhow::bail!(err);
-          }
-          err => anyhow::bail!(err),
-        },
-        Err(err) => anyhow::bail!(err),
-      },
-    };
-  }
-}
-
-/// Try to execute the given function with a PIN.
-fn try_with_pin_and_data<D, F, R>(
-  ctx: &mut Context<'_>,
-  pin_entry: &pinentry::PinEntry,
-  data: D,
-  mut op: F,
-) -> anyhow::Result<R>
-where
-  F: FnMut(&mut Context<'_>, D, &str) -> Result<R, (D, anyhow::Error)>,
-{
-  let pin = match pin_entry.pin_type() {
-    // Ideally we would not clone here, but that would require us to
-    // restrict op to work with an immutable Context, which is not
-    // possible given that some clients print data.
-    args::PinType::Admin => ctx.admin_pin.clone(),
-    args::PinType::User => ctx.user_pin.clone(),
-  };
-
-  if let Some(pin) = pin {
-    let pin = pin
-      .to_str()
-      .context("Failed to read PIN: Invalid Unicode data found")?;
-    op(ctx, data, pin).map_err(|(_, err)| err)
-  } else {
-    try_with_pin_and_data_with_pinentry(ctx, pin_entry, data, op)
-  }
-}
+/// Ask the user to confirm an operation by entering the given text.
+fn confirm(ctx: &mut Context<'_>, prompt: &str, expected: &str) -> anyhow::Result<()> {
+  write!(ctx.stderr, "{}: ", prompt)?;
+  ctx.stderr.flush()?;
 
-/// Try to execute the given function with a pin queried using pinentry.
-///
-/// This function behaves exactly as `try_with_pin_and_data`, but
-/// it refrains from passing any data to it.
-fn try_with_pin<F>(
-  ctx: &mut Context<'_>,
-  pin_entry: &pinentry::PinEntry,
-  mut op: F,
-) -> anyhow::Result<()>
-where
-  F: FnMut(&str) -> anyhow::Result<()>,
-{
-  try_with_pin_and_data(ctx, pin_entry, (), |_ctx, data, pin| {
-    op(pin).map_err(|err| (data, err))
-  })
+  let mut line = String::new();
+  let _ = io::BufRead::read_line(&mut io::BufReader::new(&mut ctx.stdin), &mut line)
+    .context("Failed to read confirmation from stdin")?;
+  anyhow::ensure!(
+    line.trim() == expected,
+    "Confirmation failed, aborting operation"
+  );
+  Ok(())
 }
 
 /// Pretty print the status of a Nitrokey Storage.
 fn print_storage_status(
-  ctx: &mut Context<'_>,
+  out: &mut dyn io::Write,
   status: &nitrokey::StorageStatus,
   sd_card_usage: &ops::Range<u8>,
 ) -> anyhow::Result<()> {
-  println!(
-    ctx,
+  writeln!(
+    out,
     r#"  Storage:
     SD card ID:        {id:#x}
     SD card usage:     {usagestart}% .. {usageend}% not written
@@ -432,81 +393,454 @@ fn ensure_string_lengths(data: &[(&str, &str, usize)]) -> anyhow::Result<()> {
   }
 }
 
-/// Pretty print the status that is common to all Nitrokey devices.
-fn print_status(
-  ctx: &mut Context<'_>,
-  model: nitrokey::Model,
-  serial_number: nitrokey::SerialNumber,
-  firmware_version: nitrokey::FirmwareVersion,
-  user_retry_count: u8,
-  admin_retry_count: u8,
+/// Format the manufacturing date of an SD card.
+fn format_manufacturing_date(sd_card: &nitrokey::SdCardData) -> String {
+  format!(
+    "{}-{:02}",
+    2000 + u32::from(sd_card.manufacturing_year),
+    sd_card.manufacturing_month
+  )
+}
+
+/// Pretty print the production information of a Nitrokey Storage.
+fn print_production_info(
+  out: &mut dyn io::Write,
+  info: &nitrokey::StorageProductionInfo,
 ) -> anyhow::Result<()> {
-  println!(
-    ctx,
+  writeln!(
+    out,
+    r#"    production info:
+      firmware version:  {fwv} (internal {fwvi})
+      CPU ID:            {cpu:#010x}
+      SD card:
+        size:            {size} GB
+        manufacturer:    {manufacturer:#04x}
+        OEM:             {oem:#06x}
+        manufactured:    {date}"#,
+    fwv = info.firmware_version,
+    fwvi = info.firmware_version_internal,
+    cpu = info.serial_number_cpu,
+    size = info.sd_card.size,
+    manufacturer = info.sd_card.manufacturer,
+    oem = info.sd_card.oem,
+    date = format_manufacturing_date(&info.sd_card),
+  )?;
+  Ok(())
+}
+
+/// Pretty print the status that is common to all Nitrokey devices.
+fn print_status(out: &mut dyn io::Write, status: &api::Status) -> anyhow::Result<()> {
+  writeln!(
+    out,
     r#"Status:
   model:             {model}
   serial number:     {id}
   firmware version:  {fwv}
   user retry count:  {urc}
   admin retry count: {arc}"#,
-    model = model,
-    id = serial_number,
-    fwv = firmware_version,
-    urc = user_retry_count,
-    arc = admin_retry_count,
+    model = status.model,
+    id = status.serial_number,
+    fwv = status.firmware_version,
+    urc = status.user_retry_count,
+    arc = status.admin_retry_count,
   )?;
 
   Ok(())
 }
 
-/// Inquire the status of the nitrokey.
-pub fn status(ctx: &mut Context<'_>) -> anyhow::Result<()> {
-  with_device(ctx, |ctx, device| {
-    if let nitrokey::DeviceWrapper::Storage(device) = device {
-      // TODO: Extract serial number from storage status, see
-      //       https://todo.sr.ht/~ireas/nitrokey-rs/1
-      let serial_number = device
-        .get_serial_number()
-        .context("Failed to retrieve serial number")?;
-      let status = device
-        .get_storage_status()
-        .context("Failed to retrieve storage status")?;
-
-      print_status(
-        ctx,
-        device.get_model(),
-        serial_number,
-        status.firmware_version,
-        status.user_retry_count,
-        status.admin_retry_count,
-      )?;
+/// The state of a status check, mirroring the exit codes used by
+/// Nagios plugins.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum CheckState {
+  Ok = 0,
+  Warning = 1,
+  Critical = 2,
+  Unknown = 3,
+}
 
-      let sd_card_usage = device
-        .get_sd_card_usage()
-        .context("Failed to retrieve SD card usage")?;
-      print_storage_status(ctx, &status, &sd_card_usage)
-    } else {
-      let status = device
-        .get_status()
-        .context("Could not query the device status")?;
-      let user_retry_count = device
-        .get_user_retry_count()
-        .context("Failed to retrieve user retry count")?;
-      let admin_retry_count = device
-        .get_admin_retry_count()
-        .context("Failed to retrieve admin retry count")?;
-      print_status(
-        ctx,
-        device.get_model(),
-        status.serial_number,
-        status.firmware_version,
-        user_retry_count,
-        admin_retry_count,
-      )
+impl fmt::Display for CheckState {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let state = match self {
+      CheckState::Ok => "OK",
+      CheckState::Warning => "WARNING",
+      CheckState::Critical => "CRITICAL",
+      CheckState::Unknown => "UNKNOWN",
+    };
+    f.write_str(state)
+  }
+}
+
+/// Check a PIN retry count against the given minimum.
+fn check_retry_count(problems: &mut Vec<(CheckState, String)>, pin: &str, count: u8, min: u8) {
+  if count == 0 {
+    problems.push((CheckState::Critical, format!("{} PIN blocked", pin)));
+  } else if count < min {
+    problems.push((
+      CheckState::Warning,
+      format!("{} retry count {} below {}", pin, count, min),
+    ));
+  }
+}
+
+/// Check the given status against the thresholds set in the arguments.
+fn check_status(status: &api::Status, args: &args::StatusArgs) -> (CheckState, String) {
+  let mut problems = Vec::new();
+  check_retry_count(
+    &mut problems,
+    "user",
+    status.user_retry_count,
+    args.min_user_retries.unwrap_or(3),
+  );
+  check_retry_count(
+    &mut problems,
+    "admin",
+    status.admin_retry_count,
+    args.min_admin_retries.unwrap_or(3),
+  );
+
+  if let Some((storage, _)) = &status.storage {
+    if let Some(firmware) = args.firmware {
+      let locked = firmware == args::FirmwareState::Locked;
+      if storage.firmware_locked != locked {
+        problems.push((CheckState::Critical, format!("firmware not {}", firmware)));
+      }
+    }
+    if args.require_stick_initialized && !storage.stick_initialized {
+      problems.push((CheckState::Critical, "storage keys not created".to_string()));
+    }
+    if args.require_sd_filled && !storage.filled_with_random {
+      problems.push((
+        CheckState::Warning,
+        "SD card not filled with random data".to_string(),
+      ));
     }
+  }
+
+  let state = problems
+    .iter()
+    .map(|(state, _)| *state)
+    .max()
+    .unwrap_or(CheckState::Ok);
+  let details = if problems.is_empty() {
+    "all checks passed".to_string()
+  } else {
+    problems
+      .into_iter()
+      .map(|(_, problem)| problem)
+      .collect::<Vec<_>>()
+      .join(", ")
+  };
+  let summary = format!(
+    "{} {}: {} | user_retry_count={} admin_retry_count={}",
+    status.model, status.serial_number, details, status.user_retry_count, status.admin_retry_count,
+  );
+  (state, summary)
+}
+
+/// Check the status of the nitrokey and report the result in the
+/// format used by Nagios plugins.
+fn status_check(ctx: &mut Context<'_>, args: &args::StatusArgs) -> anyhow::Result<()> {
+  let (state, summary) = match with_device(ctx, |_ctx, device| api::status(&device)) {
+    Ok(status) => check_status(&status, args),
+    Err(err) => (CheckState::Unknown, format!("{:#}", err)),
+  };
+
+  println!(ctx, "NITROKEY {} - {}", state, summary)?;
+  if state == CheckState::Ok {
+    Ok(())
+  } else {
+    Err(anyhow::Error::new(crate::DirectExitError(state as i32)))
+  }
+}
+
+/// The number of programmed slots of a Nitrokey device.
+struct SlotCounts {
+  hotp: usize,
+  totp: usize,
+  /// The number of programmed PWS slots, if requested.
+  pws: Option<usize>,
+}
+
+/// Count the programmed slots of the given device.
+///
+/// Querying the password safe requires the user PIN, so the PWS slots
+/// are only counted if `count_pws` is set, using the PIN from the
+/// environment.  We never prompt for it, as the Prometheus output is
+/// meant to be generated unattended.  For the same reason, we do not
+/// even try the PIN if a previous attempt failed: a stale PIN would
+/// otherwise lock the device after a few runs.
+fn get_slot_counts(
+  ctx: &mut Context<'_>,
+  device: &mut nitrokey::DeviceWrapper<'_>,
+  status: &api::Status,
+  count_pws: bool,
+) -> anyhow::Result<SlotCounts> {
+  let hotp = api::otp_slot_names(device, args::OtpAlgorithm::Hotp)?;
+  let totp = api::otp_slot_names(device, args::OtpAlgorithm::Totp)?;
+  let pws = if count_pws {
+    let pin = ctx
+      .user_pin
+      .as_ref()
+      .context("Counting the PWS slots requires the user PIN to be set in NITROCLI_USER_PIN")?
+      .to_str()
+      .context("Failed to read PIN: Invalid Unicode data found")?;
+    anyhow::ensure!(
+      status.user_retry_count == api::PIN_TRIES,
+      "Refusing to count the PWS slots because the user PIN retry count is {}",
+      status.user_retry_count
+    );
+    let mut auth = |_secret, _error: Option<&str>| Ok(pin.to_string());
+    let names = api::with_password_safe(device, &mut auth, |pws| pws.slot_names())?;
+    Some(names.iter().filter(|name| name.is_some()).count())
+  } else {
+    None
+  };
+
+  Ok(SlotCounts {
+    hotp: hotp.iter().filter(|name| name.is_some()).count(),
+    totp: totp.iter().filter(|name| name.is_some()).count(),
+    pws,
   })
 }
 
+/// Print a gauge in the Prometheus text exposition format.
+fn print_gauge(
+  out: &mut dyn io::Write,
+  name: &str,
+  help: &str,
+  labels: &str,
+  values: &[(&str, u64)],
+) -> anyhow::Result<()> {
+  writeln!(out, "# HELP nitrokey_{} {}", name, help)?;
+  writeln!(out, "# TYPE nitrokey_{} gauge", name)?;
+  for (extra_labels, value) in values {
+    writeln!(
+      out,
+      "nitrokey_{}{{{}{}}} {}",
+      name, labels, extra_labels, value
+    )?;
+  }
+  Ok(())
+}
+
+/// Print the status of a Nitrokey device in the Prometheus text
+/// exposition format, as understood by the node exporter's textfile
+/// collector.
+fn print_status_prometheus(
+  out: &mut dyn io::Write,
+  status: &api::Status,
+  slots: &SlotCounts,
+) -> anyhow::Result<()> {
+  let model = args::DeviceModel::try_from(status.model)
+    .map(|model| model.to_string())
+    .unwrap_or_else(|_| status.model.to_string());
+  let labels = format!(
+    r#"model="{}",serial_number="{}""#,
+    model, status.serial_number
+  );
+  let version = format!(r#",version="{}""#, status.firmware_version);
+
+  print_gauge(
+    out,
+    "firmware_version_info",
+    "The firmware version of the device.",
+    &labels,
+    &[(&version, 1)],
+  )?;
+  print_gauge(
+    out,
+    "user_retry_count",
+    "The number of remaining user PIN attempts.",
+    &labels,
+    &[("", status.user_retry_count.into())],
+  )?;
+  print_gauge(
+    out,
+    "admin_retry_count",
+    "The number of remaining admin PIN attempts.",
+    &labels,
+    &[("", status.admin_retry_count.into())],
+  )?;
+  print_gauge(
+    out,
+    "otp_slots_programmed",
+    "The number of programmed OTP slots.",
+    &labels,
+    &[
+      (r#",algorithm="hotp""#, slots.hotp as u64),
+      (r#",algorithm="totp""#, slots.totp as u64),
+    ],
+  )?;
+  if let Some(pws) = slots.pws {
+    print_gauge(
+      out,
+      "pws_slots_programmed",
+      "The number of programmed password safe slots.",
+      &labels,
+      &[("", pws as u64)],
+    )?;
+  }
+
+  if let Some((storage, sd_card_usage)) = &status.storage {
+    let volumes = [
+      (r#",volume="unencrypted""#, &storage.unencrypted_volume),
+      (r#",volume="encrypted""#, &storage.encrypted_volume),
+      (r#",volume="hidden""#, &storage.hidden_volume),
+    ];
+    let active = volumes
+      .iter()
+      .map(|(label, volume)| (*label, volume.active.into()))
+      .collect::<Vec<_>>();
+    let read_only = volumes
+      .iter()
+      .map(|(label, volume)| (*label, volume.read_only.into()))
+      .collect::<Vec<_>>();
+
+    print_gauge(
+      out,
+      "firmware_locked",
+      "Whether the firmware of the device is locked.",
+      &labels,
+      &[("", storage.firmware_locked.into())],
+    )?;
+    print_gauge(
+      out,
+      "storage_keys_created",
+      "Whether the storage keys of the device have been created.",
+      &labels,
+      &[("", storage.stick_initialized.into())],
+    )?;
+    print_gauge(
+      out,
+      "volume_active",
+      "Whether a volume of the device is active.",
+      &labels,
+      &active,
+    )?;
+    print_gauge(
+      out,
+      "volume_read_only",
+      "Whether a volume of the device is read-only.",
+      &labels,
+      &read_only,
+    )?;
+    print_gauge(
+      out,
+      "sd_card_not_written_start_percent",
+      "The start of the SD card range that has not been written to, in percent.",
+      &labels,
+      &[("", sd_card_usage.start.into())],
+    )?;
+    print_gauge(
+      out,
+      "sd_card_not_written_end_percent",
+      "The end of the SD card range that has not been written to, in percent.",
+      &labels,
+      &[("", sd_card_usage.end.into())],
+    )?;
+  }
+
+  if let Some(info) = &status.production_info {
+    let production_labels = format!(
+      r#",firmware_version="{}",firmware_version_internal="{}",cpu_id="{:#010x}",sd_card_manufacturer="{:#04x}",sd_card_oem="{:#06x}",sd_card_manufacturing_date="{}""#,
+      info.firmware_version,
+      info.firmware_version_internal,
+      info.serial_number_cpu,
+      info.sd_card.manufacturer,
+      info.sd_card.oem,
+      format_manufacturing_date(&info.sd_card),
+    );
+    print_gauge(
+      out,
+      "production_info",
+      "The production information of the device.",
+      &labels,
+      &[(&production_labels, 1)],
+    )?;
+    print_gauge(
+      out,
+      "sd_card_size_bytes",
+      "The size of the SD card, in bytes.",
+      &labels,
+      &[("", u64::from(info.sd_card.size) * 1_000_000_000)],
+    )?;
+  }
+  Ok(())
+}
+
+/// Write the given data to a file, replacing it atomically.
+///
+/// The data is written to a uniquely named temporary file in the same
+/// directory first, which is then renamed to the target path.  That
+/// way readers never observe a partially written file and concurrent
+/// invocations do not interfere with each other.
+fn write_file_atomically(path: &path::Path, data: &[u8]) -> anyhow::Result<()> {
+  let dir = match path.parent() {
+    Some(dir) if !dir.as_os_str().is_empty() => dir,
+    _ => path::Path::new("."),
+  };
+  let mut file = tempfile::NamedTempFile::new_in(dir)
+    .with_context(|| format!("Failed to create temporary file in '{}'", dir.display()))?;
+  file
+    .write_all(data)
+    .with_context(|| format!("Failed to write file '{}'", file.path().display()))?;
+  // Temporary files are only accessible by their owner, but the output
+  // is usually read by a different user, e.g., the node exporter.
+  file
+    .as_file()
+    .set_permissions(fs::Permissions::from_mode(0o644))
+    .with_context(|| format!("Failed to set permissions of '{}'", file.path().display()))?;
+  let _ = file
+    .persist(path)
+    .with_context(|| format!("Failed to replace file '{}'", path.display()))?;
+  Ok(())
+}
+
+/// Inquire the status of the nitrokey.
+pub fn status(ctx: &mut Context<'_>, args: args::StatusArgs) -> anyhow::Result<()> {
+  if args.check {
+    return status_check(ctx, &args);
+  }
+
+  let format = args.format;
+  anyhow::ensure!(
+    !args.count_pws || format == args::StatusFormat::Prometheus,
+    "The --count-pws option requires the Prometheus output format"
+  );
+  let data = with_device(ctx, |ctx, mut device| {
+    let mut status = api::status(&device)?;
+    if args.production_info {
+      status.production_info = api::production_info(&device)?;
+    }
+
+    let mut data = Vec::new();
+    match format {
+      args::StatusFormat::Text => {
+        print_status(&mut data, &status)?;
+        if let Some((storage, sd_card_usage)) = &status.storage {
+          print_storage_status(&mut data, storage, sd_card_usage)?;
+        }
+        if let Some(info) = &status.production_info {
+          print_production_info(&mut data, info)?;
+        }
+      }
+      args::StatusFormat::Prometheus => {
+        let slots = get_slot_counts(ctx, &mut device, &status, args.count_pws)?;
+        print_status_prometheus(&mut data, &status, &slots)?;
+      }
+    }
+    Ok(data)
+  })?;
+
+  match &args.output {
+    Some(path) => write_file_atomically(path, &data),
+    None => {
+      ctx.stdout.write_all(&data)?;
+      Ok(())
+    }
+  }
+}
+
 /// List the attached Nitrokey devices.
 pub fn list(ctx: &mut Context<'_>, no_connect: bool) -> anyhow::Result<()> {
   set_log_level(ctx);
@@ -553,10 +887,10 @@ pub fn list(ctx: &mut Context<'_>, no_connect: bool) -> anyhow::Result<()> {
 }
 
 /// Fill the SD card with random data
-pub fn fill(ctx: &mut Context<'_>, attach: bool) -> anyhow::Result<()> {
+pub fn fill(ctx: &mut Context<'_>, args: args::FillArgs) -> anyhow::Result<()> {
   with_storage_device(ctx, |ctx, mut device| {
     let mut initial_progress = 0;
-    if attach {
+    if args.attach {
       let status = device
         .get_operation_status()
         .context("Failed to query operation status")?;
@@ -571,9 +905,18 @@ pub fn fill(ctx: &mut Context<'_>, attach: bool) -> anyhow::Result<()> {
       // even if is cached to avoid accidental data loss.
       pinentry::clear(&pin_entry).context("Failed to clear cached secret")?;
 
-      try_with_pin(ctx, &pin_entry, |pin| {
+      let mut auth = pinentry::Authenticator::from(ctx, &device)?;
+      api::try_with_secret(&mut auth, api::Secret::AdminPin, |pin| {
         device.fill_sd_card(pin).context("Failed to fill SD card")
       })?;
+
+      if args.detach {
+        println!(
+          ctx,
+          "Started filling the SD card, use `nitrocli fill --attach` to show the progress"
+        )?;
+        return Ok(());
+      }
     }
 
     let mut progress_bar = output::ProgressBar::new(initial_progress);
@@ -592,6 +935,14 @@ pub fn fill(ctx: &mut Context<'_>, attach: bool) -> anyhow::Result<()> {
       progress_bar.draw(ctx)?;
     }
 
+    if let Some(command) = &args.notify_command {
+      let status = process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .status()
+        .context("Failed to run notify command")?;
+      anyhow::ensure!(status.success(), "Notify command failed with {}", status);
+    }
     Ok(())
   })
 }
@@ -605,7 +956,8 @@ pub fn reset(ctx: &mut Context<'_>, only_aes_key: bool) -> anyhow::Result<()> {
     // factory reset, we clear the pinentry cache for the admin PIN.
     pinentry::clear(&pin_entry).context("Failed to clear cached secret")?;
 
-    try_with_pin(ctx, &pin_entry, |pin| {
+    let mut auth = pinentry::Authenticator::from(ctx, &device)?;
+    api::try_with_secret(&mut auth, api::Secret::AdminPin, |pin| {
       if only_aes_key {
         // Similar to the else arm, we have to execute this command to avoid WrongPassword errors
         let _ = device.get_user_retry_count();
@@ -635,81 +987,255 @@ pub fn reset(ctx: &mut Context<'_>, only_aes_key: bool) -> anyhow::Result<()> {
 /// Change the configuration of the unencrypted volume.
 pub fn unencrypted_set(
   ctx: &mut Context<'_>,
-  mode: args::UnencryptedVolumeMode,
+  args: args::UnencryptedSetArgs,
 ) -> anyhow::Result<()> {
-  with_storage_device(ctx, |ctx, mut device| {
-    let pin_entry = pinentry::PinEntry::from(args::PinType::Admin, &device)?;
-    let mode = match mode {
-      args::UnencryptedVolumeMode::ReadWrite => nitrokey::VolumeMode::ReadWrite,
-      args::UnencryptedVolumeMode::ReadOnly => nitrokey::VolumeMode::ReadOnly,
+  let read_only = args.mode == args::UnencryptedVolumeMode::ReadOnly;
+  let changed = with_storage_device(ctx, |ctx, mut device| {
+    let status = device
+      .get_storage_status()
+      .context("Failed to retrieve storage status")?;
+    // The device does not reconnect if the mode does not change, so
+    // there would be nothing to wait for.
+    if status.unencrypted_volume.read_only == read_only {
+      return Ok(None);
+    }
+
+    let serial_number = device
+      .get_serial_number()
+      .context("Failed to retrieve serial number")?;
+    let usb_path = usb_path(ctx)?;
+    let mut mounted = false;
+    if args.remount {
+      let usb_device = volume::UsbDevice::from_hid_path(&usb_path)?;
+      mounted = !volume::unmount_all(usb_device, volume::Volume::Unencrypted)?.is_empty();
+    }
+    let mut auth = pinentry::Authenticator::from(ctx, &device)?;
+    api::set_unencrypted_volume_mode(&mut device, &mut auth, args.mode)?;
+    Ok(Some((serial_number, usb_path, mounted)))
+  })?;
+
+  if let Some((serial_number, old_usb_path, mounted)) = changed {
+    let timeout = time::Duration::from_secs(args.timeout);
+    let usb_path =
+      wait_for_unencrypted_volume_mode(serial_number, &old_usb_path, args.mode, timeout)?;
+    if mounted {
+      mount_volume(ctx, &usb_path, volume::Volume::Unencrypted, None)?;
+    }
+  }
+  Ok(())
+}
+
+/// Find the connected Nitrokey Storage with the given serial number and
+/// return its USB path if it reports the given mode for its unencrypted
+/// volume.
+fn find_storage_with_unencrypted_volume_mode(
+  serial_number: nitrokey::SerialNumber,
+  mode: args::UnencryptedVolumeMode,
+) -> anyhow::Result<Option<String>> {
+  let device_infos =
+    nitrokey::list_devices().context("Failed to list connected Nitrokey devices")?;
+  let mut manager =
+    nitrokey::take().context("Failed to acquire access to Nitrokey device manager")?;
+
+  // The device information of a Nitrokey Storage does not contain its
+  // serial number, so we have to connect to each of them.
+  for device_info in device_infos {
+    if device_info.model != Some(nitrokey::Model::Storage) {
+      continue;
+    }
+    let device = match manager.connect_path(device_info.path.deref()) {
+      Ok(nitrokey::DeviceWrapper::Storage(storage)) => storage,
+      _ => continue,
     };
+    if device.get_serial_number().ok() != Some(serial_number) {
+      continue;
+    }
 
-    // The unencrypted volume may reconnect, so be sure to flush caches to
-    // disk.
-    unsafe { sync() };
+    let status = device
+      .get_storage_status()
+      .context("Failed to retrieve storage status")?;
+    let read_only = mode == args::UnencryptedVolumeMode::ReadOnly;
+    return Ok(Some(device_info.path).filter(|_| status.unencrypted_volume.read_only == read_only));
+  }
+  Ok(None)
+}
 
-    try_with_pin(ctx, &pin_entry, |pin| {
-      device
-        .set_unencrypted_volume_mode(pin, mode)
-        .context("Failed to change unencrypted volume mode")
-    })
+/// Wait until the Nitrokey Storage with the given serial number has
+/// disconnected after changing the mode of its unencrypted volume and
+/// is connected again, reporting the new mode.
+///
+/// The status already reports the new mode before the device
+/// disconnects, so we first wait for the device with the given USB
+/// path to vanish.  The new USB path of the device is returned.
+fn wait_for_unencrypted_volume_mode(
+  serial_number: nitrokey::SerialNumber,
+  usb_path: &str,
+  mode: args::UnencryptedVolumeMode,
+  timeout: time::Duration,
+) -> anyhow::Result<String> {
+  let deadline = time::Instant::now() + timeout;
+  let timeout_err = || {
+    anyhow::anyhow!(
+      "The Nitrokey Storage did not reconnect in {} mode within {} seconds",
+      mode,
+      timeout.as_secs()
+    )
+  };
+
+  // Failing to enumerate the devices does not mean that ours is gone.
+  poll_until(deadline, || {
+    let devices = nitrokey::list_devices().ok()?;
+    (!devices.iter().any(|info| info.path == usb_path)).then(|| ())
   })
+  .ok_or_else(timeout_err)?;
+  // The device may vanish at any time while it reconnects, so errors
+  // only mean that it is not ready yet.
+  poll_until(deadline, || {
+    find_storage_with_unencrypted_volume_mode(serial_number, mode)
+      .ok()
+      .flatten()
+  })
+  .ok_or_else(timeout_err)
+}
+
+/// Call the given function until it returns a value or the given
+/// deadline passed.
+fn poll_until<F, T>(deadline: time::Instant, mut f: F) -> Option<T>
+where
+  F: FnMut() -> Option<T>,
+{
+  loop {
+    if let Some(value) = f() {
+      return Some(value);
+    }
+    if time::Instant::now() >= deadline {
+      return None;
+    }
+    thread::sleep(time::Duration::from_millis(100));
+  }
 }
 
 /// Open the encrypted volume on the Nitrokey.
-pub fn encrypted_open(ctx: &mut Context<'_>) -> anyhow::Result<()> {
+pub fn encrypted_open(
+  ctx: &mut Context<'_>,
+  mount: Option<Option<path::PathBuf>>,
+) -> anyhow::Result<()> {
   with_storage_device(ctx, |ctx, mut device| {
-    let pin_entry = pinentry::PinEntry::from(args::PinType::User, &device)?;
-
-    // We may forcefully close a hidden volume, if active, so be sure to
-    // flush caches to disk.
-    unsafe { sync() };
+    let usb_path = usb_path(ctx)?;
+    let mut auth = pinentry::Authenticator::from(ctx, &device)?;
+    api::open_encrypted_volume(&mut device, &usb_path, &mut auth)?;
 
-    try_with_pin(ctx, &pin_entry, |pin| {
-      device
-        .enable_encrypted_volume(pin)
-        .context("Failed to open encrypted volume")
-    })
+    if let Some(dir) = mount {
+      mount_volume(ctx, &usb_path, volume::Volume::Encrypted, dir.as_deref())?;
+    }
+    Ok(())
   })
 }
 
+/// Mount the given volume of the device with the given USB path once
+/// it is available.
+fn mount_volume(
+  ctx: &mut Context<'_>,
+  usb_path: &str,
+  volume: volume::Volume,
+  dir: Option<&path::Path>,
+) -> anyhow::Result<()> {
+  let usb_device = volume::UsbDevice::from_hid_path(usb_path)?;
+  let device = volume::wait_for_device(usb_device, volume)?;
+  let mount_point = volume::mount(&device, dir)
+    .with_context(|| format!("Failed to mount volume '{}'", device.display()))?;
+  println!(
+    ctx,
+    "Mounted {} at {}",
+    device.display(),
+    mount_point.display()
+  )?;
+  Ok(())
+}
+
 /// Close the previously opened encrypted volume.
 pub fn encrypted_close(ctx: &mut Context<'_>) -> anyhow::Result<()> {
-  with_storage_device(ctx, |_ctx, mut device| {
-    // Flush all filesystem caches to disk. We are mostly interested in
-    // making sure that the encrypted volume on the Nitrokey we are
-    // about to close is not closed while not all data was written to
-    // it.
-    unsafe { sync() };
-
-    device
-      .disable_encrypted_volume()
-      .context("Failed to close encrypted volume")
+  with_storage_device(ctx, |ctx, mut device| {
+    let usb_path = usb_path(ctx)?;
+    api::close_encrypted_volume(&mut device, &usb_path)
   })
 }
 
-/// Create a hidden volume.
-pub fn hidden_create(ctx: &mut Context<'_>, slot: u8, start: u8, end: u8) -> anyhow::Result<()> {
-  with_storage_device(ctx, |ctx, mut device| {
-    let pwd_entry = pinentry::PwdEntry::from(&device)?;
-    let pwd = if let Some(pwd) = &ctx.password {
-      pwd
-        .to_str()
-        .context("Failed to read password: Invalid Unicode data found")
-        .map(ToOwned::to_owned)
+/// Print whether the firmware of a Nitrokey Storage is locked.
+pub fn firmware_status(ctx: &mut Context<'_>) -> anyhow::Result<()> {
+  with_storage_device(ctx, |ctx, device| {
+    let status = device
+      .get_storage_status()
+      .context("Failed to retrieve storage status")?;
+    let state = if status.firmware_locked {
+      args::FirmwareState::Locked
     } else {
-      pinentry::choose(ctx, &pwd_entry).context("Failed to select new PIN")
-    }?;
+      args::FirmwareState::Unlocked
+    };
 
-    device
-      .create_hidden_volume(slot, start, end, &pwd)
-      .context("Failed to create hidden volume")
+    println!(ctx, "firmware version: {}", status.firmware_version)?;
+    println!(ctx, "firmware:         {}", state)?;
+    Ok(())
   })
 }
 
-/// Open a hidden volume.
-pub fn hidden_open(ctx: &mut Context<'_>) -> anyhow::Result<()> {
+/// Put a Nitrokey Storage into firmware update mode.
+pub fn firmware_update_mode(ctx: &mut Context<'_>) -> anyhow::Result<()> {
   with_storage_device(ctx, |ctx, mut device| {
+    let pin_entry = pinentry::UpdatePinEntry::from(&device)?;
+    let serial_number = device
+      .get_serial_number()
+      .context("Failed to retrieve serial number")?;
+
+    // The device can only be used again after a new firmware has been
+    // flashed, so make sure that the user really means this device.
+    writeln!(
+      ctx.stderr,
+      "The Nitrokey Storage {} will be unusable until a new firmware is flashed.",
+      serial_number
+    )?;
+    confirm(
+      ctx,
+      "Enter the serial number of the device to continue",
+      &serial_number.to_string(),
+    )?;
+
+    // Similar to reset, we want the user to re-enter the update PIN
+    // even if it is cached.
+    pinentry::clear(&pin_entry).context("Failed to clear cached secret")?;
+
+    // The volumes disappear once the device is in update mode.
+    let usb_device = volume::UsbDevice::from_hid_path(&usb_path(ctx)?)?;
+    let _ = volume::unmount_all(usb_device, volume::Volume::Unencrypted)?;
+    let _ = volume::unmount_all(usb_device, volume::Volume::Encrypted)?;
+    unsafe { sync() };
+
+    let mut auth = pinentry::Authenticator::from(ctx, &device)?;
+    api::try_with_secret(&mut auth, api::Secret::UpdatePin, |pin| {
+      device
+        .enable_firmware_update(pin)
+        .context("Failed to enable firmware update mode")
+    })
+  })
+}
+
+/// Create a hidden volume.
+pub fn hidden_create(ctx: &mut Context<'_>, slot: u8, start: u8, end: u8) -> anyhow::Result<()> {
+  with_storage_device(ctx, |ctx, mut device| {
+    let range = layout::SlotRange { slot, start, end };
+    let status = device
+      .get_storage_status()
+      .context("Failed to retrieve storage status")?;
+    let path = layout::layout_path(status.serial_number_sd_card)?;
+    let mut layout = layout::Layout::load(&path)?;
+    if let Some(other) = layout.find_overlap(&range) {
+      anyhow::bail!(
+        "The hidden volume in {} overlaps with the hidden volume in {}",
+        range,
+        other
+      );
+    }
+
     let pwd_entry = pinentry::PwdEntry::from(&device)?;
     let pwd = if let Some(pwd) = &ctx.password {
       pwd
@@ -717,28 +1243,127 @@ pub fn hidden_open(ctx: &mut Context<'_>) -> anyhow::Result<()> {
         .context("Failed to read password: Invalid Unicode data found")
         .map(ToOwned::to_owned)
     } else {
-      pinentry::inquire(ctx, &pwd_entry, pinentry::Mode::Query, None)
-        .context("Failed to inquire PIN")
+      pinentry::choose(ctx, &pwd_entry).context("Failed to select new PIN")
     }?;
 
-    // We may forcefully close an encrypted volume, if active, so be sure
-    // to flush caches to disk.
-    unsafe { sync() };
-
     device
-      .enable_hidden_volume(&pwd)
-      .context("Failed to open hidden volume")
+      .create_hidden_volume(slot, start, end, &pwd)
+      .context("Failed to create hidden volume")?;
+
+    layout.set(range);
+    layout.save(&path)
+  })
+}
+
+/// Print the recorded layout of the hidden volumes.
+fn print_layout(
+  ctx: &mut Context<'_>,
+  layout: &layout::Layout,
+  sd_card_size: u64,
+) -> anyhow::Result<()> {
+  println!(ctx, "slot\tstart\tend\tsize")?;
+  for range in &layout.slots {
+    let size = sd_card_size as f64 * f64::from(range.end.saturating_sub(range.start)) / 100.0;
+    println!(
+      ctx,
+      "{}\t{}%\t{}%\t{:.1} GiB",
+      range.slot,
+      range.start,
+      range.end,
+      size / f64::from(1 << 30)
+    )?;
+  }
+  Ok(())
+}
+
+/// Plan the location of a hidden volume and record it in the local
+/// layout.
+pub fn hidden_plan(
+  ctx: &mut Context<'_>,
+  slot: u8,
+  start: layout::Size,
+  size: layout::Size,
+) -> anyhow::Result<()> {
+  anyhow::ensure!(
+    slot < layout::SLOT_COUNT,
+    "Invalid hidden volume slot {} (must be less than {})",
+    slot,
+    layout::SLOT_COUNT
+  );
+
+  with_storage_device(ctx, |ctx, device| {
+    let status = device
+      .get_storage_status()
+      .context("Failed to retrieve storage status")?;
+    let info = device
+      .get_production_info()
+      .context("Failed to retrieve production information")?;
+    let sd_card_usage = device
+      .get_sd_card_usage()
+      .context("Failed to retrieve SD card usage")?;
+
+    // The device reports the SD card size in GB.
+    let sd_card_size = u64::from(info.sd_card.size) * 1_000_000_000;
+    let start = start.to_percent(sd_card_size, false);
+    let end = start.saturating_add(size.to_percent(sd_card_size, true));
+    anyhow::ensure!(end > start, "The hidden volume must not be empty");
+    anyhow::ensure!(
+      end <= 100,
+      "The hidden volume would end at {}%, beyond the end of the SD card",
+      end
+    );
+
+    let range = layout::SlotRange {
+      slot,
+      start: start as u8,
+      end: end as u8,
+    };
+    let path = layout::layout_path(status.serial_number_sd_card)?;
+    let mut layout = layout::Layout::load(&path)?;
+    if let Some(other) = layout.find_overlap(&range) {
+      anyhow::bail!(
+        "The hidden volume in {} overlaps with the hidden volume in {}",
+        range,
+        other
+      );
+    }
+    if !range.is_within(&sd_card_usage) {
+      writeln!(
+        ctx.stderr,
+        "Warning: The hidden volume in {} is not within the range of the SD card that has not \
+         been written to ({}% .. {}%)",
+        range, sd_card_usage.start, sd_card_usage.end
+      )?;
+    }
+
+    layout.set(range);
+    layout.save(&path)?;
+    print_layout(ctx, &layout, sd_card_size)
+  })
+}
+
+/// Open a hidden volume.
+pub fn hidden_open(
+  ctx: &mut Context<'_>,
+  mount: Option<Option<path::PathBuf>>,
+) -> anyhow::Result<()> {
+  with_storage_device(ctx, |ctx, mut device| {
+    let usb_path = usb_path(ctx)?;
+    let mut auth = pinentry::Authenticator::from(ctx, &device)?;
+    api::open_hidden_volume(&mut device, &usb_path, &mut auth)?;
+
+    if let Some(dir) = mount {
+      mount_volume(ctx, &usb_path, volume::Volume::Encrypted, dir.as_deref())?;
+    }
+    Ok(())
   })
 }
 
 /// Close a previously opened hidden volume.
 pub fn hidden_close(ctx: &mut Context<'_>) -> anyhow::Result<()> {
-  with_storage_device(ctx, |_ctx, mut device| {
-    unsafe { sync() };
-
-    device
-      .disable_hidden_volume()
-      .context("Failed to close hidden volume")
+  with_storage_device(ctx, |ctx, mut device| {
+    let usb_path = usb_path(ctx)?;
+    api::close_hidden_volume(&mut device, &usb_path)
   })
 }
 
@@ -788,24 +1413,198 @@ pub fn config_set(ctx: &mut Context<'_>, args: args::ConfigSetArgs) -> anyhow::R
   };
 
   with_device(ctx, |ctx, device| {
-    let mut device = authenticate_admin(ctx, device)?;
-    let config = device
-      .get_config()
-      .context("Failed to get current configuration")?;
-    let config = nitrokey::Config {
+    let config = api::device_config(&device)?;
+    let config = api::DeviceConfig {
       num_lock: num_lock.or(config.num_lock),
       caps_lock: caps_lock.or(config.caps_lock),
       scroll_lock: scroll_lock.or(config.scroll_lock),
-      user_password: otp_pin.unwrap_or(config.user_password),
+      otp_pin: otp_pin.unwrap_or(config.otp_pin),
+      unencrypted_volume: None,
     };
-    device
-      .write_config(config)
-      .context("Failed to set new configuration")
+    let mut auth = pinentry::Authenticator::from(ctx, &device)?;
+    api::set_device_config(device, &mut auth, &config)
+  })
+}
+
+/// Print the differences between two device configurations.
+fn print_config_diff(
+  ctx: &mut Context<'_>,
+  current: &api::DeviceConfig,
+  new: &api::DeviceConfig,
+) -> anyhow::Result<()> {
+  let changes = [
+    (
+      "num lock binding:        ",
+      format_option(current.num_lock),
+      format_option(new.num_lock),
+    ),
+    (
+      "caps lock binding:       ",
+      format_option(current.caps_lock),
+      format_option(new.caps_lock),
+    ),
+    (
+      "scroll lock binding:     ",
+      format_option(current.scroll_lock),
+      format_option(new.scroll_lock),
+    ),
+    (
+      "require user PIN for OTP:",
+      current.otp_pin.to_string(),
+      new.otp_pin.to_string(),
+    ),
+    (
+      "unencrypted volume:      ",
+      format_option(current.unencrypted_volume),
+      format_option(new.unencrypted_volume),
+    ),
+  ];
+
+  if current == new {
+    println!(ctx, "Configuration is up to date")?;
+  } else {
+    println!(ctx, "Changes:")?;
+    for (label, old, new) in changes.iter().filter(|(_, old, new)| old != new) {
+      println!(ctx, "  {} {} -> {}", label, old, new)?;
+    }
+  }
+  Ok(())
+}
+
+/// Export the Nitrokey configuration.
+pub fn config_export(ctx: &mut Context<'_>) -> anyhow::Result<()> {
+  with_device(ctx, |ctx, device| {
+    let config = api::device_config(&device)?;
+    let data = toml::to_string(&config).context("Failed to serialize configuration")?;
+    print!(ctx, "{}", data)?;
+    Ok(())
+  })
+}
+
+/// Import a previously exported Nitrokey configuration.
+pub fn config_import(ctx: &mut Context<'_>, args: args::ConfigImportArgs) -> anyhow::Result<()> {
+  let file = args.file.as_str();
+  let data = if file == "-" {
+    value_or_stdin(ctx, file)?.into_owned()
+  } else {
+    fs::read_to_string(file).with_context(|| format!("Failed to read file '{}'", file))?
+  };
+  let new = toml::from_str::<api::DeviceConfig>(&data)
+    .with_context(|| format!("Failed to parse configuration in '{}'", file))?;
+
+  with_device(ctx, |ctx, device| {
+    let current = api::device_config(&device)?;
+    anyhow::ensure!(
+      new.unencrypted_volume.is_none() || current.unencrypted_volume.is_some(),
+      "The unencrypted volume mode can only be set on a Nitrokey Storage"
+    );
+    let new = api::DeviceConfig {
+      unencrypted_volume: new.unencrypted_volume.or(current.unencrypted_volume),
+      ..new
+    };
+
+    print_config_diff(ctx, &current, &new)?;
+    if args.dry_run || current == new {
+      return Ok(());
+    }
+    if !args.yes {
+      // The configuration itself was read from stdin, so we cannot ask
+      // for confirmation there.
+      anyhow::ensure!(
+        file != "-",
+        "Refusing to apply a configuration read from stdin without --yes"
+      );
+      confirm(ctx, "Enter 'yes' to apply these changes", "yes")?;
+    }
+
+    let mut auth = pinentry::Authenticator::from(ctx, &device)?;
+    api::set_device_config(device, &mut auth, &new)
+  })
+}
+
+/// Determine the default path of the agent socket.
+fn default_agent_socket() -> anyhow::Result<path::PathBuf> {
+  let dir = env::var_os("XDG_RUNTIME_DIR")
+    .context("XDG_RUNTIME_DIR is not set, please provide a socket path using --socket")?;
+  Ok(path::PathBuf::from(dir).join("nitrocli").join("agent.sock"))
+}
+
+/// Create the socket of the agent, which only the current user may
+/// connect to.
+fn bind_agent_socket(path: &path::Path) -> anyhow::Result<net::UnixListener> {
+  if let Some(dir) = path.parent() {
+    fs::create_dir_all(dir)
+      .with_context(|| format!("Failed to create directory '{}'", dir.display()))?;
+  }
+  if fs::symlink_metadata(path).is_ok() {
+    anyhow::ensure!(
+      net::UnixStream::connect(path).is_err(),
+      "A nitrocli agent is already listening on {}",
+      path.display()
+    );
+    fs::remove_file(path)
+      .with_context(|| format!("Failed to remove stale socket '{}'", path.display()))?;
+  }
+
+  // Restrict the permissions before anybody gets a chance to connect.
+  let umask = unsafe { libc::umask(0o177) };
+  let result = net::UnixListener::bind(path);
+  let _ = unsafe { libc::umask(umask) };
+  result.with_context(|| format!("Failed to bind agent socket '{}'", path.display()))
+}
+
+/// Keep the password safe unlocked and serve the requests of other
+/// nitrocli invocations.
+pub fn agent(ctx: &mut Context<'_>, args: args::AgentArgs) -> anyhow::Result<()> {
+  let socket = match args.socket {
+    Some(socket) => socket,
+    None => default_agent_socket()?,
+  };
+  let idle_timeout = time::Duration::from_secs(args.idle_timeout);
+  let device_path = find_device(&ctx.config)?.path;
+
+  with_device(ctx, |ctx, mut device| {
+    let served = agent::Device {
+      model: args::DeviceModel::try_from(device.get_model())?,
+      serial_number: device
+        .get_serial_number()
+        .context("Could not query the serial number")?,
+      usb_path: device_path.clone(),
+    };
+    let mut auth = pinentry::Authenticator::from(ctx, &device)?;
+    let stop = api::with_password_safe(&mut device, &mut auth, |pws| {
+      let listener = bind_agent_socket(&socket)?;
+      agent::install_signal_handlers();
+      println!(ctx, "{}", socket.display())?;
+      ctx.stdout.flush()?;
+
+      // Failing to enumerate the devices does not mean that ours is
+      // gone, so we only stop once it is no longer listed.
+      let is_present = || {
+        nitrokey::list_devices()
+          .map(|devices| devices.iter().any(|info| info.path == device_path))
+          .unwrap_or(true)
+      };
+      let result = agent::serve(&listener, pws, &served, idle_timeout, is_present);
+      let _ = fs::remove_file(&socket);
+      result
+    })?;
+
+    // A removed device locks itself as it loses power.
+    if stop != agent::Stop::Removed {
+      device.lock().context("Failed to lock the device")?;
+    }
+    writeln!(ctx.stderr, "Stopped the agent: {}", stop.describe())?;
+    Ok(())
   })
 }
 
 /// Lock the Nitrokey device.
 pub fn lock(ctx: &mut Context<'_>) -> anyhow::Result<()> {
+  if let Some(client) = agent_client(ctx)? {
+    return client.lock();
+  }
+
   with_device(ctx, |_ctx, mut device| {
     device.lock().context("Failed to lock the device")
   })
@@ -829,35 +1628,181 @@ fn get_unix_timestamp() -> anyhow::Result<u64> {
     .map(|duration| duration.as_secs())
 }
 
-/// Generate a one-time password on the Nitrokey device.
-pub fn otp_get(
+/// Set the device time used for TOTP generation.
+///
+/// If `force` is not set, the device refuses to move its time
+/// backwards, as well as to set it if it has not been set before.
+fn set_time<T>(device: &mut T, time: u64, force: bool) -> anyhow::Result<()>
+where
+  T: GenerateOtp,
+{
+  match device.set_time(time, force) {
+    // The Nitrokey Pro has no battery-backed clock, so the device time
+    // is reset to zero whenever it is disconnected. A non-forced update
+    // cannot tell that apart from a device time ahead of the given one.
+    Err(nitrokey::Error::CommandError(nitrokey::CommandError::Timestamp)) if !force => {
+      Err(anyhow::anyhow!(
+        "The device time is ahead of the given time or has not been set yet; \
+        use `nitrocli otp time --force` to set it once"
+      ))
+      .context("Failed to set new time")
+    }
+    result => result.context("Failed to set new time"),
+  }
+}
+
+/// Set the device time and generate a TOTP code for it.
+fn get_totp<T>(ctx: &Context<'_>, slot: u8, time: u64, device: &mut T) -> anyhow::Result<String>
+where
+  T: GenerateOtp,
+{
+  set_time(device, time, !ctx.config.no_force_time)?;
+  get_otp(slot, args::OtpAlgorithm::Totp, device)
+}
+
+/// Calculate the number of seconds a TOTP code generated at the given
+/// time remains valid.
+fn totp_seconds_remaining(time: u64, time_window: u16) -> u64 {
+  let time_window = u64::from(time_window);
+  time_window - time % time_window
+}
+
+/// Print a fresh TOTP code along with a countdown for every time
+/// window, until interrupted.
+fn watch_totp<T>(
   ctx: &mut Context<'_>,
   slot: u8,
-  algorithm: args::OtpAlgorithm,
-  time: Option<u64>,
-) -> anyhow::Result<()> {
-  with_device(ctx, |ctx, mut device| {
-    if algorithm == args::OtpAlgorithm::Totp {
-      device
-        .set_time(
-          match time {
-            Some(time) => time,
-            None => get_unix_timestamp().context("Failed to retrieve current time")?,
-          },
-          true,
-        )
-        .context("Failed to set new time")?;
+  time_window: u16,
+  device: &mut T,
+) -> anyhow::Result<()>
+where
+  T: GenerateOtp,
+{
+  loop {
+    let time = get_unix_timestamp().context("Failed to retrieve current time")?;
+    let otp = get_totp(ctx, slot, time, device)?;
+    let remaining = totp_seconds_remaining(time, time_window);
+
+    if ctx.is_tty {
+      for seconds in (1..=remaining).rev() {
+        print!(
+          ctx,
+          "{}\r{} ({}s)",
+          termion::clear::CurrentLine,
+          otp,
+          seconds
+        )?;
+        ctx.stdout.flush()?;
+        thread::sleep(time::Duration::from_secs(1));
+      }
+    } else {
+      println!(ctx, "{}", otp)?;
+      ctx.stdout.flush()?;
+      thread::sleep(time::Duration::from_secs(remaining));
     }
+  }
+}
+
+fn generate_otp<T>(
+  ctx: &mut Context<'_>,
+  args: &args::OtpGetArgs,
+  device: &mut T,
+) -> anyhow::Result<()>
+where
+  T: GenerateOtp,
+{
+  if args.algorithm == args::OtpAlgorithm::Hotp {
+    let otp = get_otp(args.slot, args.algorithm, device)?;
+    println!(ctx, "{}", otp)?;
+    return Ok(());
+  }
+
+  if args.watch {
+    return watch_totp(ctx, args.slot, args.time_window, device);
+  }
+
+  let now = get_unix_timestamp().context("Failed to retrieve current time")?;
+  let mut time = match args.time {
+    Some(time) => time.resolve(now)?,
+    None => now,
+  };
+  if args.at_next_window {
+    time = time - time % u64::from(args.time_window) + u64::from(args.time_window);
+  }
+  if let Some(wait_min) = args.wait_min {
+    let remaining = totp_seconds_remaining(time, args.time_window);
+    if remaining < wait_min {
+      // The system time is truncated to full seconds, so after sleeping
+      // for the remaining time we are guaranteed to be in the next
+      // window.
+      thread::sleep(time::Duration::from_secs(remaining));
+      time = get_unix_timestamp().context("Failed to retrieve current time")?;
+    }
+  }
+
+  let otp = get_totp(ctx, args.slot, time, device)?;
+  println!(ctx, "{}", otp)?;
+  if args.validity {
+    let remaining = totp_seconds_remaining(time, args.time_window);
+    println!(
+      ctx,
+      "valid for {}s (from {} until {})",
+      remaining,
+      time - (u64::from(args.time_window) - remaining),
+      time + remaining
+    )?;
+  }
+  Ok(())
+}
+
+/// Generate a one-time password on the Nitrokey device.
+pub fn otp_get(ctx: &mut Context<'_>, args: args::OtpGetArgs) -> anyhow::Result<()> {
+  anyhow::ensure!(args.time_window > 0, "The time window must not be zero");
+  anyhow::ensure!(
+    args.algorithm == args::OtpAlgorithm::Totp
+      || (args.wait_min.is_none() && !args.watch && !args.validity),
+    "The --wait-min, --watch, and --validity options require the TOTP algorithm"
+  );
+  if let Some(wait_min) = args.wait_min {
+    anyhow::ensure!(
+      wait_min <= u64::from(args.time_window),
+      "The minimum validity must not exceed the time window"
+    );
+  }
+
+  with_device(ctx, |ctx, mut device| {
     let config = device
       .get_config()
       .context("Failed to get get current device configuration")?;
-    let otp = if config.user_password {
+    if config.user_password {
       let mut user = authenticate_user(ctx, device)?;
-      get_otp(slot, algorithm, &mut user)
+      generate_otp(ctx, &args, &mut user)
     } else {
-      get_otp(slot, algorithm, &mut device)
-    }?;
-    println!(ctx, "{}", otp)?;
+      generate_otp(ctx, &args, &mut device)
+    }
+  })
+}
+
+/// Compare the device time used for TOTP generation with the system
+/// time.
+pub fn otp_time(ctx: &mut Context<'_>, force: bool) -> anyhow::Result<()> {
+  with_device(ctx, |ctx, mut device| {
+    let time = get_unix_timestamp().context("Failed to retrieve current time")?;
+    println!(ctx, "system time:\t{}", time)?;
+
+    // The device does not report its time, so we cannot show the
+    // drift. But a non-forced update fails if it would move the time
+    // backwards, and if it succeeds, the device time has been
+    // overwritten with the system time, which we have to report.
+    let state = match device.set_time(time, force) {
+      Ok(()) if force => "set to system time",
+      Ok(()) => "not ahead of system time, set to system time",
+      Err(nitrokey::Error::CommandError(nitrokey::CommandError::Timestamp)) if !force => {
+        "ahead of system time or not set, unchanged"
+      }
+      Err(err) => return Err(err).context("Failed to set new time"),
+    };
+    println!(ctx, "device time:\t{}", state)?;
     Ok(())
   })
 }
@@ -935,6 +1880,7 @@ pub fn otp_set(ctx: &mut Context<'_>, args: args::OtpSetArgs) -> anyhow::Result<
       args::OtpAlgorithm::Totp => device.write_totp_slot(data, time_window),
     }
     .context("Failed to write OTP slot")?;
+    record_otp_change(ctx, &*device);
     Ok(())
   })
 }
@@ -952,39 +1898,166 @@ pub fn otp_clear(
       args::OtpAlgorithm::Totp => device.erase_totp_slot(slot),
     }
     .context("Failed to clear OTP slot")?;
+    record_otp_change(ctx, &*device);
     Ok(())
   })
 }
 
+/// Retrieve the path of the file recording modifications of the OTP
+/// slots of the device with the given serial number.
+///
+/// Extensions caching information about OTP slots receive this path
+/// and can compare the file's modification time to that of their cache.
+fn otp_stamp(ctx: &Context<'_>, serial_number: nitrokey::SerialNumber) -> Option<path::PathBuf> {
+  let dir = ctx.otp_stamps.as_ref()?;
+  Some(dir.join(serial_number.to_string()))
+}
+
+/// Record that the OTP slots of the given device were modified.
+fn record_otp_change<'mgr>(ctx: &Context<'_>, device: &impl Device<'mgr>) {
+  let stamp = device
+    .get_serial_number()
+    .ok()
+    .and_then(|serial_number| otp_stamp(ctx, serial_number));
+  if let Some(stamp) = stamp {
+    // Writing the time of the change makes sure that the modification
+    // time gets updated. Extensions also refresh their caches when they
+    // encounter an unknown slot name, so failing to record the change
+    // is not fatal.
+    let now = time::SystemTime::now()
+      .duration_since(time::UNIX_EPOCH)
+      .unwrap_or_default();
+    let _ = stamp
+      .parent()
+      .map_or(Ok(()), fs::create_dir_all)
+      .and_then(|()| fs::write(&stamp, format!("{}\n", now.as_nanos())));
+  }
+}
+
+/// Decode a hexadecimal string as produced by `prepare_secret`.
+fn decode_hex(s: &str) -> anyhow::Result<Vec<u8>> {
+  anyhow::ensure!(
+    s.is_ascii() && s.len() % 2 == 0,
+    "Failed to parse hex secret"
+  );
+  (0..s.len())
+    .step_by(2)
+    .map(|idx| u8::from_str_radix(&s[idx..idx + 2], 16).context("Failed to parse hex secret"))
+    .collect()
+}
+
+/// The number of HOTP counter values after the expected one that we
+/// consider when verifying an HOTP slot.
+const HOTP_LOOK_AHEAD: u64 = 1000;
+
+fn verify_otp<T>(
+  ctx: &mut Context<'_>,
+  args: &args::OtpVerifyArgs,
+  secret: &[u8],
+  device: &mut T,
+) -> anyhow::Result<()>
+where
+  T: GenerateOtp,
+{
+  match args.algorithm {
+    args::OtpAlgorithm::Hotp => {
+      // We do not know the counter of the slot and every code we
+      // generate advances it, so we look for the counter the first code
+      // belongs to and check that the second one belongs to its
+      // successor. Relying on a single code could yield a false match.
+      let first = get_otp(args.slot, args.algorithm, device)?;
+      let second = get_otp(args.slot, args.algorithm, device)?;
+      let digits = first.len() as u32;
+      let end = args.counter.saturating_add(HOTP_LOOK_AHEAD);
+      let counter = (args.counter..end).find(|counter| {
+        otp::hotp(secret, *counter, digits) == first
+          && otp::hotp(secret, counter + 1, digits) == second
+      });
+      match counter {
+        Some(counter) => println!(
+          ctx,
+          "OTP slot matches the secret (counter advanced to {})",
+          counter + 2
+        )?,
+        None => anyhow::bail!(
+          "OTP slot does not match the secret: the device generated {} and {}, which \
+          correspond to no counter value between {} and {}",
+          first,
+          second,
+          args.counter,
+          end - 1
+        ),
+      }
+    }
+    args::OtpAlgorithm::Totp => {
+      anyhow::ensure!(args.time_window > 0, "The time window must not be zero");
+      let time = get_unix_timestamp().context("Failed to retrieve current time")?;
+      let actual = get_totp(ctx, args.slot, time, device)?;
+      let expected = otp::totp(secret, time, args.time_window, actual.len() as u32);
+      anyhow::ensure!(
+        actual == expected,
+        "OTP slot does not match the secret: the device generated {} but {} was expected",
+        actual,
+        expected
+      );
+      println!(ctx, "OTP slot matches the secret")?;
+    }
+  }
+  Ok(())
+}
+
+/// Verify that an OTP slot generates the codes expected for a secret.
+pub fn otp_verify(ctx: &mut Context<'_>, args: args::OtpVerifyArgs) -> anyhow::Result<()> {
+  if args.algorithm == args::OtpAlgorithm::Hotp && !args.yes {
+    // If the secret is to be read from stdin, we cannot ask for
+    // confirmation there.
+    anyhow::ensure!(
+      args.secret != "-",
+      "Refusing to verify an HOTP slot with a secret read from stdin without --yes"
+    );
+    writeln!(
+      ctx.stderr,
+      "Verifying HOTP slot {} advances its counter by two.",
+      args.slot
+    )?;
+    confirm(ctx, "Enter 'yes' to continue", "yes")?;
+  }
+
+  let secret = value_or_stdin(ctx, &args.secret)?;
+  let secret = prepare_secret(secret, args.format)?;
+  let secret = decode_hex(&secret)?;
+
+  with_device(ctx, |ctx, mut device| {
+    let config = device
+      .get_config()
+      .context("Failed to get get current device configuration")?;
+    if config.user_password {
+      let mut user = authenticate_user(ctx, device)?;
+      verify_otp(ctx, &args, &secret, &mut user)
+    } else {
+      verify_otp(ctx, &args, &secret, &mut device)
+    }
+  })
+}
+
 fn print_otp_status(
   ctx: &mut Context<'_>,
   algorithm: args::OtpAlgorithm,
   device: &nitrokey::DeviceWrapper<'_>,
   all: bool,
 ) -> anyhow::Result<()> {
-  let mut slot: u8 = 0;
-  loop {
-    let result = match algorithm {
-      args::OtpAlgorithm::Hotp => device.get_hotp_slot_name(slot),
-      args::OtpAlgorithm::Totp => device.get_totp_slot_name(slot),
-    };
-    slot = slot
-      .checked_add(1)
-      .context("Encountered integer overflow when iterating OTP slots")?;
-    let name = match result {
-      Ok(name) => name,
-      Err(nitrokey::Error::LibraryError(nitrokey::LibraryError::InvalidSlot)) => return Ok(()),
-      Err(nitrokey::Error::CommandError(nitrokey::CommandError::SlotNotProgrammed)) => {
-        if all {
-          "[not programmed]".to_string()
-        } else {
-          continue;
-        }
-      }
-      Err(err) => return Err(err).context("Failed to check OTP slot"),
+  for (slot, name) in api::otp_slot_names(device, algorithm)?
+    .into_iter()
+    .enumerate()
+  {
+    let name = match name {
+      Some(name) => name,
+      None if all => "[not programmed]".to_string(),
+      None => continue,
     };
-    println!(ctx, "{}\t{}\t{}", algorithm, slot - 1, name)?;
+    println!(ctx, "{}\t{}\t{}", algorithm, slot, name)?;
   }
+  Ok(())
 }
 
 /// Print the status of the OTP slots.
@@ -1045,12 +2118,23 @@ fn choose_pin(
 }
 
 /// Change a PIN.
-pub fn pin_set(ctx: &mut Context<'_>, pin_type: args::PinType) -> anyhow::Result<()> {
+pub fn pin_set(ctx: &mut Context<'_>, pin_type: args::PinSetType) -> anyhow::Result<()> {
+  let pin_type = match pin_type {
+    args::PinSetType::Admin => args::PinType::Admin,
+    args::PinSetType::User => args::PinType::User,
+    args::PinSetType::Update => return pin_set_update(ctx),
+  };
+
   with_device(ctx, |ctx, mut device| {
     let pin_entry = pinentry::PinEntry::from(pin_type, &device)?;
     let new_pin = choose_pin(ctx, &pin_entry, true)?;
 
-    try_with_pin(ctx, &pin_entry, |current_pin| match pin_type {
+    let mut auth = pinentry::Authenticator::from(ctx, &device)?;
+    let secret = match pin_type {
+      args::PinType::Admin => api::Secret::AdminPin,
+      args::PinType::User => api::Secret::UserPin,
+    };
+    api::try_with_secret(&mut auth, secret, |current_pin| match pin_type {
       args::PinType::Admin => device
         .change_admin_pin(current_pin, &new_pin)
         .context("Failed to change admin PIN"),
@@ -1066,14 +2150,31 @@ pub fn pin_set(ctx: &mut Context<'_>, pin_type: args::PinType) -> anyhow::Result
   })
 }
 
+/// Change the update PIN of a Nitrokey Storage.
+fn pin_set_update(ctx: &mut Context<'_>) -> anyhow::Result<()> {
+  with_storage_device(ctx, |ctx, mut device| {
+    let pin_entry = pinentry::UpdatePinEntry::from(&device)?;
+    let new_pin = pinentry::choose(ctx, &pin_entry).context("Failed to select PIN")?;
+
+    let mut auth = pinentry::Authenticator::from(ctx, &device)?;
+    api::try_with_secret(&mut auth, api::Secret::UpdatePin, |current_pin| {
+      device
+        .change_update_pin(current_pin, &new_pin)
+        .context("Failed to change update PIN")
+    })?;
+
+    // As in pin_set, the old PIN may have been cached.
+    pinentry::clear(&pin_entry)
+  })
+}
+
 /// Unblock and reset the user PIN.
 pub fn pin_unblock(ctx: &mut Context<'_>) -> anyhow::Result<()> {
   with_device(ctx, |ctx, mut device| {
     let pin_entry = pinentry::PinEntry::from(args::PinType::User, &device)?;
     let user_pin = choose_pin(ctx, &pin_entry, false)?;
-    let pin_entry = pinentry::PinEntry::from(args::PinType::Admin, &device)?;
-
-    try_with_pin(ctx, &pin_entry, |admin_pin| {
+    let mut auth = pinentry::Authenticator::from(ctx, &device)?;
+    api::try_with_secret(&mut auth, api::Secret::AdminPin, |admin_pin| {
       device
         .unlock_user_pin(admin_pin, &user_pin)
         .context("Failed to unblock user PIN")
@@ -1081,21 +2182,6 @@ pub fn pin_unblock(ctx: &mut Context<'_>) -> anyhow::Result<()> {
   })
 }
 
-fn print_pws_data(
-  ctx: &mut Context<'_>,
-  description: &'static str,
-  result: Result<String, nitrokey::Error>,
-  quiet: bool,
-) -> anyhow::Result<()> {
-  let value = result.context("Failed to access PWS slot")?;
-  if quiet {
-    println!(ctx, "{}", value)?;
-  } else {
-    println!(ctx, "{} {}", description, value)?;
-  }
-  Ok(())
-}
-
 /// Read a PWS slot.
 pub fn pws_get(
   ctx: &mut Context<'_>,
@@ -1106,17 +2192,24 @@ pub fn pws_get(
   quiet: bool,
 ) -> anyhow::Result<()> {
   with_password_safe(ctx, |ctx, pws| {
-    let slot = pws.get_slot(slot).context("Failed to access PWS slot")?;
-
     let show_all = !show_name && !show_login && !show_password;
-    if show_all || show_name {
-      print_pws_data(ctx, "name:    ", slot.get_name(), quiet)?;
-    }
-    if show_all || show_login {
-      print_pws_data(ctx, "login:   ", slot.get_login(), quiet)?;
-    }
-    if show_all || show_password {
-      print_pws_data(ctx, "password:", slot.get_password(), quiet)?;
+    let data = [
+      (show_name, "name:    ", api::PwsField::Name),
+      (show_login, "login:   ", api::PwsField::Login),
+      (show_password, "password:", api::PwsField::Password),
+    ]
+    .into_iter()
+    .filter(|(show, _, _)| show_all || *show)
+    .collect::<Vec<_>>();
+    let fields = data.iter().map(|(_, _, field)| *field).collect::<Vec<_>>();
+    let values = pws.slot_fields(slot, &fields)?;
+
+    for ((_, description, _), value) in data.iter().zip(values) {
+      if quiet {
+        println!(ctx, "{}", value)?;
+      } else {
+        println!(ctx, "{} {}", description, value)?;
+      }
     }
     Ok(())
   })
@@ -1129,13 +2222,13 @@ fn ensure_pws_string_lengths(
 ) -> anyhow::Result<()> {
   let mut data = Vec::new();
   if let Some(name) = name {
-    data.push(("slot name", name, PWS_NAME_LENGTH));
+    data.push(("slot name", name, api::PWS_NAME_LENGTH));
   }
   if let Some(login) = login {
-    data.push(("login", login, PWS_LOGIN_LENGTH));
+    data.push(("login", login, api::PWS_LOGIN_LENGTH));
   }
   if let Some(password) = password {
-    data.push(("password", password, PWS_PASSWORD_LENGTH));
+    data.push(("password", password, api::PWS_PASSWORD_LENGTH));
   }
   ensure_string_lengths(&data)
 }
@@ -1150,13 +2243,13 @@ pub fn pws_add(
 ) -> anyhow::Result<()> {
   let password = value_or_stdin(ctx, password)?;
   ensure_pws_string_lengths(Some(name), Some(login), Some(&password))?;
-  with_password_safe(ctx, |ctx, mut pws| {
-    let slots = pws.get_slots()?;
+  with_password_safe(ctx, |ctx, pws| {
+    let slots = pws.programmed_slots()?;
 
     let slot_idx = if let Some(slot_idx) = slot_idx {
       // If the user specified a slot, make sure that it is not programmed
-      if let Some(slot) = slots.get(usize::from(slot_idx)) {
-        if slot.is_some() {
+      if let Some(programmed) = slots.get(usize::from(slot_idx)) {
+        if *programmed {
           Err(anyhow::anyhow!(
             "The PWS slot {} is already programmed",
             slot_idx
@@ -1172,16 +2265,19 @@ pub fn pws_add(
       }
     } else {
       // If the user did not specify a slot, we try to find the first unprogrammed slot
-      if let Some(slot_idx) = slots.iter().position(Option::is_none) {
+      if let Some(slot_idx) = slots.iter().position(|programmed| !programmed) {
         u8::try_from(slot_idx).context("Unexpected number of PWS slots")
       } else {
         Err(anyhow::anyhow!("All PWS slots are already programmed"))
       }
     }?;
 
-    pws
-      .write_slot(slot_idx, name, login, password.as_ref())
-      .context("Failed to write PWS slot")?;
+    let slot = api::PwsSlot {
+      name: name.to_string(),
+      login: login.to_string(),
+      password: password.into_owned(),
+    };
+    pws.write_slot(slot_idx, &slot)?;
     println!(ctx, "Added PWS slot {}", slot_idx)?;
     Ok(())
   })
@@ -1202,64 +2298,163 @@ pub fn pws_update(
   let password = password.map(|s| value_or_stdin(ctx, s)).transpose()?;
   ensure_pws_string_lengths(name, login, password.as_deref())?;
 
-  with_password_safe(ctx, |_ctx, mut pws| {
-    let slot = pws.get_slot(slot_idx).context("Failed to query PWS slot")?;
-    let name = name
-      .map(|s| Ok(borrow::Cow::from(s)))
-      .unwrap_or_else(|| slot.get_name().map(borrow::Cow::from))
-      .context("Failed to query current slot name")?;
-    let login = login
-      .map(|s| Ok(borrow::Cow::from(s)))
-      .unwrap_or_else(|| slot.get_login().map(borrow::Cow::from))
-      .context("Failed to query current slot login")?;
-    let password = password
-      .as_ref()
-      .map(|s| Ok(borrow::Cow::from(s.as_ref())))
-      .unwrap_or_else(|| slot.get_password().map(borrow::Cow::from))
-      .context("Failed to query current slot password")?;
-    pws
-      .write_slot(slot_idx, name.as_ref(), login.as_ref(), password.as_ref())
-      .context("Failed to write PWS slot")
+  with_password_safe(ctx, |_ctx, pws| {
+    let current = pws.slot(slot_idx)?;
+    let slot = api::PwsSlot {
+      name: name.map(ToOwned::to_owned).unwrap_or(current.name),
+      login: login.map(ToOwned::to_owned).unwrap_or(current.login),
+      password: password
+        .map(borrow::Cow::into_owned)
+        .unwrap_or(current.password),
+    };
+    pws.write_slot(slot_idx, &slot)
   })
 }
 
 /// Clear a PWS slot.
 pub fn pws_clear(ctx: &mut Context<'_>, slot: u8) -> anyhow::Result<()> {
-  with_password_safe(ctx, |_ctx, mut pws| {
-    pws.erase_slot(slot).context("Failed to clear PWS slot")
-  })
-}
-
-fn print_pws_slot(
-  ctx: &mut Context<'_>,
-  index: usize,
-  slot: Option<nitrokey::PasswordSlot<'_, '_, '_>>,
-) -> anyhow::Result<()> {
-  let name = if let Some(slot) = slot {
-    slot.get_name().context("Failed to read PWS slot name")?
-  } else {
-    "[not programmed]".to_string()
-  };
-  println!(ctx, "{}\t{}", index, name)?;
-  Ok(())
+  with_password_safe(ctx, |_ctx, pws| pws.erase_slot(slot))
 }
 
 /// Print the status of all PWS slots.
 pub fn pws_status(ctx: &mut Context<'_>, all: bool) -> anyhow::Result<()> {
   with_password_safe(ctx, |ctx, pws| {
-    let slots = pws.get_slots().context("Failed to read PWS slot status")?;
+    let names = pws.slot_names()?;
     println!(ctx, "slot\tname")?;
-    for (i, &slot) in slots
-      .iter()
+    for (i, name) in names
+      .into_iter()
       .enumerate()
-      .filter(|(_, &slot)| all || slot.is_some())
+      .filter(|(_, name)| all || name.is_some())
     {
-      print_pws_slot(ctx, i, slot)?;
+      let name = name.unwrap_or_else(|| "[not programmed]".to_string());
+      println!(ctx, "{}\t{}", i, name)?;
     }
     Ok(())
   })
 }
 
+/// Return a String representation of the source of a setting.
+fn format_source(source: &config::Source, key: &str) -> String {
+  match source {
+    config::Source::Environment => {
+      format!("environment variable NITROCLI_{}", key.to_uppercase())
+    }
+    source => source.to_string(),
+  }
+}
+
+/// Print the effective nitrocli settings along with their sources.
+pub fn settings_show(ctx: &mut Context<'_>) -> anyhow::Result<()> {
+  let config = ctx.config.clone();
+  println!(ctx, "setting\tvalue\tsource")?;
+  for (key, value, source) in &collect_settings(&config) {
+    println!(ctx, "{}\t{}\t{}", key, value, format_source(source, key))?;
+  }
+  Ok(())
+}
+
+/// Collect the key, the formatted value, and the source of every
+/// setting of the given configuration.
+fn collect_settings(config: &config::Config) -> Vec<(&'static str, String, &config::Source)> {
+  let serial_numbers = if config.serial_numbers.is_empty() {
+    None
+  } else {
+    let serial_numbers = config
+      .serial_numbers
+      .iter()
+      .map(ToString::to_string)
+      .collect::<Vec<_>>();
+    Some(serial_numbers.join(","))
+  };
+  let interactive_extensions = if config.interactive_extensions.is_empty() {
+    None
+  } else {
+    Some(config.interactive_extensions.join(","))
+  };
+  let secret_extensions = if config.secret_extensions.is_empty() {
+    None
+  } else {
+    Some(config.secret_extensions.join(","))
+  };
+  vec![
+    ("model", format_option(config.model), &config.sources.model),
+    (
+      "serial_numbers",
+      format_option(serial_numbers),
+      &config.sources.serial_numbers,
+    ),
+    (
+      "usb_path",
+      format_option(config.usb_path.as_ref()),
+      &config.sources.usb_path,
+    ),
+    (
+      "no_cache",
+      config.no_cache.to_string(),
+      &config.sources.no_cache,
+    ),
+    (
+      "verbosity",
+      config.verbosity.to_string(),
+      &config.sources.verbosity,
+    ),
+    (
+      "interactive_extensions",
+      format_option(interactive_extensions),
+      &config.sources.interactive_extensions,
+    ),
+    (
+      "secret_extensions",
+      format_option(secret_extensions),
+      &config.sources.secret_extensions,
+    ),
+    (
+      "no_force_time",
+      config.no_force_time.to_string(),
+      &config.sources.no_force_time,
+    ),
+  ]
+}
+
+/// Check a configuration file for invalid values and unknown keys.
+pub fn settings_check(ctx: &mut Context<'_>, file: Option<path::PathBuf>) -> anyhow::Result<()> {
+  let path = match file {
+    Some(file) => file,
+    None => match env::var_os(crate::NITROCLI_CONFIG) {
+      Some(path) => path::PathBuf::from(path),
+      None => config::user_config_path()?,
+    },
+  };
+  let unknown_keys = config::find_unknown_keys(&path)?;
+  for key in &unknown_keys {
+    println!(ctx, "{}: unknown key '{}'", path.display(), key)?;
+  }
+  anyhow::ensure!(
+    unknown_keys.is_empty(),
+    "Configuration file '{}' contains unknown keys",
+    path.display()
+  );
+  Ok(())
+}
+
+/// Print the available extensions along with their metadata.
+pub fn extensions_list(ctx: &mut Context<'_>) -> anyhow::Result<()> {
+  let path = ctx.path.clone().unwrap_or_default();
+  let extensions = extensions::list(&path, ctx.extension_cache.as_deref())?;
+  println!(ctx, "extension\tversion\tdescription")?;
+  for extension in extensions {
+    let version = extension.metadata.version.as_deref().unwrap_or("unknown");
+    println!(
+      ctx,
+      "{}\t{}\t{}",
+      extension.name,
+      version,
+      extension.about()
+    )?;
+  }
+  Ok(())
+}
+
 /// Find and list all available extensions.
 ///
 /// The logic used in this function should use the same criteria as
@@ -1277,7 +2472,7 @@ pub(crate) fn discover_extensions(path_var: &ffi::OsStr) -> anyhow::Result<Vec<S
           if path.is_file() {
             let name = entry.file_name();
             let file = name.to_string_lossy();
-            if file.starts_with(NITROCLI_EXT_PREFIX) {
+            if file.starts_with(NITROCLI_EXT_PREFIX) && !is_extension_manifest(&path) {
               let mut file = file.into_owned();
               file.replace_range(..NITROCLI_EXT_PREFIX.len(), "");
               commands.push(file);
@@ -1294,6 +2489,17 @@ pub(crate) fn discover_extensions(path_var: &ffi::OsStr) -> anyhow::Result<Vec<S
   Ok(commands)
 }
 
+/// Check whether the file at the given path is the manifest of an
+/// extension, i.e., whether it has the manifest suffix and an
+/// executable next to it.
+fn is_extension_manifest(path: &path::Path) -> bool {
+  let path = path.as_os_str().to_string_lossy();
+  match path.strip_suffix(EXTENSION_MANIFEST_SUFFIX) {
+    Some(ext_path) => path::Path::new(ext_path).is_file(),
+    None => false,
+  }
+}
+
 /// Resolve an extension provided by name to an actual path.
 ///
 /// Extensions are (executable) files that have the "nitrocli-" prefix
@@ -1337,6 +2543,181 @@ pub(crate) fn resolve_extension(
   Err(io::Error::new(io::ErrorKind::NotFound, err).into())
 }
 
+/// Handle a single request for a secret by an extension.
+///
+/// A request is either the name of a secret, which is answered with its
+/// value, or "reject" followed by the name of a secret, which is
+/// answered with whether the secret may be requested again.
+fn handle_secret_request<A>(
+  auth: &mut A,
+  request: &str,
+  rejected: &mut Vec<api::Secret>,
+) -> anyhow::Result<String>
+where
+  A: api::Authenticator,
+{
+  let (reject, name) = match request.strip_prefix("reject ") {
+    Some(name) => (true, name),
+    None => (false, request),
+  };
+  let secret = match name {
+    "user-pin" => api::Secret::UserPin,
+    "admin-pin" => api::Secret::AdminPin,
+    _ => anyhow::bail!("Unsupported secret request: {}", request),
+  };
+
+  if reject {
+    let retry = auth.reject(secret)?;
+    if retry && !rejected.contains(&secret) {
+      rejected.push(secret);
+    }
+    Ok(retry.to_string())
+  } else {
+    let error = if rejected.contains(&secret) {
+      Some("Wrong password, please reenter")
+    } else {
+      None
+    };
+    auth.secret(secret, error)
+  }
+}
+
+/// Serve secret requests of an extension until it closes its end of
+/// the channel.
+///
+/// Every request is a line and answered by a line consisting of "ok"
+/// or "err", a space, and the result or an error message, respectively.
+fn serve_secrets<A>(stream: net::UnixStream, mut auth: A)
+where
+  A: api::Authenticator,
+{
+  let mut rejected = Vec::new();
+  let mut writer = &stream;
+  for request in io::BufReader::new(&stream).lines() {
+    let request = match request {
+      Ok(request) => request,
+      Err(_) => break,
+    };
+    let response = match handle_secret_request(&mut auth, &request, &mut rejected) {
+      Ok(value) => format!("ok {}\n", value),
+      Err(err) => format!("err {}\n", format!("{:#}", err).replace('\n', " ")),
+    };
+    if writer.write_all(response.as_bytes()).is_err() {
+      break;
+    }
+  }
+}
+
+/// Provide the extension about to be started by the given command with
+/// a channel for requesting secrets.
+///
+/// The returned stream is the extension's end of the channel. It has to
+/// stay open until the extension has been spawned and is meant to be
+/// dropped once the extension has exited, as the channel is served for
+/// as long as any copy of it is open.
+fn offer_secrets<A>(cmd: &mut process::Command, auth: A) -> anyhow::Result<net::UnixStream>
+where
+  A: api::Authenticator + Send + 'static,
+{
+  let (ours, theirs) = net::UnixStream::pair().context("Failed to create secret channel")?;
+  let fd = theirs.as_raw_fd();
+  let _ = cmd.env(crate::NITROCLI_SECRET_FD, fd.to_string());
+  // The standard library creates the descriptors with close-on-exec
+  // set, so we have to clear the flag for the extension to inherit the
+  // descriptor. Only async-signal-safe functions are used in between
+  // fork and exec.
+  unsafe {
+    let _ = cmd.pre_exec(move || {
+      let flags = libc::fcntl(fd, libc::F_GETFD);
+      if flags < 0 || libc::fcntl(fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) < 0 {
+        Err(io::Error::last_os_error())
+      } else {
+        Ok(())
+      }
+    });
+  }
+
+  // The thread ends once the returned stream has been dropped and the
+  // extension as well as any process it passed the descriptor on to
+  // have exited.
+  let _ = thread::spawn(move || serve_secrets(ours, auth));
+  Ok(theirs)
+}
+
+/// Metadata an extension may provide in a TOML file next to its
+/// executable, named like the executable with a `.toml` suffix.
+///
+/// Extensions without a manifest may print the same data when invoked
+/// with the `--nitrocli-describe` argument.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ExtensionManifest {
+  /// A short description of the extension.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+  /// The version of the extension.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub version: Option<String>,
+  /// The minimum version of nitrocli the extension works with.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub min_nitrocli_version: Option<String>,
+  /// Whether to run the extension with the standard streams inherited.
+  ///
+  /// This flag is only honored in the manifest, as we do not want to
+  /// run the extension an additional time for every invocation.
+  #[serde(default)]
+  pub interactive: bool,
+}
+
+impl ExtensionManifest {
+  /// Check whether this version of nitrocli satisfies the extension's
+  /// minimum version requirement.
+  pub fn is_compatible(&self) -> bool {
+    match &self.min_nitrocli_version {
+      Some(min) => parse_version(env!("CARGO_PKG_VERSION")) >= parse_version(min),
+      None => true,
+    }
+  }
+}
+
+/// Parse a version string into its numeric components.
+///
+/// Components are compared numerically and anything following the
+/// digits of a component (such as a pre-release tag) is ignored.
+fn parse_version(version: &str) -> Vec<u64> {
+  version
+    .split('.')
+    .map(|part| {
+      let digits = part.split(|c: char| !c.is_ascii_digit()).next();
+      digits.and_then(|digits| digits.parse().ok()).unwrap_or(0)
+    })
+    .collect()
+}
+
+/// Retrieve the path of the manifest of the extension at the given
+/// path.
+pub(crate) fn extension_manifest_path(ext_path: &path::Path) -> path::PathBuf {
+  let mut path = ext_path.as_os_str().to_os_string();
+  path.push(EXTENSION_MANIFEST_SUFFIX);
+  path::PathBuf::from(path)
+}
+
+/// Read the manifest of the extension at the given path, if any.
+pub(crate) fn read_extension_manifest(
+  ext_path: &path::Path,
+) -> anyhow::Result<Option<ExtensionManifest>> {
+  let path = extension_manifest_path(ext_path);
+  match fs::read_to_string(&path) {
+    Ok(s) => toml::from_str(&s)
+      .map(Some)
+      .with_context(|| format!("Failed to parse extension manifest {}", path.display())),
+    Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+    Err(err) => {
+      Err(err).with_context(|| format!("Failed to read extension manifest {}", path.display()))
+    }
+  }
+}
+
 /// Run an extension.
 pub fn extension(ctx: &mut Context<'_>, args: Vec<ffi::OsString>) -> anyhow::Result<()> {
   // Note that while `Command` would actually honor PATH by itself, we
@@ -1355,10 +2736,49 @@ pub fn extension(ctx: &mut Context<'_>, args: Vec<ffi::OsString>) -> anyhow::Res
   // a cargo test context.
   let mut cmd = process::Command::new(&ext_path);
 
-  if let Ok(device_info) = find_device(&ctx.config) {
-    let _ = cmd.env(crate::NITROCLI_RESOLVED_USB_PATH, device_info.path);
+  let device_info = find_device(&ctx.config).ok();
+  if let Some(device_info) = &device_info {
+    let _ = cmd.env(crate::NITROCLI_RESOLVED_USB_PATH, &device_info.path);
+
+    let model = device_info
+      .model
+      .and_then(|model| args::DeviceModel::try_from(model).ok());
+    if let Some(model) = model {
+      let _ = cmd.env(crate::NITROCLI_RESOLVED_MODEL, model.to_string());
+    }
+    if let Some(serial_number) = device_info.serial_number {
+      let _ = cmd.env(
+        crate::NITROCLI_RESOLVED_SERIAL_NUMBER,
+        serial_number.to_string(),
+      );
+      if let Some(stamp) = otp_stamp(ctx, serial_number) {
+        let _ = cmd.env(crate::NITROCLI_OTP_STAMP, stamp);
+      }
+    }
   }
 
+  // Any executable in PATH can pose as an extension, so only those
+  // the user trusts with their PINs get to request them. Secrets are
+  // always specific to a device, so we can only provide them if we
+  // know which one the extension is going to work with.
+  let trusted = ctx
+    .config
+    .secret_extensions
+    .iter()
+    .any(|name| ext_name == name.as_str());
+  let secret_channel = match &device_info {
+    Some(nitrokey::DeviceInfo {
+      model: Some(model),
+      serial_number: Some(serial_number),
+      ..
+    }) if trusted => {
+      let auth = pinentry::Authenticator::new(ctx, *model, *serial_number)
+        .requested_by(&ext_name.to_string_lossy());
+      Some(offer_secrets(&mut cmd, auth)?)
+    }
+    _ => None,
+  };
+
   if let Some(model) = ctx.config.model {
     let _ = cmd.env(crate::NITROCLI_MODEL, model.to_string());
   }
@@ -1378,20 +2798,53 @@ pub fn extension(ctx: &mut Context<'_>, args: Vec<ffi::OsString>) -> anyhow::Res
     .collect::<Vec<_>>()
     .join(",");
 
-  let out = cmd
+  let _ = cmd
     .env(crate::NITROCLI_BINARY, binary)
     .env(crate::NITROCLI_VERBOSITY, ctx.config.verbosity.to_string())
     .env(crate::NITROCLI_NO_CACHE, ctx.config.no_cache.to_string())
+    .env(
+      crate::NITROCLI_NO_FORCE_TIME,
+      ctx.config.no_force_time.to_string(),
+    )
     .env(crate::NITROCLI_SERIAL_NUMBERS, serial_numbers)
-    .args(args)
-    .output()
-    .with_context(|| format!("Failed to execute extension {}", ext_path.display()))?;
-  ctx.stdout.write_all(&out.stdout)?;
-  ctx.stderr.write_all(&out.stderr)?;
+    .args(args);
+
+  let manifest = read_extension_manifest(&ext_path)?.unwrap_or_default();
+  if !manifest.is_compatible() {
+    anyhow::bail!(
+      "Extension {} requires nitrocli {} or newer",
+      ext_name.to_string_lossy(),
+      manifest.min_nitrocli_version.unwrap_or_default()
+    );
+  }
+
+  let interactive = manifest.interactive
+    || ctx
+      .config
+      .interactive_extensions
+      .iter()
+      .any(|name| ext_name == name.as_str());
+  let status = if interactive {
+    // Interactive extensions get to use the terminal directly, which
+    // means that their output bypasses our execution context.
+    cmd
+      .status()
+      .with_context(|| format!("Failed to execute extension {}", ext_path.display()))?
+  } else {
+    let out = cmd
+      .output()
+      .with_context(|| format!("Failed to execute extension {}", ext_path.display()))?;
+    ctx.stdout.write_all(&out.stdout)?;
+    ctx.stderr.write_all(&out.stderr)?;
+    out.status
+  };
 
-  if out.status.success() {
+  // The extension has exited, so the channel has served its purpose.
+  drop(secret_channel);
+
+  if status.success() {
     Ok(())
-  } else if let Some(rc) = out.status.code() {
+  } else if let Some(rc) = status.code() {
     Err(anyhow::Error::new(crate::DirectExitError(rc)))
   } else {
     Err(anyhow::Error::new(crate::DirectExitError(1)))
@@ -1402,6 +2855,102 @@ pub fn extension(ctx: &mut Context<'_>, args: Vec<ffi::OsString>) -> anyhow::Res
 mod tests {
   use super::*;
 
+  #[test]
+  fn version_compatibility() {
+    let manifest = |min: &str| ExtensionManifest {
+      min_nitrocli_version: Some(min.to_string()),
+      ..Default::default()
+    };
+    assert!(ExtensionManifest::default().is_compatible());
+    assert!(manifest("0.1").is_compatible());
+    assert!(manifest(env!("CARGO_PKG_VERSION")).is_compatible());
+    assert!(!manifest("999.0.0").is_compatible());
+    assert!(!manifest("0.99.0-rc1").is_compatible());
+    assert!(parse_version("0.10.0") > parse_version("0.9.9"));
+  }
+
+  #[test]
+  fn extension_manifest_detection() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("nitrocli-ext"), "")?;
+    fs::write(dir.path().join("nitrocli-ext.toml"), "")?;
+    fs::write(dir.path().join("nitrocli-other.toml"), "")?;
+
+    assert!(is_extension_manifest(&dir.path().join("nitrocli-ext.toml")));
+    assert!(!is_extension_manifest(&dir.path().join("nitrocli-ext")));
+    // Without an executable next to it, the file is an extension.
+    assert!(!is_extension_manifest(
+      &dir.path().join("nitrocli-other.toml")
+    ));
+
+    let path = dir.path().as_os_str();
+    let mut extensions = discover_extensions(path)?;
+    extensions.sort();
+    assert_eq!(extensions, vec!["ext", "other.toml"]);
+    Ok(())
+  }
+
+  /// An authenticator providing a fixed user PIN.
+  struct TestAuthenticator;
+
+  impl api::Authenticator for TestAuthenticator {
+    fn secret(&mut self, secret: api::Secret, error: Option<&str>) -> anyhow::Result<String> {
+      match (secret, error) {
+        (api::Secret::UserPin, None) => Ok("123456".to_string()),
+        (api::Secret::UserPin, Some(error)) => Ok(error.to_string()),
+        _ => anyhow::bail!("No PIN\navailable"),
+      }
+    }
+
+    fn reject(&mut self, secret: api::Secret) -> anyhow::Result<bool> {
+      Ok(secret == api::Secret::UserPin)
+    }
+  }
+
+  #[test]
+  fn secret_requests() {
+    let (ours, theirs) = net::UnixStream::pair().unwrap();
+    let server = thread::spawn(move || serve_secrets(ours, TestAuthenticator));
+
+    let mut reader = io::BufReader::new(&theirs);
+    let mut request = |request: &str| {
+      let mut writer = &theirs;
+      writer
+        .write_all(format!("{}\n", request).as_bytes())
+        .unwrap();
+      let mut response = String::new();
+      let _ = reader.read_line(&mut response).unwrap();
+      response
+    };
+
+    assert_eq!(request("user-pin"), "ok 123456\n");
+    assert_eq!(request("reject admin-pin"), "ok false\n");
+    assert_eq!(request("admin-pin"), "err No PIN available\n");
+    assert_eq!(request("reject user-pin"), "ok true\n");
+    assert_eq!(request("user-pin"), "ok Wrong password, please reenter\n");
+    assert_eq!(
+      request("password"),
+      "err Unsupported secret request: password\n"
+    );
+
+    drop(theirs);
+    server.join().unwrap();
+  }
+
+  #[test]
+  fn secret_channel_inheritance() -> anyhow::Result<()> {
+    let mut cmd = process::Command::new("sh");
+    let script = "echo user-pin >&$NITROCLI_SECRET_FD; head -n1 <&$NITROCLI_SECRET_FD";
+    let _ = cmd.args(["-c", script].iter());
+    let theirs = offer_secrets(&mut cmd, TestAuthenticator)?;
+    let out = cmd.output()?;
+    drop(theirs);
+
+    assert!(out.status.success(), "{:?}", out);
+    assert_eq!(out.stdout, b"ok 123456\n");
+    Ok(())
+  }
+
   #[test]
   fn prepare_secret_ascii() {
     let result = prepare_ascii_secret("12345678901234567890");
@@ -1428,10 +2977,218 @@ mod tests {
     assert_eq!(result, result2);
   }
 
+  #[test]
+  fn decode_hex_secret() {
+    assert_eq!(decode_hex("").unwrap(), Vec::<u8>::new());
+    assert_eq!(decode_hex("00ff7A").unwrap(), vec![0x00, 0xff, 0x7a]);
+    assert!(decode_hex("123").is_err());
+    assert!(decode_hex("zz").is_err());
+    assert!(decode_hex("\u{e9}").is_err());
+
+    let secret = prepare_secret("gezdgnbvgy3tqojq".into(), args::OtpSecretFormat::Base32).unwrap();
+    assert_eq!(decode_hex(&secret).unwrap(), b"1234567890");
+  }
+
+  #[test]
+  fn check_status_thresholds() {
+    use std::str::FromStr as _;
+
+    let mut status = api::Status {
+      model: nitrokey::Model::Pro,
+      serial_number: nitrokey::SerialNumber::from_str("0x1234").unwrap(),
+      firmware_version: nitrokey::FirmwareVersion {
+        major: 0,
+        minor: 15,
+      },
+      user_retry_count: 3,
+      admin_retry_count: 3,
+      storage: None,
+      production_info: None,
+    };
+    let args = args::StatusArgs {
+      check: true,
+      min_user_retries: None,
+      min_admin_retries: Some(2),
+      firmware: Some(args::FirmwareState::Locked),
+      require_stick_initialized: true,
+      require_sd_filled: true,
+      format: args::StatusFormat::Text,
+      output: None,
+      count_pws: false,
+      production_info: false,
+    };
+
+    let (state, summary) = check_status(&status, &args);
+    assert_eq!(state, CheckState::Ok);
+    assert_eq!(
+      summary,
+      "Nitrokey Pro 0x00001234: all checks passed | user_retry_count=3 admin_retry_count=3"
+    );
+
+    status.user_retry_count = 2;
+    status.admin_retry_count = 2;
+    let (state, summary) = check_status(&status, &args);
+    assert_eq!(state, CheckState::Warning);
+    assert!(
+      summary.contains(": user retry count 2 below 3 |"),
+      "{}",
+      summary
+    );
+
+    status.admin_retry_count = 0;
+    let (state, summary) = check_status(&status, &args);
+    assert_eq!(state, CheckState::Critical);
+    assert!(
+      summary.contains(": user retry count 2 below 3, admin PIN blocked |"),
+      "{}",
+      summary
+    );
+  }
+
+  #[test]
+  fn prometheus_status() -> anyhow::Result<()> {
+    use std::str::FromStr as _;
+
+    let status = api::Status {
+      model: nitrokey::Model::Pro,
+      serial_number: nitrokey::SerialNumber::from_str("0x1234").unwrap(),
+      firmware_version: nitrokey::FirmwareVersion {
+        major: 0,
+        minor: 15,
+      },
+      user_retry_count: 3,
+      admin_retry_count: 2,
+      storage: None,
+      production_info: None,
+    };
+    let slots = SlotCounts {
+      hotp: 1,
+      totp: 4,
+      pws: None,
+    };
+
+    let mut out = Vec::new();
+    print_status_prometheus(&mut out, &status, &slots)?;
+    let out = String::from_utf8(out)?;
+
+    let labels = r#"model="pro",serial_number="0x00001234""#;
+    for line in &[
+      "# TYPE nitrokey_user_retry_count gauge".to_string(),
+      format!(
+        r#"nitrokey_firmware_version_info{{{},version="v0.15"}} 1"#,
+        labels
+      ),
+      format!("nitrokey_user_retry_count{{{}}} 3", labels),
+      format!("nitrokey_admin_retry_count{{{}}} 2", labels),
+      format!(
+        r#"nitrokey_otp_slots_programmed{{{},algorithm="hotp"}} 1"#,
+        labels
+      ),
+      format!(
+        r#"nitrokey_otp_slots_programmed{{{},algorithm="totp"}} 4"#,
+        labels
+      ),
+    ] {
+      assert!(out.lines().any(|l| l == line), "{} not in:\n{}", line, out);
+    }
+    assert!(!out.contains("pws_slots_programmed"), "{}", out);
+    assert!(!out.contains("volume_active"), "{}", out);
+    Ok(())
+  }
+
+  #[test]
+  fn production_info_output() -> anyhow::Result<()> {
+    use std::str::FromStr as _;
+
+    let firmware_version = nitrokey::FirmwareVersion {
+      major: 0,
+      minor: 54,
+    };
+    let status = api::Status {
+      model: nitrokey::Model::Storage,
+      serial_number: nitrokey::SerialNumber::from_str("0x1234").unwrap(),
+      firmware_version,
+      user_retry_count: 3,
+      admin_retry_count: 3,
+      storage: None,
+      production_info: Some(nitrokey::StorageProductionInfo {
+        firmware_version,
+        firmware_version_internal: 2,
+        serial_number_cpu: 0xdeadbeef,
+        sd_card: nitrokey::SdCardData {
+          serial_number: 0x1234,
+          size: 16,
+          manufacturing_year: 17,
+          manufacturing_month: 8,
+          oem: 0x534d,
+          manufacturer: 0x1b,
+        },
+      }),
+    };
+
+    let mut out = Vec::new();
+    print_production_info(&mut out, status.production_info.as_ref().unwrap())?;
+    let out = String::from_utf8(out)?;
+    assert_eq!(
+      out,
+      r#"    production info:
+      firmware version:  v0.54 (internal 2)
+      CPU ID:            0xdeadbeef
+      SD card:
+        size:            16 GB
+        manufacturer:    0x1b
+        OEM:             0x534d
+        manufactured:    2017-08
+"#
+    );
+
+    let slots = SlotCounts {
+      hotp: 0,
+      totp: 0,
+      pws: None,
+    };
+    let mut out = Vec::new();
+    print_status_prometheus(&mut out, &status, &slots)?;
+    let out = String::from_utf8(out)?;
+    let labels = r#"model="storage",serial_number="0x00001234""#;
+    for line in &[
+      format!(
+        r#"nitrokey_production_info{{{},firmware_version="v0.54",firmware_version_internal="2",cpu_id="0xdeadbeef",sd_card_manufacturer="0x1b",sd_card_oem="0x534d",sd_card_manufacturing_date="2017-08"}} 1"#,
+        labels
+      ),
+      format!("nitrokey_sd_card_size_bytes{{{}}} 16000000000", labels),
+    ] {
+      assert!(out.lines().any(|l| l == line), "{} not in:\n{}", line, out);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn atomic_file_write() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("nitrokey.prom");
+
+    write_file_atomically(&path, b"first")?;
+    write_file_atomically(&path, b"second")?;
+    assert_eq!(fs::read_to_string(&path)?, "second");
+    assert_eq!(fs::read_dir(dir.path())?.count(), 1);
+    Ok(())
+  }
+
   #[test]
   fn hex_string() {
     assert_eq!(format_bytes(b" "), "20");
     assert_eq!(format_bytes(b"  "), "2020");
     assert_eq!(format_bytes(b"\n\n"), "0a0a");
   }
+
+  #[test]
+  fn settings_keys() {
+    let config = config::Config::default();
+    let keys = collect_settings(&config)
+      .into_iter()
+      .map(|(key, _, _)| key)
+      .collect::<Vec<_>>();
+    assert_eq!(keys, config::config_keys());
+  }
 }
@@ -3,7 +3,11 @@
 // Copyright (C) 2020 The Nitrocli Developers
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use std::collections;
+use std::env;
+use std::fmt;
 use std::fs;
+use std::io;
 use std::path;
 use std::str::FromStr as _;
 
@@ -22,6 +26,56 @@ use anyhow::Context as _;
 /// (defaults to `$HOME/.config/nitrocli`).
 const CONFIG_FILE: &str = "config.toml";
 
+/// The directory containing the system-wide configuration file.
+const SYSTEM_CONFIG_DIR: &str = "/etc/nitrocli";
+
+/// The name of the directory containing drop-in configuration files,
+/// relative to the system configuration directory.
+const DROP_IN_DIR: &str = "config.d";
+
+/// The origin of a configuration value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+  /// The built-in default value.
+  Default,
+  /// A configuration file at the given path.
+  File(path::PathBuf),
+  /// The `NITROCLI_*` environment variable for the value.
+  Environment,
+  /// A command-line option.
+  CommandLine,
+}
+
+impl Default for Source {
+  fn default() -> Self {
+    Source::Default
+  }
+}
+
+impl fmt::Display for Source {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Source::Default => write!(f, "default"),
+      Source::File(path) => write!(f, "file {}", path.display()),
+      Source::Environment => write!(f, "environment"),
+      Source::CommandLine => write!(f, "command line"),
+    }
+  }
+}
+
+/// The origins of the values of a `Config`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Sources {
+  pub model: Source,
+  pub serial_numbers: Source,
+  pub usb_path: Source,
+  pub no_cache: Source,
+  pub verbosity: Source,
+  pub interactive_extensions: Source,
+  pub secret_extensions: Source,
+  pub no_force_time: Source,
+}
+
 /// The configuration for nitrocli, usually read from configuration
 /// files and environment variables.
 #[derive(Clone, Debug, Default, PartialEq, merge::Merge, serde::Deserialize)]
@@ -42,6 +96,23 @@ pub struct Config {
   #[merge(strategy = merge::num::overwrite_zero)]
   #[serde(default)]
   pub verbosity: u8,
+  /// The extensions to run with the standard streams inherited.
+  #[merge(strategy = merge::vec::overwrite_empty)]
+  #[serde(default)]
+  pub interactive_extensions: Vec<String>,
+  /// The extensions to provide with a channel for requesting PINs.
+  #[merge(strategy = merge::vec::overwrite_empty)]
+  #[serde(default)]
+  pub secret_extensions: Vec<String>,
+  /// Whether to refuse setting the device time for TOTP generation if
+  /// that would move it backwards.
+  #[merge(strategy = merge::bool::overwrite_false)]
+  #[serde(default)]
+  pub no_force_time: bool,
+  /// The origin of each of the above values.
+  #[merge(skip)]
+  #[serde(skip)]
+  pub sources: Sources,
 }
 
 fn deserialize_serial_number_vec<'de, D>(d: D) -> Result<Vec<nitrokey::SerialNumber>, D::Error>
@@ -57,52 +128,168 @@ where
 }
 
 impl Config {
+  /// Load the configuration from the configuration files and the
+  /// environment.
+  ///
+  /// In order of increasing precedence, the configuration is read from
+  /// the system-wide configuration file, the system-wide drop-in files,
+  /// the user configuration file, and the `NITROCLI_*` environment
+  /// variables. If the `NITROCLI_CONFIG` environment variable is set,
+  /// only the file it points to is read instead of all the
+  /// aforementioned configuration files.
   pub fn load() -> anyhow::Result<Self> {
-    use merge::Merge as _;
+    let files = if let Some(path) = env::var_os(crate::NITROCLI_CONFIG) {
+      vec![path::PathBuf::from(path)]
+    } else {
+      config_files(path::Path::new(SYSTEM_CONFIG_DIR), user_config_path()?)?
+    };
 
     let mut config = Config::default();
-    if let Some(user_config) = load_user_config()? {
-      config.merge(user_config);
-    }
-    config.merge(load_env_config()?);
+    let (env_config, env_keys) = load_env_config()?;
+    config.merge_from(env_config, &env_keys, Source::Environment);
+    config.merge_files(&files)?;
 
     Ok(config)
   }
 
+  /// Merge the configuration files at the given paths into this
+  /// configuration.
+  ///
+  /// The files are expected to be ordered by increasing precedence.
+  fn merge_files(&mut self, files: &[path::PathBuf]) -> anyhow::Result<()> {
+    // `Merge` keeps values that are already set, so we have to work our
+    // way from the highest to the lowest precedence.
+    for path in files.iter().rev() {
+      let config = read_config_file(path)?;
+      let keys = read_config_keys(path)?;
+      self.merge_from(config, &keys, Source::File(path.clone()));
+    }
+    Ok(())
+  }
+
+  /// Merge another configuration into this one, recording the given
+  /// source for every value that it sets.
+  ///
+  /// `keys` are the keys that are explicitly set in `other`.  A key
+  /// counts as set by `other` if its value changed in the process or
+  /// if no other source set it before, i.e., explicitly setting a key
+  /// to its default value is attributed to `source`, too.
+  fn merge_from(&mut self, other: Config, keys: &collections::HashSet<String>, source: Source) {
+    use merge::Merge as _;
+
+    let before = self.clone();
+    self.merge(other);
+
+    macro_rules! record_sources {
+      ( $($key:ident),* ) => {
+        $(
+          if keys.contains(stringify!($key))
+            && (self.$key != before.$key || self.sources.$key == Source::Default)
+          {
+            self.sources.$key = source.clone();
+          }
+        )*
+      };
+    }
+
+    record_sources!(
+      model,
+      serial_numbers,
+      usb_path,
+      no_cache,
+      verbosity,
+      interactive_extensions,
+      secret_extensions,
+      no_force_time
+    );
+  }
+
   pub fn update(&mut self, args: &args::Args) {
     if args.model.is_some() {
       self.model = args.model;
+      self.sources.model = Source::CommandLine;
     }
     if !args.serial_numbers.is_empty() {
       self.serial_numbers = args.serial_numbers.clone();
+      self.sources.serial_numbers = Source::CommandLine;
     }
     if args.usb_path.is_some() {
       self.usb_path = args.usb_path.clone();
+      self.sources.usb_path = Source::CommandLine;
     }
     if args.no_cache {
       self.no_cache = true;
+      self.sources.no_cache = Source::CommandLine;
     }
     if args.verbose > 0 {
       self.verbosity = args.verbose;
+      self.sources.verbosity = Source::CommandLine;
     }
   }
 }
 
-fn load_user_config() -> anyhow::Result<Option<Config>> {
+/// Retrieve the path to the user's configuration file.
+pub fn user_config_path() -> anyhow::Result<path::PathBuf> {
   let project_dirs = directories::ProjectDirs::from("", "", "nitrocli")
     .context("Could not determine the nitrocli application directory")?;
-  let path = project_dirs.config_dir().join(CONFIG_FILE);
-  if path.is_file() {
-    read_config_file(&path).map(Some)
-  } else {
-    Ok(None)
+  Ok(project_dirs.config_dir().join(CONFIG_FILE))
+}
+
+/// Retrieve the paths of all existing configuration files, ordered by
+/// increasing precedence.
+fn config_files(
+  system_dir: &path::Path,
+  user_path: path::PathBuf,
+) -> anyhow::Result<Vec<path::PathBuf>> {
+  let mut files = vec![system_dir.join(CONFIG_FILE)];
+  files.append(&mut drop_in_files(&system_dir.join(DROP_IN_DIR))?);
+  files.push(user_path);
+  files.retain(|path| path.is_file());
+  Ok(files)
+}
+
+/// Retrieve the paths of all `*.toml` files in the given drop-in
+/// directory, sorted by name.
+fn drop_in_files(dir: &path::Path) -> anyhow::Result<Vec<path::PathBuf>> {
+  let entries = match fs::read_dir(dir) {
+    Ok(entries) => entries,
+    Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+    Err(err) => {
+      return Err(err)
+        .with_context(|| format!("Failed to read configuration directory '{}'", dir.display()))
+    }
+  };
+
+  let mut files = Vec::new();
+  for entry in entries {
+    let path = entry
+      .with_context(|| format!("Failed to read configuration directory '{}'", dir.display()))?
+      .path();
+    if path.extension() == Some("toml".as_ref()) {
+      files.push(path);
+    }
   }
+  files.sort();
+  Ok(files)
 }
 
-fn load_env_config() -> anyhow::Result<Config> {
-  envy::prefixed("NITROCLI_")
+/// Load the configuration from the `NITROCLI_*` environment variables,
+/// along with the keys that they set.
+fn load_env_config() -> anyhow::Result<(Config, collections::HashSet<String>)> {
+  const PREFIX: &str = "NITROCLI_";
+
+  let config = envy::prefixed(PREFIX)
     .from_env()
-    .context("Failed to parse environment variables")
+    .context("Failed to parse environment variables")?;
+  let keys = env::vars_os()
+    .filter_map(|(name, _)| {
+      name
+        .to_str()
+        .and_then(|name| name.strip_prefix(PREFIX))
+        .map(str::to_lowercase)
+    })
+    .collect();
+  Ok((config, keys))
 }
 
 pub fn read_config_file(path: &path::Path) -> anyhow::Result<Config> {
@@ -111,3 +298,165 @@ pub fn read_config_file(path: &path::Path) -> anyhow::Result<Config> {
   toml::from_str(&s)
     .with_context(|| format!("Failed to parse configuration file '{}'", path.display()))
 }
+
+/// Read the keys that are set in the configuration file at the given
+/// path.
+fn read_config_keys(path: &path::Path) -> anyhow::Result<collections::HashSet<String>> {
+  let s = fs::read_to_string(path)
+    .with_context(|| format!("Failed to read configuration file '{}'", path.display()))?;
+  let table = toml::from_str::<toml::value::Table>(&s)
+    .with_context(|| format!("Failed to parse configuration file '{}'", path.display()))?;
+  Ok(table.into_iter().map(|(key, _)| key).collect())
+}
+
+/// A deserializer that does nothing but record the field names of the
+/// struct it is asked to deserialize.
+struct FieldNames<'f>(&'f mut &'static [&'static str]);
+
+impl<'de, 'f> serde::Deserializer<'de> for FieldNames<'f> {
+  type Error = serde::de::value::Error;
+
+  fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+  where
+    V: serde::de::Visitor<'de>,
+  {
+    Err(Self::Error::custom("expected a struct"))
+  }
+
+  fn deserialize_struct<V>(
+    self,
+    _name: &'static str,
+    fields: &'static [&'static str],
+    _visitor: V,
+  ) -> Result<V::Value, Self::Error>
+  where
+    V: serde::de::Visitor<'de>,
+  {
+    *self.0 = fields;
+    Err(Self::Error::custom("field names recorded"))
+  }
+
+  serde::forward_to_deserialize_any! {
+    bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+    bytes byte_buf option unit unit_struct newtype_struct seq tuple
+    tuple_struct map enum identifier ignored_any
+  }
+}
+
+/// Retrieve the keys that may be present in a configuration file.
+///
+/// The keys are derived from the fields of `Config` known to serde, so
+/// that they cannot get out of sync with the struct definition.
+pub fn config_keys() -> &'static [&'static str] {
+  let mut fields: &'static [&'static str] = &[];
+  // Deserialization always fails; all we are interested in is the
+  // recorded field names.
+  let _ = Config::deserialize(FieldNames(&mut fields));
+  fields
+}
+
+/// Read the configuration file at the given path and return the keys
+/// in it that nitrocli does not know about.
+pub fn find_unknown_keys(path: &path::Path) -> anyhow::Result<Vec<String>> {
+  // Parse the file as a `Config` first, to report invalid values.
+  let _ = read_config_file(path)?;
+
+  let keys = config_keys();
+  let mut unknown = read_config_keys(path)?
+    .into_iter()
+    .filter(|key| !keys.contains(&key.as_str()))
+    .collect::<Vec<_>>();
+  unknown.sort();
+  Ok(unknown)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn no_config_files() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let files = config_files(dir.path(), dir.path().join("user.toml"))?;
+    assert!(files.is_empty(), "{:?}", files);
+    Ok(())
+  }
+
+  #[test]
+  fn layered_config_files() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let drop_in_dir = dir.path().join(DROP_IN_DIR);
+    let system = dir.path().join(CONFIG_FILE);
+    let drop_in1 = drop_in_dir.join("10-model.toml");
+    let drop_in2 = drop_in_dir.join("20-usb-path.toml");
+    let user = dir.path().join("user.toml");
+
+    fs::create_dir(&drop_in_dir)?;
+    fs::write(
+      &system,
+      "model = \"pro\"\nusb_path = \"1\"\nverbosity = 1\n",
+    )?;
+    fs::write(&drop_in1, "model = \"storage\"\n")?;
+    fs::write(&drop_in2, "usb_path = \"2\"\n")?;
+    fs::write(drop_in_dir.join("ignored.conf"), "usb_path = \"3\"\n")?;
+    fs::write(&user, "usb_path = \"4\"\n")?;
+
+    let files = config_files(dir.path(), user.clone())?;
+    assert_eq!(
+      files,
+      vec![system.clone(), drop_in1.clone(), drop_in2, user.clone()]
+    );
+
+    let mut config = Config::default();
+    config.merge_files(&files)?;
+    assert_eq!(config.model, Some(args::DeviceModel::Storage));
+    assert_eq!(config.sources.model, Source::File(drop_in1));
+    assert_eq!(config.usb_path, Some("4".to_string()));
+    assert_eq!(config.sources.usb_path, Source::File(user));
+    assert_eq!(config.verbosity, 1);
+    assert_eq!(config.sources.verbosity, Source::File(system));
+    assert!(!config.no_cache);
+    assert_eq!(config.sources.no_cache, Source::Default);
+    Ok(())
+  }
+
+  #[test]
+  fn explicit_default_values() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let system = dir.path().join(CONFIG_FILE);
+    let user = dir.path().join("user.toml");
+
+    fs::write(&system, "no_cache = true\nverbosity = 2\n")?;
+    fs::write(&user, "verbosity = 0\nno_force_time = false\n")?;
+
+    let mut config = Config::default();
+    config.merge_files(&[system.clone(), user.clone()])?;
+    assert!(config.no_cache);
+    assert_eq!(config.sources.no_cache, Source::File(system.clone()));
+    // `Merge` cannot distinguish an explicit zero from an unset value,
+    // so the lower precedence value wins.
+    assert_eq!(config.verbosity, 2);
+    assert_eq!(config.sources.verbosity, Source::File(system));
+    assert!(!config.no_force_time);
+    assert_eq!(config.sources.no_force_time, Source::File(user));
+    assert_eq!(config.sources.model, Source::Default);
+    Ok(())
+  }
+
+  #[test]
+  fn keys_match_struct() {
+    assert_eq!(
+      config_keys(),
+      [
+        "model",
+        "serial_numbers",
+        "usb_path",
+        "no_cache",
+        "verbosity",
+        "interactive_extensions",
+        "secret_extensions",
+        "no_force_time",
+      ]
+    );
+  }
+}
@@ -0,0 +1,243 @@
+// extensions.rs
+
+// Copyright (C) 2026 The Nitrocli Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::env;
+use std::ffi;
+use std::fs;
+use std::io::Read as _;
+use std::path;
+use std::process;
+use std::thread;
+use std::time;
+
+use anyhow::Context as _;
+
+use crate::commands;
+use crate::commands::ExtensionManifest;
+
+/// The argument with which an extension is asked to print its metadata.
+const DESCRIBE_ARG: &str = "--nitrocli-describe";
+
+/// The time an extension has to print its metadata.
+const DESCRIBE_TIMEOUT: time::Duration = time::Duration::from_secs(2);
+
+/// An extension along with its metadata.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Extension {
+  /// The name of the extension, without the "nitrocli-" prefix.
+  pub name: String,
+  /// The path to the extension's executable.
+  pub path: path::PathBuf,
+  /// The modification time of the executable.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  modified: Option<u64>,
+  /// The modification time of the manifest, if any.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  manifest_modified: Option<u64>,
+  /// The extension's metadata.
+  pub metadata: ExtensionManifest,
+}
+
+impl Extension {
+  /// Retrieve the text describing the extension in the help output.
+  pub fn about(&self) -> String {
+    let mut about = match &self.metadata.description {
+      Some(description) => description.clone(),
+      None => format!("Run the {} extension", self.name),
+    };
+    if !self.metadata.is_compatible() {
+      if let Some(min) = &self.metadata.min_nitrocli_version {
+        about.push_str(&format!(" (requires nitrocli {} or newer)", min));
+      }
+    }
+    about
+  }
+
+  /// Check whether the files the extension was created from are
+  /// unchanged.
+  fn is_current(&self) -> bool {
+    self.modified == modification_time(&self.path)
+      && self.manifest_modified == modification_time(&commands::extension_manifest_path(&self.path))
+  }
+}
+
+/// A file system path along with its modification time.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+struct Stamp {
+  path: path::PathBuf,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  modified: Option<u64>,
+}
+
+impl Stamp {
+  fn new(path: path::PathBuf) -> Self {
+    let modified = modification_time(&path);
+    Self { path, modified }
+  }
+}
+
+/// The cached result of discovering extensions.
+///
+/// The cache is valid as long as `PATH` is the same and neither the
+/// directories it contains nor any of the discovered extensions have
+/// been modified. That way, we only have to inspect a few files
+/// instead of scanning directories and running extensions.
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+struct Cache {
+  /// The value of `PATH` the extensions were discovered with.
+  path: String,
+  /// The directories in `PATH`.
+  #[serde(default)]
+  dirs: Vec<Stamp>,
+  /// The discovered extensions.
+  #[serde(default)]
+  extensions: Vec<Extension>,
+}
+
+impl Cache {
+  fn is_valid(&self, path_var: &ffi::OsStr) -> bool {
+    self.path == path_var.to_string_lossy()
+      && self.dirs == dir_stamps(path_var)
+      && self.extensions.iter().all(Extension::is_current)
+  }
+}
+
+fn modification_time(path: &path::Path) -> Option<u64> {
+  let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+  let duration = modified.duration_since(time::UNIX_EPOCH).ok()?;
+  u64::try_from(duration.as_nanos()).ok()
+}
+
+fn dir_stamps(path_var: &ffi::OsStr) -> Vec<Stamp> {
+  env::split_paths(path_var).map(Stamp::new).collect()
+}
+
+/// Ask the extension at the given path for its metadata.
+///
+/// Extensions not supporting the protocol are expected to fail or
+/// print something else, in which case no metadata is available.
+fn describe(ext_path: &path::Path) -> ExtensionManifest {
+  let child = process::Command::new(ext_path)
+    .arg(DESCRIBE_ARG)
+    .stdin(process::Stdio::null())
+    .stdout(process::Stdio::piped())
+    .stderr(process::Stdio::null())
+    .spawn();
+  let mut child = match child {
+    Ok(child) => child,
+    Err(_) => return ExtensionManifest::default(),
+  };
+
+  let start = time::Instant::now();
+  let status = loop {
+    match child.try_wait() {
+      Ok(Some(status)) => break status,
+      Ok(None) if start.elapsed() < DESCRIBE_TIMEOUT => {
+        thread::sleep(time::Duration::from_millis(10))
+      }
+      _ => {
+        let _ = child.kill();
+        let _ = child.wait();
+        return ExtensionManifest::default();
+      }
+    }
+  };
+
+  let mut output = String::new();
+  match child.stdout.take() {
+    Some(mut stdout) if status.success() => {
+      if stdout.read_to_string(&mut output).is_err() {
+        return ExtensionManifest::default();
+      }
+    }
+    _ => return ExtensionManifest::default(),
+  }
+  toml::from_str(&output).unwrap_or_default()
+}
+
+/// Retrieve the metadata of the extension at the given path, preferring
+/// its manifest over asking the extension itself.
+fn metadata(ext_path: &path::Path) -> ExtensionManifest {
+  match commands::read_extension_manifest(ext_path) {
+    Ok(Some(metadata)) => metadata,
+    // A broken manifest should not prevent listing the extension.
+    Ok(None) | Err(_) => describe(ext_path),
+  }
+}
+
+/// Discover all extensions in `PATH` along with their metadata.
+fn scan(path_var: &ffi::OsStr, cache: Option<&Cache>) -> anyhow::Result<Vec<Extension>> {
+  let mut names = commands::discover_extensions(path_var)?;
+  names.sort();
+  names.dedup();
+
+  let mut extensions = Vec::with_capacity(names.len());
+  for name in names {
+    let path = commands::resolve_extension(path_var, ffi::OsStr::new(&name))?;
+    let modified = modification_time(&path);
+    let manifest_modified = modification_time(&commands::extension_manifest_path(&path));
+    // Only run extensions that changed since we last asked them.
+    let cached = cache.and_then(|cache| {
+      cache.extensions.iter().find(|ext| {
+        ext.path == path && ext.modified == modified && ext.manifest_modified == manifest_modified
+      })
+    });
+    let metadata = match cached {
+      Some(ext) => ext.metadata.clone(),
+      None => metadata(&path),
+    };
+    extensions.push(Extension {
+      name,
+      path,
+      modified,
+      manifest_modified,
+      metadata,
+    });
+  }
+  Ok(extensions)
+}
+
+fn load_cache(path: &path::Path) -> anyhow::Result<Cache> {
+  let s = fs::read_to_string(path).context("Failed to read extension cache")?;
+  toml::from_str(&s).context("Failed to parse extension cache")
+}
+
+fn save_cache(path: &path::Path, cache: &Cache) -> anyhow::Result<()> {
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent).context("Failed to create extension cache directory")?;
+  }
+  let data = toml::to_string(cache).context("Failed to serialize extension cache")?;
+  fs::write(path, data).context("Failed to write extension cache")
+}
+
+/// List all extensions available through the given `PATH`, sorted by
+/// name.
+///
+/// If a cache file is provided, the result is taken from it if it is
+/// still valid and it is updated otherwise.
+pub fn list(
+  path_var: &ffi::OsStr,
+  cache_file: Option<&path::Path>,
+) -> anyhow::Result<Vec<Extension>> {
+  // A missing or broken cache is simply recreated.
+  let cache = cache_file.and_then(|file| load_cache(file).ok());
+  if let Some(cache) = &cache {
+    if cache.is_valid(path_var) {
+      return Ok(cache.extensions.clone());
+    }
+  }
+
+  let extensions = scan(path_var, cache.as_ref())?;
+  if let Some(file) = cache_file {
+    let cache = Cache {
+      path: path_var.to_string_lossy().into_owned(),
+      dirs: dir_stamps(path_var),
+      extensions: extensions.clone(),
+    };
+    // Failing to update the cache only costs us time on the next run.
+    let _ = save_cache(file, &cache);
+  }
+  Ok(extensions)
+}
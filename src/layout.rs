@@ -0,0 +1,258 @@
+// layout.rs
+
+// Copyright (C) 2026 The Nitrocli Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops;
+use std::path;
+use std::str;
+
+use anyhow::Context as _;
+
+/// The name of the directory storing the hidden volume layouts,
+/// relative to the application data directory.
+const LAYOUT_DIR: &str = "hidden-volumes";
+
+/// The number of hidden volume slots of a Nitrokey Storage.
+pub const SLOT_COUNT: u8 = 4;
+
+const MIB: u64 = 1024 * 1024;
+const GIB: u64 = 1024 * MIB;
+
+/// A size or position on the SD card.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Size {
+  /// A percentage of the SD card size.
+  Percent(u8),
+  /// An absolute number of bytes.
+  Bytes(u64),
+}
+
+impl Size {
+  /// Convert this size into a percentage of an SD card with the given
+  /// size in bytes, rounding up if `round_up` is set and down
+  /// otherwise.
+  pub fn to_percent(self, sd_card_size: u64, round_up: bool) -> u64 {
+    match self {
+      Size::Percent(percent) => percent.into(),
+      Size::Bytes(bytes) => {
+        let percent = u128::from(bytes) * 100;
+        let size = u128::from(sd_card_size.max(1));
+        let result = if round_up {
+          (percent + size - 1) / size
+        } else {
+          percent / size
+        };
+        u64::try_from(result).unwrap_or(u64::MAX)
+      }
+    }
+  }
+}
+
+impl str::FromStr for Size {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let parse = |value: &str| {
+      value
+        .parse::<u64>()
+        .map_err(|err| format!("Invalid size '{}': {}", s, err))
+    };
+
+    if let Some(value) = s.strip_suffix('%') {
+      match parse(value)? {
+        percent @ 0..=100 => Ok(Size::Percent(percent as u8)),
+        _ => Err(format!("Invalid percentage '{}': must be at most 100%", s)),
+      }
+    } else if let Some(value) = s.strip_suffix("MiB") {
+      Ok(Size::Bytes(parse(value)?.saturating_mul(MIB)))
+    } else if let Some(value) = s.strip_suffix("GiB") {
+      Ok(Size::Bytes(parse(value)?.saturating_mul(GIB)))
+    } else {
+      Err(format!(
+        "Invalid size '{}': expected a percentage (e.g., 10%) or a size in MiB or GiB (e.g., 2GiB)",
+        s
+      ))
+    }
+  }
+}
+
+/// The range of a hidden volume slot, as percentages of the SD card.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct SlotRange {
+  pub slot: u8,
+  pub start: u8,
+  pub end: u8,
+}
+
+impl SlotRange {
+  /// Check whether this range overlaps with the given one.
+  pub fn overlaps(&self, other: &SlotRange) -> bool {
+    self.start < other.end && other.start < self.end
+  }
+
+  /// Check whether this range lies within the given range.
+  pub fn is_within(&self, range: &ops::Range<u8>) -> bool {
+    range.start <= self.start && self.end <= range.end
+  }
+}
+
+impl fmt::Display for SlotRange {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "slot {} ({}% .. {}%)", self.slot, self.start, self.end)
+  }
+}
+
+/// The locally recorded layout of the hidden volumes on an SD card.
+#[derive(Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Layout {
+  #[serde(default)]
+  pub slots: Vec<SlotRange>,
+}
+
+impl Layout {
+  /// Load the layout from the given file, or return an empty layout if
+  /// it does not exist.
+  pub fn load(path: &path::Path) -> anyhow::Result<Self> {
+    match fs::read_to_string(path) {
+      Ok(s) => toml::from_str(&s)
+        .with_context(|| format!("Failed to parse layout file '{}'", path.display())),
+      Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+      Err(err) => {
+        Err(err).with_context(|| format!("Failed to read layout file '{}'", path.display()))
+      }
+    }
+  }
+
+  /// Save the layout to the given file.
+  pub fn save(&self, path: &path::Path) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent).context("Failed to create layout directory")?;
+    }
+    let data = toml::to_vec(self).context("Failed to serialize layout")?;
+    fs::write(path, data)
+      .with_context(|| format!("Failed to write layout file '{}'", path.display()))
+  }
+
+  /// Find a slot other than the one of the given range that overlaps
+  /// with it.
+  pub fn find_overlap(&self, range: &SlotRange) -> Option<&SlotRange> {
+    self
+      .slots
+      .iter()
+      .find(|other| other.slot != range.slot && other.overlaps(range))
+  }
+
+  /// Set the range of a slot, replacing any previously recorded range
+  /// for it.
+  pub fn set(&mut self, range: SlotRange) {
+    self.slots.retain(|other| other.slot != range.slot);
+    self.slots.push(range);
+    self.slots.sort_by_key(|range| range.slot);
+  }
+}
+
+/// Retrieve the path of the layout file for the SD card with the given
+/// serial number.
+pub fn layout_path(sd_card_id: u32) -> anyhow::Result<path::PathBuf> {
+  let project_dirs = directories::ProjectDirs::from("", "", "nitrocli")
+    .context("Could not determine the nitrocli application directory")?;
+  Ok(
+    project_dirs
+      .data_dir()
+      .join(LAYOUT_DIR)
+      .join(format!("{:#010x}.toml", sd_card_id)),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::str::FromStr as _;
+
+  #[test]
+  fn parse_size() {
+    assert_eq!(Size::from_str("0%").unwrap(), Size::Percent(0));
+    assert_eq!(Size::from_str("100%").unwrap(), Size::Percent(100));
+    assert_eq!(Size::from_str("512MiB").unwrap(), Size::Bytes(512 * MIB));
+    assert_eq!(Size::from_str("2GiB").unwrap(), Size::Bytes(2 * GIB));
+    assert!(Size::from_str("101%").is_err());
+    assert!(Size::from_str("10").is_err());
+    assert!(Size::from_str("1GB").is_err());
+    assert!(Size::from_str("-1%").is_err());
+  }
+
+  #[test]
+  fn size_to_percent() {
+    let sd_card_size = 16 * 1_000_000_000;
+    assert_eq!(Size::Percent(42).to_percent(sd_card_size, true), 42);
+    assert_eq!(Size::Bytes(2 * GIB).to_percent(sd_card_size, false), 13);
+    assert_eq!(Size::Bytes(2 * GIB).to_percent(sd_card_size, true), 14);
+    assert_eq!(Size::Bytes(0).to_percent(sd_card_size, true), 0);
+  }
+
+  #[test]
+  fn overlaps() {
+    let mut layout = Layout::default();
+    layout.set(SlotRange {
+      slot: 1,
+      start: 20,
+      end: 40,
+    });
+    layout.set(SlotRange {
+      slot: 0,
+      start: 50,
+      end: 60,
+    });
+    assert_eq!(
+      layout.slots.iter().map(|r| r.slot).collect::<Vec<_>>(),
+      vec![0, 1]
+    );
+
+    let range = SlotRange {
+      slot: 2,
+      start: 40,
+      end: 50,
+    };
+    assert_eq!(layout.find_overlap(&range), None);
+
+    let range = SlotRange {
+      slot: 2,
+      start: 39,
+      end: 50,
+    };
+    assert_eq!(layout.find_overlap(&range).map(|r| r.slot), Some(1));
+
+    // Replanning a slot does not conflict with its previous range.
+    let range = SlotRange {
+      slot: 1,
+      start: 25,
+      end: 45,
+    };
+    assert_eq!(layout.find_overlap(&range), None);
+    layout.set(range.clone());
+    assert_eq!(layout.slots[1], range);
+    assert_eq!(layout.slots.len(), 2);
+  }
+
+  #[test]
+  fn save_load() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("layout").join("card.toml");
+    assert_eq!(Layout::load(&path)?, Layout::default());
+
+    let mut layout = Layout::default();
+    layout.set(SlotRange {
+      slot: 3,
+      start: 0,
+      end: 100,
+    });
+    layout.save(&path)?;
+    assert_eq!(Layout::load(&path)?, layout);
+    Ok(())
+  }
+}
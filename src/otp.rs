@@ -0,0 +1,90 @@
+// otp.rs
+
+// Copyright (C) 2026 The Nitrocli Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! A software implementation of the HOTP (RFC 4226) and TOTP (RFC 6238)
+//! algorithms as used by Nitrokey devices, i.e., based on HMAC-SHA1.
+
+use hmac::Mac as _;
+
+/// Calculate the HMAC-SHA1 (RFC 2104) of the given message.
+fn hmac_sha1(key: &[u8], message: &[u8]) -> [u8; 20] {
+  // HMAC accepts keys of any length, so this cannot fail.
+  let mut mac = hmac::Hmac::<sha1::Sha1>::new_from_slice(key).unwrap();
+  mac.update(message);
+  mac.finalize().into_bytes().into()
+}
+
+/// Generate an HOTP with the given number of digits for the given
+/// counter value.
+pub fn hotp(secret: &[u8], counter: u64, digits: u32) -> String {
+  let hmac = hmac_sha1(secret, &counter.to_be_bytes());
+  let offset = usize::from(hmac[19] & 0xf);
+  let code = u32::from_be_bytes([
+    hmac[offset] & 0x7f,
+    hmac[offset + 1],
+    hmac[offset + 2],
+    hmac[offset + 3],
+  ]);
+  let code = u64::from(code) % 10u64.pow(digits);
+  format!("{:0width$}", code, width = digits as usize)
+}
+
+/// Generate a TOTP with the given number of digits for the given Unix
+/// timestamp and time window.
+pub fn totp(secret: &[u8], time: u64, time_window: u16, digits: u32) -> String {
+  hotp(secret, time / u64::from(time_window), digits)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+  }
+
+  #[test]
+  fn hmac_sha1_digests() {
+    // Test cases as per RFC 2202.
+    assert_eq!(
+      hex(&hmac_sha1(&[0x0b; 20], b"Hi There")),
+      "b617318655057264e28bc0b6fb378c8ef146be00"
+    );
+    assert_eq!(
+      hex(&hmac_sha1(
+        &[0xaa; 80],
+        b"Test Using Larger Than Block-Size Key - Hash Key First"
+      )),
+      "aa4ae5e15272d00e95705637ce8a3b55ed402112"
+    );
+  }
+
+  #[test]
+  fn hotp_codes() {
+    // Test values as per RFC 4226: Appendix D -- HOTP Algorithm: Test
+    // Values.
+    const SECRET: &[u8] = b"12345678901234567890";
+    let expected = [
+      "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583", "399871",
+      "520489",
+    ];
+    for (counter, code) in expected.iter().enumerate() {
+      assert_eq!(hotp(SECRET, counter as u64, 6), *code);
+    }
+  }
+
+  #[test]
+  fn totp_codes() {
+    // Test values as per RFC 6238: Appendix B -- Test Vectors.
+    const SECRET: &[u8] = b"12345678901234567890";
+    assert_eq!(totp(SECRET, 59, 30, 8), "94287082");
+    assert_eq!(totp(SECRET, 1_111_111_109, 30, 8), "07081804");
+    assert_eq!(totp(SECRET, 1_111_111_111, 30, 8), "14050471");
+    assert_eq!(totp(SECRET, 1_234_567_890, 30, 8), "89005924");
+    assert_eq!(totp(SECRET, 2_000_000_000, 30, 8), "69279037");
+    assert_eq!(totp(SECRET, 20_000_000_000, 30, 8), "65353130");
+    assert_eq!(totp(SECRET, 1_111_111_111, 30, 6), "050471");
+  }
+}
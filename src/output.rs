@@ -3,6 +3,8 @@
 // Copyright (C) 2020 The Nitrocli Developers
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use std::time;
+
 use anyhow::Context as _;
 
 use progressing::Baring as _;
@@ -12,26 +14,36 @@ use termion::raw::IntoRawMode as _;
 
 use crate::Context;
 
+/// The interval in which to print log lines for non-interactive output.
+const LOG_INTERVAL: time::Duration = time::Duration::from_secs(60);
+
 /// A progress bar that can be printed to an interactive output.
 pub struct ProgressBar {
-  /// Whether to redraw the entire progress bar in the next call to `draw`.
-  redraw: bool,
   /// The current progress of the progress bar (0 <= progress <= 100).
   progress: u8,
   /// Toggled on every call to `draw` to print a pulsing indicator.
   toggle: bool,
   /// Whether this progress bar finished.
   finished: bool,
+  /// The progress at the time the progress bar was created.
+  initial_progress: u8,
+  /// The time the progress bar was created.
+  start: time::Instant,
+  /// The time the last log line was printed for non-interactive
+  /// output.
+  last_log: Option<time::Instant>,
 }
 
 impl ProgressBar {
   /// Creates a new empty progress bar.
   pub fn new(progress: u8) -> ProgressBar {
     ProgressBar {
-      redraw: true,
       progress,
       toggle: false,
       finished: false,
+      initial_progress: progress,
+      start: time::Instant::now(),
+      last_log: None,
     }
   }
 
@@ -48,10 +60,7 @@ impl ProgressBar {
       "Progress bar value out of range: {}",
       progress
     );
-    if progress != self.progress {
-      self.redraw = true;
-      self.progress = progress;
-    }
+    self.progress = progress;
     self.toggle = !self.toggle;
     Ok(())
   }
@@ -61,19 +70,64 @@ impl ProgressBar {
   /// A finished progress bar may no longer be updated.
   pub fn finish(&mut self) {
     self.finished = true;
-    self.redraw = true;
     self.progress = 100;
   }
 
+  /// Estimate the remaining time based on the progress made in the
+  /// given elapsed time.
+  fn remaining(&self, elapsed: time::Duration) -> Option<time::Duration> {
+    let done = u32::from(self.progress.checked_sub(self.initial_progress)?);
+    if done == 0 {
+      return None;
+    }
+    let left = u32::from(100 - self.progress);
+    Some(elapsed / done * left)
+  }
+
+  /// Format the elapsed and the estimated remaining time.
+  fn format_time(&self, elapsed: time::Duration) -> String {
+    if self.finished {
+      format!("elapsed {}", format_duration(elapsed))
+    } else {
+      let remaining = self
+        .remaining(elapsed)
+        .map(format_duration)
+        .unwrap_or_else(|| "unknown".to_string());
+      format!(
+        "elapsed {}, remaining {}",
+        format_duration(elapsed),
+        remaining
+      )
+    }
+  }
+
   /// Print the progress bar to the stdout set in the given context.
   ///
   /// On every call of this method (as long as the progress bar is not
   /// finished), a pulsing indicator is printed to show that the process
-  /// is still running. If there was progress since the last call to
-  /// `draw`, or if this is the first call, this function will also
-  /// print the progress bar itself.
-  pub fn draw(&self, ctx: &mut Context<'_>) -> anyhow::Result<()> {
+  /// is still running, together with the progress bar itself and the
+  /// elapsed and estimated remaining time.
+  ///
+  /// If the output is not interactive, a log line with the progress is
+  /// printed instead, at most once per `LOG_INTERVAL`.
+  pub fn draw(&mut self, ctx: &mut Context<'_>) -> anyhow::Result<()> {
+    let now = time::Instant::now();
+    let elapsed = now.duration_since(self.start);
+
     if !ctx.is_tty {
+      let due = match self.last_log {
+        Some(last_log) => now.duration_since(last_log) >= LOG_INTERVAL,
+        None => true,
+      };
+      if due || self.finished {
+        println!(
+          ctx,
+          "Progress: {}% ({})",
+          self.progress,
+          self.format_time(elapsed)
+        )?;
+        self.last_log = Some(now);
+      }
       return Ok(());
     }
 
@@ -90,21 +144,59 @@ impl ProgressBar {
       " "
     };
 
-    if self.redraw {
-      let mut progress_bar = progressing::mapping::Bar::with_range(0, 100);
-      progress_bar.set(self.progress);
-
-      print!(ctx, "{}", termion::clear::CurrentLine)?;
-      print!(ctx, "{}", termion::cursor::Goto(1, pos.1))?;
-      print!(ctx, " {} {}", progress_char, progress_bar)?;
-      if self.finished {
-        println!(ctx)?;
-      }
-    } else {
-      print!(ctx, "{}{}", termion::cursor::Goto(2, pos.1), progress_char)?;
+    let mut progress_bar = progressing::mapping::Bar::with_range(0, 100);
+    progress_bar.set(self.progress);
+
+    print!(ctx, "{}", termion::clear::CurrentLine)?;
+    print!(ctx, "{}", termion::cursor::Goto(1, pos.1))?;
+    print!(
+      ctx,
+      " {} {} {}",
+      progress_char,
+      progress_bar,
+      self.format_time(elapsed)
+    )?;
+    if self.finished {
+      println!(ctx)?;
     }
 
     ctx.stdout.flush()?;
     Ok(())
   }
 }
+
+/// Format a duration as hours, minutes, and seconds.
+fn format_duration(duration: time::Duration) -> String {
+  let secs = duration.as_secs();
+  format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn remaining_time() -> anyhow::Result<()> {
+    let minute = time::Duration::from_secs(60);
+    let mut progress_bar = ProgressBar::new(20);
+    assert_eq!(progress_bar.remaining(minute), None);
+    assert_eq!(
+      progress_bar.format_time(minute),
+      "elapsed 0:01:00, remaining unknown"
+    );
+
+    progress_bar.update(30)?;
+    assert_eq!(progress_bar.remaining(10 * minute), Some(70 * minute));
+    assert_eq!(
+      progress_bar.format_time(10 * minute),
+      "elapsed 0:10:00, remaining 1:10:00"
+    );
+
+    progress_bar.finish();
+    assert_eq!(
+      progress_bar.format_time(80 * minute + time::Duration::from_secs(5)),
+      "elapsed 1:20:05"
+    );
+    Ok(())
+  }
+}
@@ -12,6 +12,7 @@ use std::str;
 
 use anyhow::Context as _;
 
+use crate::api;
 use crate::args;
 use crate::tty;
 use crate::Context;
@@ -35,6 +36,8 @@ pub struct PinEntry {
   pin_type: args::PinType,
   model: nitrokey::Model,
   serial: nitrokey::SerialNumber,
+  /// The name of the extension requesting the PIN, if any.
+  extension: Option<String>,
 }
 
 impl PinEntry {
@@ -51,6 +54,7 @@ impl PinEntry {
       pin_type,
       model,
       serial,
+      extension: None,
     })
   }
 
@@ -84,8 +88,12 @@ impl SecretEntry for PinEntry {
   }
 
   fn description(&self, mode: Mode) -> CowStr {
+    let requester = match &self.extension {
+      Some(extension) => format!("\r(requested by extension {})", extension),
+      None => String::new(),
+    };
     format!(
-      "{} for\r{} {}",
+      "{} for\r{} {}{}",
       match self.pin_type {
         args::PinType::Admin => match mode {
           Mode::Choose => "Please enter a new admin PIN",
@@ -100,6 +108,7 @@ impl SecretEntry for PinEntry {
       },
       self.model,
       self.serial,
+      requester,
     )
     .into()
   }
@@ -164,6 +173,57 @@ impl SecretEntry for PwdEntry {
   }
 }
 
+/// The update PIN of a Nitrokey Storage, which protects the firmware
+/// update mode.
+#[derive(Debug)]
+pub struct UpdatePinEntry {
+  model: nitrokey::Model,
+  serial: nitrokey::SerialNumber,
+}
+
+impl UpdatePinEntry {
+  pub fn from<'mgr, D>(device: &D) -> anyhow::Result<Self>
+  where
+    D: nitrokey::Device<'mgr>,
+  {
+    let model = device.get_model();
+    let serial = device
+      .get_serial_number()
+      .context("Failed to retrieve serial number")?;
+
+    Ok(Self { model, serial })
+  }
+}
+
+impl SecretEntry for UpdatePinEntry {
+  fn cache_id(&self) -> Option<CowStr> {
+    Some(format!("nitrocli:update:storage:{}", self.serial).into())
+  }
+
+  fn prompt(&self) -> CowStr {
+    "Update PIN".into()
+  }
+
+  fn description(&self, mode: Mode) -> CowStr {
+    format!(
+      "{} for\r{} {}",
+      match mode {
+        Mode::Choose => "Please enter a new update PIN",
+        Mode::Confirm => "Please confirm the new update PIN",
+        Mode::Query => "Please enter the update PIN",
+      },
+      self.model,
+      self.serial,
+    )
+    .into()
+  }
+
+  fn min_len(&self) -> u8 {
+    // The update PIN has the same requirements as the admin PIN.
+    8
+  }
+}
+
 /// Secret entry mode for pinentry.
 ///
 /// This enum describes the context of the pinentry query, for example
@@ -261,12 +321,26 @@ pub fn inquire<E>(
   mode: Mode,
   error_msg: Option<&str>,
 ) -> anyhow::Result<String>
+where
+  E: SecretEntry,
+{
+  query(ctx.config.no_cache, entry, mode, error_msg)
+}
+
+/// Query a secret from the user, as `inquire` does, without requiring
+/// the execution context.
+fn query<E>(
+  no_cache: bool,
+  entry: &E,
+  mode: Mode,
+  error_msg: Option<&str>,
+) -> anyhow::Result<String>
 where
   E: SecretEntry,
 {
   let cache_id = entry
     .cache_id()
-    .filter(|_id| !ctx.config.no_cache)
+    .filter(|_id| !no_cache)
     // "X" is a sentinel value indicating that no caching is desired.
     .unwrap_or_else(|| "X".into())
     .into();
@@ -356,6 +430,134 @@ where
   }
 }
 
+/// An authenticator retrieving secrets from the environment or, if
+/// they are not provided there, through pinentry.
+#[derive(Debug)]
+pub struct Authenticator {
+  model: nitrokey::Model,
+  serial: nitrokey::SerialNumber,
+  no_cache: bool,
+  extension: Option<String>,
+  admin_pin: Option<ffi::OsString>,
+  user_pin: Option<ffi::OsString>,
+  password: Option<ffi::OsString>,
+}
+
+impl Authenticator {
+  pub fn from<'mgr, D>(ctx: &Context<'_>, device: &D) -> anyhow::Result<Self>
+  where
+    D: nitrokey::Device<'mgr>,
+  {
+    let model = device.get_model();
+    let serial = device
+      .get_serial_number()
+      .context("Failed to retrieve serial number")?;
+
+    Ok(Self::new(ctx, model, serial))
+  }
+
+  /// Create an authenticator for the device with the given model and
+  /// serial number.
+  pub fn new(ctx: &Context<'_>, model: nitrokey::Model, serial: nitrokey::SerialNumber) -> Self {
+    Self {
+      model,
+      serial,
+      no_cache: ctx.config.no_cache,
+      extension: None,
+      admin_pin: ctx.admin_pin.clone(),
+      user_pin: ctx.user_pin.clone(),
+      password: ctx.password.clone(),
+    }
+  }
+
+  /// Mention the given extension as the requester of PINs when
+  /// inquiring them.
+  pub fn requested_by(mut self, extension: &str) -> Self {
+    self.extension = Some(extension.to_string());
+    self
+  }
+
+  /// Retrieve the value of the given secret from the environment.
+  fn env_secret(&self, secret: api::Secret) -> Option<&ffi::OsStr> {
+    match secret {
+      api::Secret::UserPin => self.user_pin.as_deref(),
+      api::Secret::AdminPin => self.admin_pin.as_deref(),
+      api::Secret::HiddenVolumePassword => self.password.as_deref(),
+      // There is no environment variable for the update PIN.
+      api::Secret::UpdatePin => None,
+    }
+  }
+
+  fn update_pin_entry(&self) -> UpdatePinEntry {
+    UpdatePinEntry {
+      model: self.model,
+      serial: self.serial,
+    }
+  }
+
+  fn pin_entry(&self, pin_type: args::PinType) -> PinEntry {
+    PinEntry {
+      pin_type,
+      model: self.model,
+      serial: self.serial,
+      extension: self.extension.clone(),
+    }
+  }
+}
+
+impl api::Authenticator for Authenticator {
+  fn secret(&mut self, secret: api::Secret, error: Option<&str>) -> anyhow::Result<String> {
+    if let Some(value) = self.env_secret(secret) {
+      let what = match secret {
+        api::Secret::HiddenVolumePassword => "password",
+        _ => "PIN",
+      };
+      return value
+        .to_str()
+        .map(ToOwned::to_owned)
+        .with_context(|| format!("Failed to read {}: Invalid Unicode data found", what));
+    }
+
+    match secret {
+      api::Secret::UserPin => {
+        let entry = self.pin_entry(args::PinType::User);
+        query(self.no_cache, &entry, Mode::Query, error)
+      }
+      api::Secret::AdminPin => {
+        let entry = self.pin_entry(args::PinType::Admin);
+        query(self.no_cache, &entry, Mode::Query, error)
+      }
+      api::Secret::HiddenVolumePassword => {
+        let entry = PwdEntry {
+          model: self.model,
+          serial: self.serial,
+        };
+        query(self.no_cache, &entry, Mode::Query, error).context("Failed to inquire PIN")
+      }
+      api::Secret::UpdatePin => {
+        let entry = self.update_pin_entry();
+        query(self.no_cache, &entry, Mode::Query, error)
+      }
+    }
+  }
+
+  fn reject(&mut self, secret: api::Secret) -> anyhow::Result<bool> {
+    // A secret from the environment would just be rejected again.
+    if self.env_secret(secret).is_some() {
+      return Ok(false);
+    }
+
+    match secret {
+      api::Secret::UserPin => clear(&self.pin_entry(args::PinType::User)),
+      api::Secret::AdminPin => clear(&self.pin_entry(args::PinType::Admin)),
+      api::Secret::UpdatePin => clear(&self.update_pin_entry()),
+      api::Secret::HiddenVolumePassword => return Ok(false),
+    }
+    .context("Failed to clear cached secret")?;
+    Ok(true)
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -411,4 +613,24 @@ mod tests {
     let error = parse_pinentry_response(response).unwrap_err();
     assert_eq!(error.to_string(), expected)
   }
+
+  #[test]
+  fn pin_entry_description_extension() {
+    let mut entry = PinEntry {
+      pin_type: args::PinType::User,
+      model: nitrokey::Model::Pro,
+      serial: "0x1234".parse().unwrap(),
+      extension: None,
+    };
+    assert_eq!(
+      entry.description(Mode::Query),
+      "Please enter the user PIN for\rNitrokey Pro 0x00001234"
+    );
+
+    entry.extension = Some("otp-cache".to_string());
+    assert_eq!(
+      entry.description(Mode::Query),
+      "Please enter the user PIN for\rNitrokey Pro 0x00001234\r(requested by extension otp-cache)"
+    );
+  }
 }
@@ -74,3 +74,80 @@ $"#,
   assert!(re.is_match(&out), "{}", out);
   Ok(())
 }
+
+#[test]
+fn import_invalid() -> anyhow::Result<()> {
+  let err = Nitrocli::new()
+    .stdin("num_lock = 1\nnumlock = 2\n")
+    .handle(&["config", "import", "-"])
+    .unwrap_err();
+  assert_eq!(err.to_string(), "Failed to parse configuration in '-'");
+  Ok(())
+}
+
+#[test_device]
+fn export_import(model: nitrokey::Model) -> anyhow::Result<()> {
+  let mut ncli = Nitrocli::new().model(model);
+  let _ = ncli.handle(&["config", "set", "-n", "1", "-C", "-S", "-O"])?;
+
+  let exported = ncli.handle(&["config", "export"])?;
+  assert!(exported.contains("num_lock = 1\n"), "{}", exported);
+  assert!(exported.contains("otp_pin = false\n"), "{}", exported);
+  assert!(!exported.contains("caps_lock"), "{}", exported);
+
+  let out = ncli.stdin("caps_lock = 2\notp_pin = false\n").handle(&[
+    "config",
+    "import",
+    "--dry-run",
+    "-",
+  ])?;
+  assert_eq!(
+    out,
+    "Changes:\n  num lock binding:         1 -> not set\n  caps lock binding:        not set -> 2\n"
+  );
+
+  let mut ncli = Nitrocli::new().model(model).stdin(exported.clone());
+  let out = ncli.handle(&["config", "import", "-"])?;
+  assert_eq!(out, "Configuration is up to date\n");
+
+  let _ = ncli.handle(&["config", "set", "-N"])?;
+  let err = Nitrocli::new()
+    .model(model)
+    .stdin(exported.clone())
+    .handle(&["config", "import", "-"])
+    .unwrap_err()
+    .to_string();
+  assert_eq!(
+    err,
+    "Refusing to apply a configuration read from stdin without --yes"
+  );
+
+  let dir = tempfile::tempdir()?;
+  let file = dir.path().join("nitrokey.toml");
+  std::fs::write(&file, &exported)?;
+  let file = file.to_str().unwrap();
+  let err = Nitrocli::new()
+    .model(model)
+    .stdin("no\n")
+    .handle(&["config", "import", file])
+    .unwrap_err()
+    .to_string();
+  assert_eq!(err, "Confirmation failed, aborting operation");
+
+  let out = Nitrocli::new()
+    .model(model)
+    .stdin("yes\n")
+    .handle(&["config", "import", file])?;
+  assert_eq!(out, "Changes:\n  num lock binding:         not set -> 1\n");
+
+  let _ = ncli.handle(&["config", "set", "-N"])?;
+  let out = Nitrocli::new()
+    .model(model)
+    .stdin(exported)
+    .handle(&["config", "import", "--yes", "-"])?;
+  assert_eq!(out, "Changes:\n  num lock binding:         not set -> 1\n");
+
+  let out = Nitrocli::new().model(model).handle(&["config", "get"])?;
+  assert!(out.contains("num lock binding:         1\n"), "{}", out);
+  Ok(())
+}
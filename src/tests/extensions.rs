@@ -5,6 +5,9 @@
 
 use std::env;
 use std::fs;
+use std::io::Write as _;
+use std::os::unix::fs::OpenOptionsExt as _;
+use std::path;
 
 use super::*;
 
@@ -71,3 +74,125 @@ fn resolve_extensions() -> anyhow::Result<()> {
   }
   Ok(())
 }
+
+/// Create an executable extension with the given name and content.
+fn create_extension(dir: &path::Path, name: &str, content: &str) -> anyhow::Result<()> {
+  let mut ext = fs::OpenOptions::new()
+    .create(true)
+    .truncate(true)
+    .mode(0o755)
+    .write(true)
+    .open(dir.join(format!("nitrocli-{}", name)))?;
+  ext.write_all(content.as_bytes())?;
+  Ok(())
+}
+
+#[test]
+fn extension_metadata() -> anyhow::Result<()> {
+  let dir = tempfile::tempdir()?;
+  create_extension(
+    dir.path(),
+    "manifest",
+    "#!/usr/bin/env python\nraise SystemExit(1)\n",
+  )?;
+  fs::write(
+    dir.path().join("nitrocli-manifest.toml"),
+    "description = \"Described by a manifest\"\nversion = \"1.2.3\"\n",
+  )?;
+  create_extension(
+    dir.path(),
+    "describe",
+    r#"#!/usr/bin/env python
+import sys
+if sys.argv[1:] == ["--nitrocli-describe"]:
+    print('description = "Described by itself"')
+    print('min_nitrocli_version = "999.0"')
+"#,
+  )?;
+  create_extension(
+    dir.path(),
+    "plain",
+    "#!/usr/bin/env python\nprint(\"hello\")\n",
+  )?;
+
+  let path = dir.path().as_os_str().to_os_string();
+  let out = Nitrocli::new()
+    .path(&path)
+    .handle(&["extensions", "list"])?;
+  let expected = "extension\tversion\tdescription
+describe\tunknown\tDescribed by itself (requires nitrocli 999.0 or newer)
+manifest\t1.2.3\tDescribed by a manifest
+plain\tunknown\tRun the plain extension
+";
+  assert_eq!(out, expected);
+
+  let out = Nitrocli::new().path(&path).handle(&["--help"])?;
+  assert!(out.contains("Described by a manifest\n"), "{}", out);
+  assert!(out.contains("Run the plain extension\n"), "{}", out);
+  Ok(())
+}
+
+#[test]
+fn incompatible_extension() -> anyhow::Result<()> {
+  let dir = tempfile::tempdir()?;
+  create_extension(dir.path(), "ext", "#!/usr/bin/env python\n")?;
+  fs::write(
+    dir.path().join("nitrocli-ext.toml"),
+    "min_nitrocli_version = \"999.0\"\n",
+  )?;
+
+  let path = dir.path().as_os_str().to_os_string();
+  let err = Nitrocli::new()
+    .path(&path)
+    .handle(&["ext"])
+    .unwrap_err()
+    .to_string();
+  assert_eq!(err, "Extension ext requires nitrocli 999.0 or newer");
+  Ok(())
+}
+
+#[test]
+fn extension_cache() -> anyhow::Result<()> {
+  let dir = tempfile::tempdir()?;
+  let cache = dir.path().join("cache").join("extensions.toml");
+  let count = dir.path().join("count");
+  let ext_dir = dir.path().join("bin");
+  fs::create_dir(&ext_dir)?;
+  // The extension records each time it is asked for its metadata.
+  create_extension(
+    &ext_dir,
+    "ext",
+    &format!(
+      r#"#!/usr/bin/env python
+import sys
+if sys.argv[1:] == ["--nitrocli-describe"]:
+    with open("{}", "a") as f:
+        f.write("x")
+    print('description = "Counting"')
+"#,
+      count.display()
+    ),
+  )?;
+
+  let path = ext_dir.as_os_str();
+  let list = || crate::extensions::list(path, Some(&cache));
+  let exts = list()?;
+  assert_eq!(exts.len(), 1);
+  assert_eq!(exts[0].about(), "Counting");
+  assert_eq!(fs::read_to_string(&count)?, "x");
+  assert!(cache.is_file());
+
+  // A valid cache means no extension has to be run.
+  assert_eq!(list()?, exts);
+  assert_eq!(fs::read_to_string(&count)?, "x");
+
+  // Adding a manifest invalidates the cache.
+  fs::write(
+    ext_dir.join("nitrocli-ext.toml"),
+    "description = \"Manifest\"\n",
+  )?;
+  let exts = list()?;
+  assert_eq!(exts[0].about(), "Manifest");
+  assert_eq!(fs::read_to_string(&count)?, "x");
+  Ok(())
+}
@@ -13,3 +13,14 @@ fn fill(model: nitrokey::Model) -> anyhow::Result<()> {
   assert!(res.is_ok());
   Ok(())
 }
+
+#[test]
+fn detach_with_notify_command() {
+  let (rc, out, err) = Nitrocli::new().run(&["fill", "--detach", "--notify-command", "true"]);
+
+  assert_ne!(rc, 0);
+  assert_eq!(out, b"", "{}", String::from_utf8_lossy(&out));
+
+  let err = String::from_utf8(err).unwrap();
+  assert!(err.contains("--notify-command"), "{}", err);
+}
@@ -0,0 +1,39 @@
+// firmware.rs
+
+// Copyright (C) 2026 The Nitrocli Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use super::*;
+
+#[test_device(storage)]
+fn status(model: nitrokey::Model) -> anyhow::Result<()> {
+  let re = regex::Regex::new(
+    r#"^firmware version: v\d+\.\d+
+firmware:         (locked|unlocked)
+$"#,
+  )
+  .unwrap();
+
+  let out = Nitrocli::new()
+    .model(model)
+    .handle(&["firmware", "status"])?;
+  assert!(re.is_match(&out), "{}", out);
+  Ok(())
+}
+
+#[test_device(storage)]
+fn update_mode_not_confirmed(model: nitrokey::Model) -> anyhow::Result<()> {
+  let res = Nitrocli::new()
+    .model(model)
+    .stdin("no\n")
+    .handle(&["firmware", "update-mode"]);
+  let err = res.unwrap_err().to_string();
+  assert_eq!(err, "Confirmation failed, aborting operation");
+
+  // The device must still be usable.
+  let out = Nitrocli::new()
+    .model(model)
+    .handle(&["firmware", "status"])?;
+  assert!(out.starts_with("firmware version: "), "{}", out);
+  Ok(())
+}
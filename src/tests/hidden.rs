@@ -33,3 +33,28 @@ fn hidden_create_open_close(model: nitrokey::Model) -> anyhow::Result<()> {
 
   Ok(())
 }
+
+#[test]
+fn hidden_plan_invalid_size() {
+  let (rc, out, err) = Nitrocli::new().run(&["hidden", "plan", "0", "10%", "2GB"]);
+
+  assert_ne!(rc, 0);
+  assert_eq!(out, b"", "{}", String::from_utf8_lossy(&out));
+
+  let err = String::from_utf8(err).unwrap();
+  assert!(err.contains("Invalid size '2GB'"), "{}", err);
+}
+
+#[test]
+fn hidden_plan_invalid_slot() {
+  let (rc, out, err) = Nitrocli::new().run(&["hidden", "plan", "4", "10%", "20%"]);
+
+  assert_ne!(rc, 0);
+  assert_eq!(out, b"", "{}", String::from_utf8_lossy(&out));
+  assert_eq!(
+    err,
+    b"Invalid hidden volume slot 4 (must be less than 4)\n",
+    "{}",
+    String::from_utf8_lossy(&err)
+  );
+}
@@ -4,6 +4,7 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use std::ffi;
+use std::path;
 
 use nitrokey_test::test as test_device;
 
@@ -11,6 +12,7 @@ mod config;
 mod encrypted;
 mod extensions;
 mod fill;
+mod firmware;
 mod hidden;
 mod list;
 mod lock;
@@ -19,6 +21,7 @@ mod pin;
 mod pws;
 mod reset;
 mod run;
+mod settings;
 mod status;
 mod unencrypted;
 
@@ -31,6 +34,8 @@ struct Nitrocli {
   new_admin_pin: Option<ffi::OsString>,
   new_user_pin: Option<ffi::OsString>,
   password: Option<ffi::OsString>,
+  otp_stamps: Option<path::PathBuf>,
+  secret_extensions: Vec<String>,
 }
 
 impl Nitrocli {
@@ -44,6 +49,8 @@ impl Nitrocli {
       new_admin_pin: None,
       new_user_pin: None,
       password: None,
+      otp_stamps: None,
+      secret_extensions: Vec::new(),
     }
   }
 
@@ -65,6 +72,19 @@ impl Nitrocli {
     self
   }
 
+  /// Set the directory in which modifications of OTP slots are
+  /// recorded.
+  fn otp_stamps(mut self, dir: impl Into<path::PathBuf>) -> Self {
+    self.otp_stamps = Some(dir.into());
+    self
+  }
+
+  /// Set the extensions that may request secrets.
+  fn secret_extensions(mut self, names: &[&str]) -> Self {
+    self.secret_extensions = names.iter().map(ToString::to_string).collect();
+    self
+  }
+
   pub fn stdin(mut self, stdin: impl Into<String>) -> Self {
     self.stdin = stdin.into();
     self
@@ -126,8 +146,12 @@ impl Nitrocli {
       new_admin_pin: self.new_admin_pin.clone(),
       new_user_pin: self.new_user_pin.clone(),
       password: self.password.clone(),
+      agent_socket: None,
+      extension_cache: None,
+      otp_stamps: self.otp_stamps.clone(),
       config: crate::config::Config {
         no_cache: true,
+        secret_extensions: self.secret_extensions.clone(),
         ..Default::default()
       },
     };
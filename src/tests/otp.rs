@@ -1,6 +1,6 @@
 // otp.rs
 
-// Copyright (C) 2019-2021 The Nitrocli Developers
+// Copyright (C) 2019-2026 The Nitrocli Developers
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use super::*;
@@ -122,6 +122,92 @@ fn set_get_totp(model: nitrokey::Model) -> anyhow::Result<()> {
   Ok(())
 }
 
+#[test_device]
+fn get_totp_validity(model: nitrokey::Model) -> anyhow::Result<()> {
+  const SECRET: &str = "12345678901234567890";
+
+  let mut ncli = Nitrocli::new().model(model);
+  let _ = ncli.handle(&["otp", "set", "-d", "8", "-f", "ascii", "2", "name", SECRET])?;
+
+  let out = ncli.handle(&["otp", "get", "--validity", "-t", "1111111111", "2"])?;
+  assert_eq!(
+    out,
+    "14050471\nvalid for 29s (from 1111111110 until 1111111140)\n"
+  );
+
+  let out = ncli.handle(&[
+    "otp",
+    "get",
+    "--validity",
+    "-w",
+    "60",
+    "-t",
+    "1111111111",
+    "2",
+  ])?;
+  assert!(
+    out.ends_with("valid for 29s (from 1111111080 until 1111111140)\n"),
+    "{}",
+    out
+  );
+  Ok(())
+}
+
+#[test_device]
+fn get_totp_dates(model: nitrokey::Model) -> anyhow::Result<()> {
+  const SECRET: &str = "12345678901234567890";
+  const OTP: &str = concat!(14050471, "\n");
+
+  let mut ncli = Nitrocli::new().model(model);
+  let _ = ncli.handle(&["otp", "set", "-d", "8", "-f", "ascii", "2", "name", SECRET])?;
+
+  let out = ncli.handle(&["otp", "get", "-t", "2005-03-18T01:58:31Z", "2"])?;
+  assert_eq!(out, OTP);
+  let out = ncli.handle(&["otp", "get", "-t", "2005-03-17T20:28:31-05:30", "2"])?;
+  assert_eq!(out, OTP);
+  let out = ncli.handle(&["otp", "get", "-t", "1111111080", "--at-next-window", "2"])?;
+  assert_eq!(out, OTP);
+  Ok(())
+}
+
+#[test]
+fn get_invalid_window_args() {
+  let err = Nitrocli::new()
+    .handle(&["otp", "get", "--time-window", "0", "1"])
+    .unwrap_err()
+    .to_string();
+  assert_eq!(err, "The time window must not be zero");
+
+  let err = Nitrocli::new()
+    .handle(&["otp", "get", "--wait-min", "31", "1"])
+    .unwrap_err()
+    .to_string();
+  assert_eq!(err, "The minimum validity must not exceed the time window");
+
+  let err = Nitrocli::new()
+    .handle(&["otp", "get", "--watch", "--time", "1", "1"])
+    .unwrap_err()
+    .to_string();
+  assert!(err.contains("cannot be used with"), "{}", err);
+
+  let err = Nitrocli::new()
+    .handle(&["otp", "get", "--watch", "--at-next-window", "1"])
+    .unwrap_err()
+    .to_string();
+  assert!(err.contains("cannot be used with"), "{}", err);
+
+  for arg in &["--watch", "--validity"] {
+    let err = Nitrocli::new()
+      .handle(&["otp", "get", "-a", "hotp", arg, "1"])
+      .unwrap_err()
+      .to_string();
+    assert_eq!(
+      err,
+      "The --wait-min, --watch, and --validity options require the TOTP algorithm"
+    );
+  }
+}
+
 #[test_device]
 fn set_totp_uneven_chars(model: nitrokey::Model) -> anyhow::Result<()> {
   let secrets = [
@@ -165,3 +251,110 @@ fn clear(model: nitrokey::Model) -> anyhow::Result<()> {
   assert_eq!(err, "Failed to generate OTP");
   Ok(())
 }
+
+#[test_device]
+fn set_clear_record_change(model: nitrokey::Model) -> anyhow::Result<()> {
+  use nitrokey::Device as _;
+
+  let serial_number = {
+    let mut manager = nitrokey::force_take()?;
+    let device = manager.connect_model(model)?;
+    device.get_serial_number()?
+  };
+  let dir = tempfile::tempdir()?;
+  let file = dir.path().join(serial_number.to_string());
+
+  let mut ncli = Nitrocli::new().model(model).otp_stamps(dir.path());
+  let _ = ncli.handle(&["otp", "set", "3", "hotp-test", "abcdef"])?;
+  let set = std::fs::read_to_string(&file)?;
+
+  let _ = ncli.handle(&["otp", "clear", "3"])?;
+  let cleared = std::fs::read_to_string(&file)?;
+  assert_ne!(set, cleared);
+  Ok(())
+}
+
+#[test_device]
+fn time(model: nitrokey::Model) -> anyhow::Result<()> {
+  let mut ncli = Nitrocli::new().model(model);
+  let out = ncli.handle(&["otp", "time", "--force"])?;
+  assert!(
+    out.contains("device time:\tset to system time\n"),
+    "{}",
+    out
+  );
+
+  let out = ncli.handle(&["otp", "time"])?;
+  assert!(
+    out.contains("device time:\tnot ahead of system time, set to system time\n"),
+    "{}",
+    out
+  );
+
+  // Move the device time into the future. Whether the slot is
+  // programmed does not matter for that.
+  let _ = ncli.handle(&["otp", "get", "-t", "4000000000", "1"]);
+  let out = ncli.handle(&["otp", "time"])?;
+  assert!(
+    out.contains("device time:\tahead of system time or not set, unchanged\n"),
+    "{}",
+    out
+  );
+
+  let _ = ncli.handle(&["otp", "time", "--force"])?;
+  Ok(())
+}
+
+#[test_device]
+fn verify(model: nitrokey::Model) -> anyhow::Result<()> {
+  const SECRET: &str = "12345678901234567890";
+
+  let mut ncli = Nitrocli::new().model(model);
+  let _ = ncli.handle(&[
+    "otp", "set", "-a", "hotp", "-f", "ascii", "1", "name", SECRET,
+  ])?;
+  let out = ncli.handle(&[
+    "otp", "verify", "-a", "hotp", "-y", "-f", "ascii", "-s", SECRET, "1",
+  ])?;
+  assert_eq!(out, "OTP slot matches the secret (counter advanced to 2)\n");
+  let _ = ncli.handle(&["otp", "get", "-a", "hotp", "1"])?;
+  let out = Nitrocli::new().model(model).stdin("yes\n").handle(&[
+    "otp", "verify", "-a", "hotp", "-f", "ascii", "-s", SECRET, "1",
+  ])?;
+  assert_eq!(out, "OTP slot matches the secret (counter advanced to 5)\n");
+
+  let _ = ncli.handle(&["otp", "set", "-d", "8", "-f", "ascii", "2", "name", SECRET])?;
+  let out = ncli.handle(&["otp", "verify", "-f", "ascii", "--secret", SECRET, "2"])?;
+  assert_eq!(out, "OTP slot matches the secret\n");
+
+  let err = ncli
+    .handle(&["otp", "verify", "-f", "ascii", "--secret", "other", "2"])
+    .unwrap_err()
+    .to_string();
+  assert!(
+    err.starts_with("OTP slot does not match the secret: the device generated"),
+    "{}",
+    err
+  );
+  Ok(())
+}
+
+#[test]
+fn verify_hotp_unconfirmed() {
+  let err = Nitrocli::new()
+    .stdin("no\n")
+    .handle(&["otp", "verify", "-a", "hotp", "-s", "AAAA", "1"])
+    .unwrap_err()
+    .to_string();
+  assert_eq!(err, "Confirmation failed, aborting operation");
+
+  let err = Nitrocli::new()
+    .stdin("AAAA\n")
+    .handle(&["otp", "verify", "-a", "hotp", "-s", "-", "1"])
+    .unwrap_err()
+    .to_string();
+  assert_eq!(
+    err,
+    "Refusing to verify an HOTP slot with a secret read from stdin without --yes"
+  );
+}
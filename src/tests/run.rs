@@ -9,6 +9,7 @@ use std::convert::TryInto as _;
 use std::fs;
 use std::io::Write;
 use std::ops;
+use std::os::unix::fs::MetadataExt as _;
 use std::os::unix::fs::OpenOptionsExt;
 use std::path;
 
@@ -51,16 +52,25 @@ fn help_options() {
   }
 
   test(&[]);
+  test(&["agent"]);
   test(&["config"]);
+  test(&["config", "export"]);
   test(&["config", "get"]);
+  test(&["config", "import"]);
   test(&["config", "set"]);
   test(&["encrypted"]);
   test(&["encrypted", "open"]);
   test(&["encrypted", "close"]);
+  test(&["extensions"]);
+  test(&["extensions", "list"]);
+  test(&["firmware"]);
+  test(&["firmware", "status"]);
+  test(&["firmware", "update-mode"]);
   test(&["hidden"]);
   test(&["hidden", "close"]);
   test(&["hidden", "create"]);
   test(&["hidden", "open"]);
+  test(&["hidden", "plan"]);
   test(&["lock"]);
   test(&["otp"]);
   test(&["otp", "clear"]);
@@ -78,6 +88,9 @@ fn help_options() {
   test(&["pws", "update"]);
   test(&["pws", "status"]);
   test(&["reset"]);
+  test(&["settings"]);
+  test(&["settings", "check"]);
+  test(&["settings", "show"]);
   test(&["status"]);
   test(&["unencrypted"]);
   test(&["unencrypted", "set"]);
@@ -113,6 +126,8 @@ fn config_file() {
   assert_eq!(Some(args::DeviceModel::Pro), config.model);
   assert!(config.no_cache);
   assert_eq!(2, config.verbosity);
+  assert_eq!(vec!["otp-cache"], config.interactive_extensions);
+  assert_eq!(vec!["otp-cache"], config.secret_extensions);
 }
 
 #[test_device]
@@ -352,9 +367,59 @@ sys.exit(42);
   Ok(())
 }
 
+#[test]
+fn extension_interactive() -> anyhow::Result<()> {
+  let ext_dir = tempfile::tempdir()?;
+  {
+    let mut ext = fs::OpenOptions::new()
+      .create(true)
+      .truncate(true)
+      .mode(0o755)
+      .write(true)
+      .open(ext_dir.path().join("nitrocli-ext"))?;
+
+    // The extension checks that its stdout is the very same file as
+    // ours.
+    let stdout = fs::metadata("/dev/fd/1")?;
+    let script = format!(
+      r#"#!/usr/bin/env python
+import os
+import sys
+stat = os.fstat(1)
+sys.exit(0 if (stat.st_dev, stat.st_ino) == ({}, {}) else 1)
+"#,
+      stdout.dev(),
+      stdout.ino()
+    );
+    ext.write_all(script.as_bytes())?;
+    fs::write(
+      ext_dir.path().join("nitrocli-ext.toml"),
+      "interactive = true\n",
+    )?;
+  }
+
+  let path = ext_dir.path().as_os_str().to_os_string();
+  // The manifest must not show up as an extension of its own.
+  let out = Nitrocli::new().path(&path).handle(&["--help"])?;
+  assert!(!out.contains("ext.toml"), "{}", out);
+
+  // With inherited streams we do not capture any output.
+  let (rc, out, err) = Nitrocli::new().path(&path).run(&["ext"]);
+  assert_eq!(rc, 0);
+  assert_eq!(out, b"", "{}", String::from_utf8_lossy(&out));
+  assert_eq!(err, b"", "{}", String::from_utf8_lossy(&err));
+  Ok(())
+}
+
 #[test_device]
 fn extension_arguments(model: nitrokey::Model) -> anyhow::Result<()> {
-  fn test<F>(model: nitrokey::Model, what: &str, args: &[&str], check: F) -> anyhow::Result<()>
+  fn test_with<F>(
+    model: nitrokey::Model,
+    secret_extensions: &[&str],
+    what: &str,
+    args: &[&str],
+    check: F,
+  ) -> anyhow::Result<()>
   where
     F: FnOnce(&str) -> bool,
   {
@@ -374,12 +439,23 @@ fn extension_arguments(model: nitrokey::Model) -> anyhow::Result<()> {
     args.append(&mut vec!["ext", what]);
 
     let path = ext_dir.path().as_os_str().to_os_string();
-    let out = Nitrocli::new().model(model).path(path).handle(&args)?;
+    let out = Nitrocli::new()
+      .model(model)
+      .path(path)
+      .secret_extensions(secret_extensions)
+      .handle(&args)?;
 
     assert!(check(&out), "{}", out);
     Ok(())
   }
 
+  fn test<F>(model: nitrokey::Model, what: &str, args: &[&str], check: F) -> anyhow::Result<()>
+  where
+    F: FnOnce(&str) -> bool,
+  {
+    test_with(model, &["ext"], what, args, check)
+  }
+
   test(model, "NITROCLI_BINARY", &[], |out| {
     path::Path::new(out)
       .file_stem()
@@ -392,7 +468,21 @@ fn extension_arguments(model: nitrokey::Model) -> anyhow::Result<()> {
   test(model, "NITROCLI_MODEL", &[], |out| {
     out == args::DeviceModel::try_from(model).unwrap().to_string() + "\n"
   })?;
+  test(model, "NITROCLI_RESOLVED_MODEL", &[], |out| {
+    out == args::DeviceModel::try_from(model).unwrap().to_string() + "\n"
+  })?;
+  test(model, "NITROCLI_RESOLVED_SERIAL_NUMBER", &[], |out| {
+    out.starts_with("0x")
+  })?;
+  test(model, "NITROCLI_SECRET_FD", &[], |out| {
+    out.trim().parse::<i32>().is_ok()
+  })?;
+  // Extensions not listed in the secret_extensions setting must not get
+  // access to the secret channel, so the program errors out.
+  let _ = test_with(model, &[], "NITROCLI_SECRET_FD", &[], |_| true).unwrap_err();
+  let _ = test_with(model, &["other"], "NITROCLI_SECRET_FD", &[], |_| true).unwrap_err();
   test(model, "NITROCLI_NO_CACHE", &[], |out| out == "true\n")?;
+  test(model, "NITROCLI_NO_FORCE_TIME", &[], |out| out == "false\n")?;
   test(model, "NITROCLI_SERIAL_NUMBERS", &[], |out| out == "\n")?;
   test(model, "NITROCLI_VERBOSITY", &[], |out| out == "0\n")?;
   test(model, "NITROCLI_VERBOSITY", &["-v"], |out| out == "1\n")?;
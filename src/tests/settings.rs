@@ -0,0 +1,94 @@
+// settings.rs
+
+// Copyright (C) 2026 The Nitrocli Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fs;
+use std::io::Write as _;
+
+use super::*;
+
+#[test]
+fn show() -> anyhow::Result<()> {
+  let out = Nitrocli::new().handle(&["settings", "show"])?;
+  let expected = r#"setting	value	source
+model	not set	default
+serial_numbers	not set	default
+usb_path	not set	default
+no_cache	true	default
+verbosity	0	default
+interactive_extensions	not set	default
+secret_extensions	not set	default
+no_force_time	false	default
+"#;
+  assert_eq!(out, expected);
+  Ok(())
+}
+
+#[test]
+fn show_command_line() -> anyhow::Result<()> {
+  let out = Nitrocli::new().handle(&[
+    "settings",
+    "show",
+    "--model=storage",
+    "--serial-number=0xdeadbeef",
+    "-vv",
+  ])?;
+  assert!(out.contains("model\tstorage\tcommand line\n"), "{}", out);
+  assert!(
+    out.contains("serial_numbers\t0xdeadbeef\tcommand line\n"),
+    "{}",
+    out
+  );
+  assert!(out.contains("usb_path\tnot set\tdefault\n"), "{}", out);
+  assert!(out.contains("verbosity\t2\tcommand line\n"), "{}", out);
+  Ok(())
+}
+
+#[test]
+fn check_valid() -> anyhow::Result<()> {
+  let out = Nitrocli::new().handle(&["settings", "check", "doc/config.example.toml"])?;
+  assert_eq!(out, "");
+  Ok(())
+}
+
+#[test]
+fn check_unknown_keys() -> anyhow::Result<()> {
+  let mut file = tempfile::NamedTempFile::new()?;
+  writeln!(file, "model = \"pro\"\nverbose = 2\nno-cache = true")?;
+  let path = file.path().to_str().unwrap();
+
+  let (rc, out, err) = Nitrocli::new().run(&["settings", "check", path]);
+  assert_ne!(rc, 0);
+
+  let out = String::from_utf8(out)?;
+  assert_eq!(
+    out,
+    format!(
+      "{path}: unknown key 'no-cache'\n{path}: unknown key 'verbose'\n",
+      path = path
+    )
+  );
+  let err = String::from_utf8(err)?;
+  assert_eq!(
+    err,
+    format!("Configuration file '{}' contains unknown keys\n", path)
+  );
+  Ok(())
+}
+
+#[test]
+fn check_invalid_value() -> anyhow::Result<()> {
+  let dir = tempfile::tempdir()?;
+  let path = dir.path().join("config.toml");
+  fs::write(&path, "model = \"nokia\"\n")?;
+
+  let err = Nitrocli::new()
+    .handle(&["settings", "check", path.to_str().unwrap()])
+    .unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    format!("Failed to parse configuration file '{}'", path.display())
+  );
+  Ok(())
+}
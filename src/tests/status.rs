@@ -88,3 +88,137 @@ $"#,
   assert!(re.is_match(&out), "{}", out);
   Ok(())
 }
+
+#[test_device(storage)]
+fn output_storage_production_info(model: nitrokey::Model) -> anyhow::Result<()> {
+  let re = regex::Regex::new(
+    r#"
+      hidden:          (read-only|active|inactive)
+    production info:
+      firmware version:  v\d+\.\d+ \(internal \d+\)
+      CPU ID:            0x[[:xdigit:]]{8}
+      SD card:
+        size:            \d+ GB
+        manufacturer:    0x[[:xdigit:]]{2}
+        OEM:             0x[[:xdigit:]]{4}
+        manufactured:    \d{4}-\d{2}
+$"#,
+  )
+  .unwrap();
+
+  let out = Nitrocli::new()
+    .model(model)
+    .handle(&["status", "--production-info"])?;
+  assert!(re.is_match(&out), "{}", out);
+  Ok(())
+}
+
+#[test_device]
+fn check_not_found() {
+  let (rc, out, err) = Nitrocli::new().run(&["status", "--check"]);
+
+  assert_eq!(rc, 3);
+  assert_eq!(
+    out,
+    b"NITROKEY UNKNOWN - Nitrokey device not found\n",
+    "{}",
+    String::from_utf8_lossy(&out)
+  );
+  assert_eq!(err, b"", "{}", String::from_utf8_lossy(&err));
+}
+
+#[test]
+fn check_options_without_check() {
+  let (rc, out, err) = Nitrocli::new().run(&["status", "--min-user-retries", "2"]);
+
+  assert_ne!(rc, 0);
+  assert_eq!(out, b"", "{}", String::from_utf8_lossy(&out));
+
+  let err = String::from_utf8(err).unwrap();
+  assert!(err.contains("--check"), "{}", err);
+}
+
+#[test]
+fn format_with_check() {
+  let (rc, out, err) = Nitrocli::new().run(&["status", "--check", "--format", "prometheus"]);
+
+  assert_ne!(rc, 0);
+  assert_eq!(out, b"", "{}", String::from_utf8_lossy(&out));
+
+  let err = String::from_utf8(err).unwrap();
+  assert!(err.contains("--format"), "{}", err);
+}
+
+#[test_device]
+fn check(model: nitrokey::Model) {
+  let re = regex::Regex::new(
+    r#"^NITROKEY (OK|WARNING|CRITICAL) - .+ 0x[[:xdigit:]]{8}: .+ \| user_retry_count=[0-3] admin_retry_count=[0-3]
+$"#,
+  )
+  .unwrap();
+
+  let (rc, out, err) = Nitrocli::new().model(model).run(&[
+    "status",
+    "--check",
+    "--min-user-retries=0",
+    "--min-admin-retries=0",
+  ]);
+  let out = String::from_utf8(out).unwrap();
+  assert!(re.is_match(&out), "{}", out);
+  assert!(rc == 0 || rc == 2, "{}", rc);
+  assert_eq!(err, b"", "{}", String::from_utf8_lossy(&err));
+}
+
+#[test_device]
+fn prometheus(model: nitrokey::Model) -> anyhow::Result<()> {
+  let re = regex::Regex::new(
+    r#"(?m)^nitrokey_user_retry_count\{model="[a-z]+",serial_number="0x[[:xdigit:]]{8}"\} [0-3]$"#,
+  )
+  .unwrap();
+
+  let dir = tempfile::tempdir()?;
+  let path = dir.path().join("nitrokey.prom");
+  let out = Nitrocli::new().model(model).handle(&[
+    "status",
+    "--format=prometheus",
+    "--output",
+    path.to_str().unwrap(),
+  ])?;
+  assert_eq!(out, "");
+
+  let content = std::fs::read_to_string(&path)?;
+  assert!(re.is_match(&content), "{}", content);
+  assert!(
+    content.contains("# TYPE nitrokey_admin_retry_count gauge\n"),
+    "{}",
+    content
+  );
+  assert!(
+    !content.contains("nitrokey_pws_slots_programmed"),
+    "{}",
+    content
+  );
+
+  let out =
+    Nitrocli::new()
+      .model(model)
+      .handle(&["status", "--format=prometheus", "--count-pws"])?;
+  assert!(
+    out.contains("# TYPE nitrokey_pws_slots_programmed gauge\n"),
+    "{}",
+    out
+  );
+  Ok(())
+}
+
+#[test]
+fn count_pws_text() {
+  let err = Nitrocli::new()
+    .handle(&["status", "--count-pws"])
+    .unwrap_err()
+    .to_string();
+  assert_eq!(
+    err,
+    "The --count-pws option requires the Prometheus output format"
+  );
+}
@@ -0,0 +1,298 @@
+// timestamp.rs
+
+// Copyright (C) 2026 The Nitrocli Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::str;
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+
+/// A point in time as provided by the user.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timestamp {
+  /// A Unix timestamp.
+  Absolute(u64),
+  /// An offset in seconds relative to the current time.
+  Relative(i64),
+}
+
+impl Timestamp {
+  /// Convert this timestamp into a Unix timestamp, relative to the
+  /// given current time.
+  pub fn resolve(self, now: u64) -> anyhow::Result<u64> {
+    match self {
+      Timestamp::Absolute(time) => Ok(time),
+      Timestamp::Relative(offset) => {
+        let time = i128::from(now) + i128::from(offset);
+        u64::try_from(time)
+          .map_err(|_| anyhow::anyhow!("The given time offset lies before the Unix epoch"))
+      }
+    }
+  }
+}
+
+impl str::FromStr for Timestamp {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    if let Some(offset) = s.strip_prefix('+') {
+      parse_offset(offset)
+        .map(Timestamp::Relative)
+        .map_err(|err| format!("Invalid time offset '{}': {}", s, err))
+    } else if let Some(offset) = s.strip_prefix('-') {
+      parse_offset(offset)
+        .map(|offset| Timestamp::Relative(-offset))
+        .map_err(|err| format!("Invalid time offset '{}': {}", s, err))
+    } else if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+      s.parse()
+        .map(Timestamp::Absolute)
+        .map_err(|err| format!("Invalid Unix timestamp '{}': {}", s, err))
+    } else {
+      parse_rfc3339(s)
+        .map(Timestamp::Absolute)
+        .map_err(|err| format!("Invalid date '{}': {}", s, err))
+    }
+  }
+}
+
+/// Parse an offset such as `30s` or `1w` into a number of seconds.
+///
+/// Without a unit, the offset is interpreted as seconds.
+fn parse_offset(s: &str) -> Result<i64, String> {
+  let (value, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+    Some(idx) => s.split_at(idx),
+    None => (s, "s"),
+  };
+  let multiplier = match unit {
+    "s" => 1,
+    "m" => MINUTE,
+    "h" => HOUR,
+    "d" => DAY,
+    "w" => WEEK,
+    _ => return Err("expected a unit of s, m, h, d, or w".to_string()),
+  };
+  let value = value.parse::<u64>().map_err(|err| err.to_string())?;
+  value
+    .checked_mul(multiplier)
+    .and_then(|offset| i64::try_from(offset).ok())
+    .ok_or_else(|| "offset is too large".to_string())
+}
+
+/// Parse a decimal number consisting of exactly the given number of
+/// digits.
+fn parse_digits(s: &str, digits: usize) -> Result<u64, String> {
+  if s.len() == digits && s.bytes().all(|b| b.is_ascii_digit()) {
+    s.parse().map_err(|_| "invalid number".to_string())
+  } else {
+    Err(format!("expected {} digits but found '{}'", digits, s))
+  }
+}
+
+/// Calculate the number of days between 1970-01-01 and the given date.
+fn days_from_civil(year: i64, month: u64, day: u64) -> i64 {
+  // See http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+  let year = if month <= 2 { year - 1 } else { year };
+  let era = year.div_euclid(400);
+  let year_of_era = year.rem_euclid(400);
+  let month = month as i64;
+  let day_of_year =
+    (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+  let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+  era * 146_097 + day_of_era - 719_468
+}
+
+fn days_in_month(year: u64, month: u64) -> u64 {
+  match month {
+    2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+    2 => 28,
+    4 | 6 | 9 | 11 => 30,
+    _ => 31,
+  }
+}
+
+/// Parse an RFC 3339 date such as `2026-10-17T12:00:00+02:00` into a
+/// Unix timestamp.
+///
+/// Fractional seconds are truncated.
+fn parse_rfc3339(s: &str) -> Result<u64, String> {
+  let format_err = || "expected an RFC 3339 date (e.g., 2026-10-17T12:00:00Z)".to_string();
+
+  if !s.is_ascii() || s.len() < 20 {
+    return Err(format_err());
+  }
+  let (date, rest) = s.split_at(10);
+  let (separator, rest) = rest.split_at(1);
+  if !matches!(separator, "T" | "t" | " ") {
+    return Err(format_err());
+  }
+  let (time, rest) = rest.split_at(8);
+
+  let date = date.split('-').collect::<Vec<_>>();
+  let time = time.split(':').collect::<Vec<_>>();
+  let (year, month, day, hour, minute, second) = match (date.as_slice(), time.as_slice()) {
+    ([year, month, day], [hour, minute, second]) => (
+      parse_digits(year, 4)?,
+      parse_digits(month, 2)?,
+      parse_digits(day, 2)?,
+      parse_digits(hour, 2)?,
+      parse_digits(minute, 2)?,
+      parse_digits(second, 2)?,
+    ),
+    _ => return Err(format_err()),
+  };
+
+  if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+    return Err("the date does not exist".to_string());
+  }
+  // RFC 3339 permits leap seconds.
+  if hour > 23 || minute > 59 || second > 60 {
+    return Err("the time does not exist".to_string());
+  }
+
+  let rest = match rest.strip_prefix('.') {
+    Some(fraction) => {
+      let idx = fraction
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(fraction.len());
+      if idx == 0 {
+        return Err(format_err());
+      }
+      &fraction[idx..]
+    }
+    None => rest,
+  };
+
+  let offset = match rest {
+    "Z" | "z" => 0,
+    _ => {
+      let (sign, offset) = rest.split_at(rest.len().min(1));
+      let sign = match sign {
+        "+" => 1,
+        "-" => -1,
+        _ => return Err(format_err()),
+      };
+      let (hours, minutes) = offset.split_once(':').ok_or_else(format_err)?;
+      let (hours, minutes) = (parse_digits(hours, 2)?, parse_digits(minutes, 2)?);
+      if hours > 23 || minutes > 59 {
+        return Err("the time zone offset is invalid".to_string());
+      }
+      sign * (hours * HOUR + minutes * MINUTE) as i64
+    }
+  };
+
+  let days = days_from_civil(year as i64, month, day);
+  let seconds = (hour * HOUR + minute * MINUTE + second) as i64;
+  // The offset is the difference of the local time to UTC.
+  let time = days * DAY as i64 + seconds - offset;
+  u64::try_from(time).map_err(|_| "the date lies before the Unix epoch".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::str::FromStr as _;
+
+  fn parse(s: &str) -> Result<Timestamp, String> {
+    Timestamp::from_str(s)
+  }
+
+  #[test]
+  fn parse_unix_timestamp() {
+    assert_eq!(parse("0").unwrap(), Timestamp::Absolute(0));
+    assert_eq!(
+      parse("1111111111").unwrap(),
+      Timestamp::Absolute(1_111_111_111)
+    );
+    assert!(parse("").is_err());
+    assert!(parse("18446744073709551616").is_err());
+  }
+
+  #[test]
+  fn parse_relative() {
+    assert_eq!(parse("+30s").unwrap(), Timestamp::Relative(30));
+    assert_eq!(parse("+30").unwrap(), Timestamp::Relative(30));
+    assert_eq!(parse("-5m").unwrap(), Timestamp::Relative(-300));
+    assert_eq!(parse("+2h").unwrap(), Timestamp::Relative(7200));
+    assert_eq!(parse("+1d").unwrap(), Timestamp::Relative(86400));
+    assert_eq!(parse("-1w").unwrap(), Timestamp::Relative(-604_800));
+    assert_eq!(
+      parse("+1y").unwrap_err(),
+      "Invalid time offset '+1y': expected a unit of s, m, h, d, or w"
+    );
+    assert!(parse("+").is_err());
+    assert!(parse("+s").is_err());
+    assert!(parse("+-1s").is_err());
+    assert!(parse("+99999999999999999w").is_err());
+
+    assert_eq!(parse("+30s").unwrap().resolve(100).unwrap(), 130);
+    assert_eq!(parse("-1m").unwrap().resolve(100).unwrap(), 40);
+    assert!(parse("-2m").unwrap().resolve(100).is_err());
+  }
+
+  #[test]
+  fn parse_dates() {
+    let absolute = |s| match parse(s) {
+      Ok(Timestamp::Absolute(time)) => time,
+      result => panic!("Unexpected result for {}: {:?}", s, result),
+    };
+
+    assert_eq!(absolute("1970-01-01T00:00:00Z"), 0);
+    assert_eq!(absolute("2005-03-18T01:58:31Z"), 1_111_111_111);
+    assert_eq!(absolute("2026-10-17T12:00:00Z"), 1_792_238_400);
+    assert_eq!(absolute("2026-10-17t12:00:00z"), 1_792_238_400);
+    assert_eq!(absolute("2026-10-17 12:00:00Z"), 1_792_238_400);
+    assert_eq!(absolute("2026-10-17T12:00:00.999Z"), 1_792_238_400);
+    assert_eq!(absolute("2000-02-29T00:00:00Z"), 951_782_400);
+    assert_eq!(absolute("2016-12-31T23:59:60Z"), 1_483_228_800);
+  }
+
+  #[test]
+  fn parse_time_zones() {
+    let absolute = |s| match parse(s) {
+      Ok(Timestamp::Absolute(time)) => time,
+      result => panic!("Unexpected result for {}: {:?}", s, result),
+    };
+
+    let utc = absolute("2026-10-17T12:00:00Z");
+    assert_eq!(absolute("2026-10-17T12:00:00+00:00"), utc);
+    assert_eq!(absolute("2026-10-17T12:00:00-00:00"), utc);
+    assert_eq!(absolute("2026-10-17T14:00:00+02:00"), utc);
+    assert_eq!(absolute("2026-10-17T06:30:00-05:30"), utc);
+    // Offsets may cross day, month, and year boundaries.
+    assert_eq!(absolute("2026-10-18T01:45:00+13:45"), utc);
+    assert_eq!(
+      absolute("2026-12-31T20:00:00-10:00"),
+      absolute("2027-01-01T06:00:00Z")
+    );
+    assert_eq!(absolute("1970-01-01T01:00:00+01:00"), 0);
+    assert_eq!(
+      parse("1970-01-01T00:00:00+01:00").unwrap_err(),
+      "Invalid date '1970-01-01T00:00:00+01:00': the date lies before the Unix epoch"
+    );
+  }
+
+  #[test]
+  fn parse_invalid_dates() {
+    assert!(parse("2026-10-17").is_err());
+    assert!(parse("2026-10-17T12:00:00").is_err());
+    assert!(parse("2026-10-17T12:00Z").is_err());
+    assert!(parse("2026-10-17T12:00:00+0200").is_err());
+    assert!(parse("2026-10-17T12:00:00+24:00").is_err());
+    assert!(parse("2026-10-17T12:00:00.Z").is_err());
+    assert!(parse("2026-10-17X12:00:00Z").is_err());
+    assert!(parse("2026-13-17T12:00:00Z").is_err());
+    assert!(parse("2026-02-29T12:00:00Z").is_err());
+    assert!(parse("1900-02-29T12:00:00Z").is_err());
+    assert!(parse("2026-10-17T24:00:00Z").is_err());
+    assert!(parse("2026-10-17T12:00:00Zulu").is_err());
+    assert!(parse("2026-10-17T12:00:00Z\u{e9}").is_err());
+    assert_eq!(
+      parse("2026-10-17T12:60:00Z").unwrap_err(),
+      "Invalid date '2026-10-17T12:60:00Z': the time does not exist"
+    );
+  }
+}
@@ -0,0 +1,459 @@
+// volume.rs
+
+// Copyright (C) 2026 The Nitrocli Developers
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fs;
+use std::path;
+use std::process;
+use std::thread;
+use std::time;
+
+use anyhow::Context as _;
+
+/// The USB vendor ID of the Nitrokey Storage.
+const STORAGE_VENDOR_ID: &str = "20a0";
+/// The USB product ID of the Nitrokey Storage.
+const STORAGE_PRODUCT_ID: &str = "4109";
+/// The directory listing the block devices of the system.
+const SYS_BLOCK_DIR: &str = "/sys/block";
+/// The directory listing the hidraw devices of the system.
+const SYS_HIDRAW_DIR: &str = "/sys/class/hidraw";
+/// The file listing the mounted file systems.
+const MOUNTS_FILE: &str = "/proc/self/mounts";
+/// The maximum time to wait for the block device of a volume to appear.
+const DEVICE_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+/// The interval in which to check for the block device of a volume.
+const POLL_INTERVAL: time::Duration = time::Duration::from_millis(100);
+
+/// A volume of a Nitrokey Storage that is exposed as a block device.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Volume {
+  /// The unencrypted volume.
+  Unencrypted,
+  /// The encrypted volume or the hidden volume, whichever is open.
+  Encrypted,
+}
+
+impl Volume {
+  /// The SCSI logical unit number under which the Nitrokey Storage
+  /// exposes the volume.
+  fn lun(self) -> u32 {
+    match self {
+      Volume::Unencrypted => 0,
+      Volume::Encrypted => 1,
+    }
+  }
+}
+
+/// Read a sysfs attribute, returning `None` if it does not exist.
+fn read_attribute(path: &path::Path) -> Option<String> {
+  fs::read_to_string(path)
+    .ok()
+    .map(|value| value.trim().to_string())
+}
+
+/// The USB device of a Nitrokey Storage, identified by its bus number
+/// and device address.
+///
+/// Block devices are matched against it to tell apart the volumes of
+/// multiple connected Nitrokey Storage devices.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UsbDevice {
+  bus: u32,
+  address: u32,
+}
+
+impl UsbDevice {
+  /// Determine the USB device of the given HID device path, as reported
+  /// by `nitrokey::list_devices`.
+  pub fn from_hid_path(path: &str) -> anyhow::Result<Self> {
+    Self::from_hid_path_in(path::Path::new(SYS_HIDRAW_DIR), path)
+  }
+
+  fn from_hid_path_in(sys_hidraw_dir: &path::Path, path: &str) -> anyhow::Result<Self> {
+    let parse_err = || format!("Failed to determine the USB device of '{}'", path);
+
+    // The libusb backend of hidapi uses paths of the form
+    // <bus>:<address>:<interface>, all in hexadecimal.
+    let fields = path.split(':').collect::<Vec<_>>();
+    if let [bus, address, _interface] = fields.as_slice() {
+      let bus = u32::from_str_radix(bus, 16).with_context(parse_err)?;
+      let address = u32::from_str_radix(address, 16).with_context(parse_err)?;
+      return Ok(Self { bus, address });
+    }
+
+    // The hidraw backend uses the device node, e.g., /dev/hidraw0,
+    // whose sysfs entry is a descendant of the USB device.
+    let name = path::Path::new(path).file_name().with_context(parse_err)?;
+    let dir = fs::canonicalize(sys_hidraw_dir.join(name).join("device")).with_context(parse_err)?;
+    dir
+      .ancestors()
+      .find_map(Self::from_sysfs)
+      .with_context(parse_err)
+  }
+
+  /// Read the USB device from the given sysfs directory, if it
+  /// represents a USB device.
+  fn from_sysfs(dir: &path::Path) -> Option<Self> {
+    let bus = read_attribute(&dir.join("busnum"))?.parse().ok()?;
+    let address = read_attribute(&dir.join("devnum"))?.parse().ok()?;
+    Some(Self { bus, address })
+  }
+}
+
+/// Check whether the given sysfs block device directory belongs to the
+/// given volume of the given Nitrokey Storage.
+fn is_volume_device(block_dir: &path::Path, usb_device: UsbDevice, volume: Volume) -> bool {
+  // The `device` link points to the SCSI device, named
+  // <host>:<channel>:<target>:<lun>, which is a descendant of the USB
+  // device carrying the vendor and product IDs.
+  let scsi_dir = match fs::canonicalize(block_dir.join("device")) {
+    Ok(dir) => dir,
+    Err(_) => return false,
+  };
+  let lun = scsi_dir
+    .file_name()
+    .and_then(|name| name.to_str())
+    .and_then(|name| name.rsplit(':').next())
+    .and_then(|lun| lun.parse::<u32>().ok());
+  if lun != Some(volume.lun()) {
+    return false;
+  }
+
+  scsi_dir.ancestors().any(|dir| {
+    read_attribute(&dir.join("idVendor")).as_deref() == Some(STORAGE_VENDOR_ID)
+      && read_attribute(&dir.join("idProduct")).as_deref() == Some(STORAGE_PRODUCT_ID)
+      && UsbDevice::from_sysfs(dir) == Some(usb_device)
+  })
+}
+
+/// Find the block devices of the given volume in the given sysfs block
+/// directory, if the volume is currently available.
+///
+/// The returned paths are the device nodes of all partitions, sorted by
+/// name, or of the whole disk if it is not partitioned.
+fn find_devices_in(
+  sys_block_dir: &path::Path,
+  usb_device: UsbDevice,
+  volume: Volume,
+) -> anyhow::Result<Vec<path::PathBuf>> {
+  let entries = match fs::read_dir(sys_block_dir) {
+    Ok(entries) => entries,
+    // Without sysfs there is nothing we could find.
+    Err(_) => return Ok(Vec::new()),
+  };
+
+  for entry in entries {
+    let entry = entry.with_context(|| {
+      format!(
+        "Failed to read block device directory '{}'",
+        sys_block_dir.display()
+      )
+    })?;
+    let block_dir = entry.path();
+    if !is_volume_device(&block_dir, usb_device, volume) {
+      continue;
+    }
+    // A closed volume is reported as a disk without a medium.
+    let size = read_attribute(&block_dir.join("size")).and_then(|size| size.parse::<u64>().ok());
+    if size.unwrap_or(0) == 0 {
+      continue;
+    }
+
+    let disk = entry.file_name();
+    let mut partitions = fs::read_dir(&block_dir)
+      .with_context(|| format!("Failed to read directory '{}'", block_dir.display()))?
+      .filter_map(|entry| entry.ok())
+      .filter(|entry| entry.path().join("partition").is_file())
+      .map(|entry| entry.file_name())
+      .collect::<Vec<_>>();
+    partitions.sort();
+    if partitions.is_empty() {
+      partitions.push(disk);
+    }
+
+    let devices = partitions
+      .into_iter()
+      .map(|name| path::Path::new("/dev").join(name))
+      .collect();
+    return Ok(devices);
+  }
+  Ok(Vec::new())
+}
+
+/// Find the block devices of the given volume of the given Nitrokey
+/// Storage, if the volume is currently available.
+fn find_devices(usb_device: UsbDevice, volume: Volume) -> anyhow::Result<Vec<path::PathBuf>> {
+  find_devices_in(path::Path::new(SYS_BLOCK_DIR), usb_device, volume)
+}
+
+/// Find the block device of the given volume of the given Nitrokey
+/// Storage, if the volume is currently available.
+///
+/// The returned path is the device node of the first partition, or of
+/// the whole disk if it is not partitioned.
+pub fn find_device(usb_device: UsbDevice, volume: Volume) -> anyhow::Result<Option<path::PathBuf>> {
+  Ok(find_devices(usb_device, volume)?.into_iter().next())
+}
+
+/// Wait for the block device of the given volume of the given Nitrokey
+/// Storage to appear, e.g., after the volume has been opened.
+pub fn wait_for_device(usb_device: UsbDevice, volume: Volume) -> anyhow::Result<path::PathBuf> {
+  let start = time::Instant::now();
+  loop {
+    if let Some(device) = find_device(usb_device, volume)? {
+      // The device node is created asynchronously by udev.
+      if device.exists() {
+        return Ok(device);
+      }
+    }
+    if start.elapsed() >= DEVICE_TIMEOUT {
+      anyhow::bail!(
+        "The block device of the volume did not appear within {} seconds",
+        DEVICE_TIMEOUT.as_secs()
+      );
+    }
+    thread::sleep(POLL_INTERVAL);
+  }
+}
+
+/// Decode the octal escape sequences used for white space in the
+/// fields of the mounts file.
+fn unescape_mount_field(field: &str) -> String {
+  let mut result = String::with_capacity(field.len());
+  let mut rest = field;
+  while let Some(idx) = rest.find('\\') {
+    result.push_str(&rest[..idx]);
+    let escaped = rest.get(idx + 1..idx + 4);
+    match escaped.and_then(|code| u8::from_str_radix(code, 8).ok()) {
+      Some(byte) => {
+        result.push(char::from(byte));
+        rest = &rest[idx + 4..];
+      }
+      None => {
+        result.push('\\');
+        rest = &rest[idx + 1..];
+      }
+    }
+  }
+  result.push_str(rest);
+  result
+}
+
+/// Find the mount points of the given device in the given mounts file
+/// content.
+fn parse_mount_points(mounts: &str, device: &path::Path) -> Vec<path::PathBuf> {
+  mounts
+    .lines()
+    .filter_map(|line| {
+      let mut fields = line.split_whitespace();
+      let source = unescape_mount_field(fields.next()?);
+      let target = unescape_mount_field(fields.next()?);
+      if path::Path::new(&source) == device {
+        Some(path::PathBuf::from(target))
+      } else {
+        None
+      }
+    })
+    .collect()
+}
+
+/// Find the mount points of the given device.
+pub fn find_mount_points(device: &path::Path) -> anyhow::Result<Vec<path::PathBuf>> {
+  let mounts = fs::read_to_string(MOUNTS_FILE)
+    .with_context(|| format!("Failed to read mount table '{}'", MOUNTS_FILE))?;
+  Ok(parse_mount_points(&mounts, device))
+}
+
+/// Run the given command, reporting its error output on failure.
+fn run(command: &mut process::Command, name: &str) -> anyhow::Result<()> {
+  let output = command
+    .stdin(process::Stdio::null())
+    .output()
+    .with_context(|| format!("Failed to invoke {}", name))?;
+  if output.status.success() {
+    Ok(())
+  } else {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    anyhow::bail!("{} failed: {}", name, stderr.trim())
+  }
+}
+
+/// Mount the given device and return the mount point.
+///
+/// If no directory is given, the device is mounted using `udisksctl`,
+/// which chooses the mount point and does not require root privileges.
+pub fn mount(device: &path::Path, dir: Option<&path::Path>) -> anyhow::Result<path::PathBuf> {
+  match dir {
+    Some(dir) => run(process::Command::new("mount").arg(device).arg(dir), "mount")?,
+    None => run(
+      process::Command::new("udisksctl")
+        .arg("mount")
+        .arg("--no-user-interaction")
+        .arg("--block-device")
+        .arg(device),
+      "udisksctl",
+    )?,
+  }
+
+  find_mount_points(device)?
+    .pop()
+    .with_context(|| format!("Failed to find mount point of '{}'", device.display()))
+}
+
+/// Unmount all file systems on all partitions of the given volume of
+/// the given Nitrokey Storage and return the directories they were
+/// mounted at.
+///
+/// This fails if any of the file systems is still in use, in which
+/// case the volume must not be closed.
+pub fn unmount_all(usb_device: UsbDevice, volume: Volume) -> anyhow::Result<Vec<path::PathBuf>> {
+  let mut dirs = Vec::new();
+  for device in find_devices(usb_device, volume)? {
+    dirs.append(&mut find_mount_points(&device)?);
+  }
+
+  for dir in &dirs {
+    // umount(8) uses the helper recorded for mounts created by udisks,
+    // so this works for unprivileged users as well.
+    run(process::Command::new("umount").arg(dir), "umount").with_context(|| {
+      format!(
+        "Failed to unmount '{}', refusing to change the volume",
+        dir.display()
+      )
+    })?;
+  }
+  Ok(dirs)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::os::unix::fs::symlink;
+
+  const USB_DEVICE: UsbDevice = UsbDevice { bus: 1, address: 6 };
+
+  /// Create a fake sysfs block device backed by a USB device with the
+  /// given product ID and address.
+  fn create_block_device(
+    root: &path::Path,
+    name: &str,
+    lun: u32,
+    (product_id, usb_device): (&str, UsbDevice),
+    size: u64,
+    partitions: &[&str],
+  ) -> anyhow::Result<()> {
+    let usb_dir = root.join(format!("devices/usb-{}", name));
+    let scsi_dir = usb_dir.join(format!("1-1:1.0/host6/target6:0:0/6:0:0:{}", lun));
+    fs::create_dir_all(&scsi_dir)?;
+    fs::write(usb_dir.join("idVendor"), format!("{}\n", STORAGE_VENDOR_ID))?;
+    fs::write(usb_dir.join("idProduct"), format!("{}\n", product_id))?;
+    fs::write(usb_dir.join("busnum"), format!("{}\n", usb_device.bus))?;
+    fs::write(usb_dir.join("devnum"), format!("{}\n", usb_device.address))?;
+
+    let block_dir = root.join("block").join(name);
+    fs::create_dir_all(&block_dir)?;
+    symlink(&scsi_dir, block_dir.join("device"))?;
+    fs::write(block_dir.join("size"), format!("{}\n", size))?;
+    for partition in partitions {
+      fs::create_dir(block_dir.join(partition))?;
+      fs::write(block_dir.join(partition).join("partition"), "1\n")?;
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn find_volume_devices() -> anyhow::Result<()> {
+    let storage = (STORAGE_PRODUCT_ID, USB_DEVICE);
+    let other_storage = (STORAGE_PRODUCT_ID, UsbDevice { bus: 1, address: 7 });
+    let find = |block_dir, volume| find_devices_in(block_dir, USB_DEVICE, volume);
+
+    let root = tempfile::tempdir()?;
+    let block_dir = root.path().join("block");
+    fs::create_dir(&block_dir)?;
+    assert!(find(&block_dir, Volume::Encrypted)?.is_empty());
+
+    // The unencrypted volume and an unrelated device.
+    create_block_device(root.path(), "sdb", 0, storage, 1024, &["sdb1"])?;
+    create_block_device(root.path(), "sdd", 1, ("4108", USB_DEVICE), 1024, &[])?;
+    // The closed encrypted volume.
+    create_block_device(root.path(), "sdc", 1, storage, 0, &[])?;
+    // The open encrypted volume of another Nitrokey Storage.
+    create_block_device(root.path(), "sde", 1, other_storage, 1024, &[])?;
+    assert!(find(&block_dir, Volume::Encrypted)?.is_empty());
+    assert_eq!(
+      find(&block_dir, Volume::Unencrypted)?,
+      vec![path::PathBuf::from("/dev/sdb1")]
+    );
+
+    fs::write(block_dir.join("sdc/size"), "2048\n")?;
+    assert_eq!(
+      find(&block_dir, Volume::Encrypted)?,
+      vec![path::PathBuf::from("/dev/sdc")]
+    );
+
+    fs::create_dir(block_dir.join("sdc/sdc2"))?;
+    fs::write(block_dir.join("sdc/sdc2/partition"), "2\n")?;
+    fs::create_dir(block_dir.join("sdc/sdc1"))?;
+    fs::write(block_dir.join("sdc/sdc1/partition"), "1\n")?;
+    assert_eq!(
+      find(&block_dir, Volume::Encrypted)?,
+      vec![
+        path::PathBuf::from("/dev/sdc1"),
+        path::PathBuf::from("/dev/sdc2")
+      ]
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn usb_device_from_hid_path() -> anyhow::Result<()> {
+    let root = tempfile::tempdir()?;
+    let hidraw_dir = root.path().join("hidraw");
+    let usb_dir = root.path().join("devices/usb1/1-1");
+    let hid_dir = usb_dir.join("1-1:1.0/0003:20A0:4109.0001");
+    fs::create_dir_all(&hid_dir)?;
+    fs::create_dir_all(hidraw_dir.join("hidraw3"))?;
+    fs::write(usb_dir.join("busnum"), "3\n")?;
+    fs::write(usb_dir.join("devnum"), "18\n")?;
+    symlink(&hid_dir, hidraw_dir.join("hidraw3/device"))?;
+
+    let from_path = |path| UsbDevice::from_hid_path_in(&hidraw_dir, path);
+    assert_eq!(
+      from_path("0003:0012:02")?,
+      UsbDevice {
+        bus: 3,
+        address: 18
+      }
+    );
+    assert_eq!(
+      from_path("/dev/hidraw3")?,
+      UsbDevice {
+        bus: 3,
+        address: 18
+      }
+    );
+    assert!(from_path("/dev/hidraw4").is_err());
+    assert!(from_path("0003:xyz:02").is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn mount_points() {
+    let mounts = r#"/dev/sda2 / ext4 rw,relatime 0 0
+/dev/sdc1 /run/media/user/my\040volume vfat rw,nosuid,nodev 0 0
+/dev/sdc12 /mnt/other vfat rw 0 0
+/dev/sdc1 /mnt/second vfat rw 0 0
+"#;
+    assert_eq!(
+      parse_mount_points(mounts, path::Path::new("/dev/sdc1")),
+      vec![
+        path::PathBuf::from("/run/media/user/my volume"),
+        path::PathBuf::from("/mnt/second"),
+      ]
+    );
+    assert!(parse_mount_points(mounts, path::Path::new("/dev/sdb1")).is_empty());
+  }
+}
@@ -31,6 +31,18 @@ mod nitrocli {
   include!("../src/args.rs");
 }
 
+/// The argument types of the `hidden plan` command.
+#[allow(unused)]
+mod layout {
+  include!("../src/layout.rs");
+}
+
+/// The argument type of the `otp get --time` option.
+#[allow(unused)]
+mod timestamp {
+  include!("../src/timestamp.rs");
+}
+
 /// Generate a shell completion script for nitrocli.
 ///
 /// The script will be emitted to standard output.